@@ -6,7 +6,8 @@ tallpapers = $HOME ++ "/Pictures/Wallpapers/Tallpapers"
 
 function select_widepaper(label, display) {
     list = find $widepapers "-name" "*.png" "-or" "-name" "*.jpeg" "-or" "-name" "*.jpg" | shuf
-    file = head $list
+    entry = head $list
+    file = $entry["full_path"]
     echo $label ++ ": " ++ $file >> $HOME ++ "/lockscreen.log"
     return $display ++ ":" ++ $file
 }
@@ -14,7 +15,8 @@ function select_widepaper(label, display) {
 
 function select_tallpaper(label, display) {
     list = find $tallpapers "-name" "*.png" "-or" "-name" "*.jpeg" "-or" "-name" "*.jpg" | shuf
-    file = head $list
+    entry = head $list
+    file = $entry["full_path"]
     echo $label ++ ": " ++ $file >> $HOME ++ "/lockscreen.log"
     return $display ++ ":" ++ $file
 }
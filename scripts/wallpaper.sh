@@ -23,7 +23,8 @@ tallpapers = $HOME ++ "/Pictures/Wallpapers/Tallpapers"
 
 function select_widepaper(label, display) {
     list = find $widepapers "-name" "*.png" "-or" "-name" "*.jpeg" "-or" "-name" "*.jpg" "-or" "-name" "*.mp4" "-or" "-name" "*.webm" | shuf
-    file = head $list
+    entry = head $list
+    file = $entry["full_path"]
     echo $label ++ ": " ++ $file >> $HOME ++ "/wallpaper.log"
     return $file
 }
@@ -31,7 +32,8 @@ function select_widepaper(label, display) {
 
 function select_tallpaper(label, display) {
     list = find $tallpapers "-name" "*.png" "-or" "-name" "*.jpeg" "-or" "-name" "*.jpg" "-or" "-name" "*.mp4" "-or" "-name" "*.webm" | shuf
-    file = head $list
+    entry = head $list
+    file = $entry["full_path"]
     echo $label ++ ": " ++ $file >> $HOME ++ "/wallpaper.log"
     return $file
 }
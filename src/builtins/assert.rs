@@ -0,0 +1,119 @@
+use caat_rust::Value;
+
+
+fn describe(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("()"),
+        Value::Integer(i) => format!("{}", i),
+        Value::Float(f) => format!("{}", f),
+        Value::Boolean(b) => format!("{}", b),
+        Value::String(s) => format!("{:?}", s),
+        Value::Failure(msg) => format!("Failure({:?})", msg),
+        Value::CAATFunction(_) => String::from("<function>"),
+        value => format!("{}", value),
+    }
+}
+
+fn path_string(path: &[String]) -> String {
+    if path.is_empty() {
+        String::from("value")
+    } else {
+        path.join("")
+    }
+}
+
+/// Lists where two values differ, one line per difference, naming each place
+/// by the access expression that reaches it, e.g. `[2]["name"]`.
+pub fn diff_values(left: &Value, right: &Value) -> Vec<String> {
+    let mut output = Vec::new();
+    diff(left, right, &mut Vec::new(), &mut output);
+    output
+}
+
+fn diff(left: &Value, right: &Value, path: &mut Vec<String>, output: &mut Vec<String>) {
+    match (left, right) {
+        (Value::List(a), Value::List(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                path.push(format!("[{}]", i));
+                match (a.get(i), b.get(i)) {
+                    (Some(a), Some(b)) => diff(a, b, path, output),
+                    (Some(a), None) => output.push(format!("{}: only on the left: {}", path_string(path), describe(a))),
+                    (None, Some(b)) => output.push(format!("{}: only on the right: {}", path_string(path), describe(b))),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        (Value::Map(a, _), Value::Map(b, _)) => {
+            let mut keys: Vec<&String> = a.keys().chain(b.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                path.push(format!("[{:?}]", key));
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => diff(a, b, path, output),
+                    (Some(a), None) => output.push(format!("{}: only on the left: {}", path_string(path), describe(a))),
+                    (None, Some(b)) => output.push(format!("{}: only on the right: {}", path_string(path), describe(b))),
+                    (None, None) => {}
+                }
+                path.pop();
+            }
+        }
+        (a, b) => {
+            if a != b {
+                output.push(format!("{}: {} != {}", path_string(path), describe(a), describe(b)));
+            }
+        }
+    }
+}
+
+/// `assert condition [message]`
+pub fn assert(args: &[Value]) -> Result<Value, String> {
+    let message = match args.get(1) {
+        Some(Value::String(message)) => format!("assertion failed: {}", message),
+        _ => String::from("assertion failed"),
+    };
+    match args.get(0) {
+        Some(Value::Boolean(true)) => Ok(Value::Null),
+        Some(Value::Boolean(false)) => Err(message),
+        Some(value) => Err(format!("{}: expected a boolean, got {}", message, describe(value))),
+        None => Err("assert: Expected a condition".to_string()),
+    }
+}
+
+/// `assert_eq left right [message]`
+pub fn assert_eq(args: &[Value]) -> Result<Value, String> {
+    let (left, right) = match (args.get(0), args.get(1)) {
+        (Some(left), Some(right)) => (left, right),
+        _ => return Err("assert_eq: Expected 2 values".to_string()),
+    };
+    let differences = diff_values(left, right);
+    if differences.is_empty() {
+        return Ok(Value::Null);
+    }
+    let mut message = match args.get(2) {
+        Some(Value::String(message)) => format!("assertion failed: {}", message),
+        _ => String::from("assertion failed: values are not equal"),
+    };
+    for difference in differences {
+        message.push_str("\n    ");
+        message.push_str(&difference);
+    }
+    Err(message)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_values() {
+        let left = Value::List(vec![Value::Integer(1), Value::String("a".to_string())].into());
+        let right = Value::List(vec![Value::Integer(1), Value::String("b".to_string()), Value::Null].into());
+        assert_eq!(diff_values(&left, &right), vec![
+            String::from(r#"[1]: "a" != "b""#),
+            String::from("[2]: only on the right: ()"),
+        ]);
+    }
+}
@@ -3,11 +3,12 @@ use crate::shell::Shell;
 use std::sync::{Arc, RwLock};
 
 
-pub fn background(shell: Option<Arc<RwLock<Shell>>>, args: &Vec<Value>) -> Result<Value,String> {
+pub fn background(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
     if let Some(shell) = shell {
         if let Some(command) = args.get(0) {
             let mut borrowed_shell = borrow_mut!(shell);
-            return borrowed_shell.job_manager_mut().spawn_command(command.clone(), &args[1..].to_vec());
+            let job_shell = Arc::new(RwLock::new(borrowed_shell.fork(shell.clone())));
+            return borrowed_shell.job_manager_mut().spawn_command(job_shell, command.clone(), &args[1..].to_vec());
         } else {
             return Err("background: No command provided".to_string());
         }
@@ -17,19 +18,21 @@ pub fn background(shell: Option<Arc<RwLock<Shell>>>, args: &Vec<Value>) -> Resul
 }
 
 
-pub fn join(shell: Option<Arc<RwLock<Shell>>>, args: &Vec<Value>) -> Result<Value,String> {
+pub fn join(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
     if let Some(shell) = shell {
         if args.len() != 1 {
             return Err("join: Expected 1 argument".to_string());
         }
         let mut borrowed_shell = borrow_mut!(shell);
-        return borrowed_shell.job_manager_mut().join(args[0].clone());
+        let job = borrowed_shell.job_manager_mut().take_job(args[0].clone())?;
+        drop(borrowed_shell);
+        return job.wait();
     } else {
         return Err("join: Called from bad context".to_string());
     }
 }
 
-pub fn jobs(shell: Option<Arc<RwLock<Shell>>>, args: &Vec<Value>) -> Result<Value,String> {
+pub fn jobs(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
     if let Some(shell) = shell {
         let borrowed_shell = borrow!(shell);
         return Ok(borrowed_shell.job_manager().jobs(args));
@@ -37,3 +40,29 @@ pub fn jobs(shell: Option<Arc<RwLock<Shell>>>, args: &Vec<Value>) -> Result<Valu
         return Err("jobs: Called from bad context".to_string());
     }
 }
+
+/// Copies a variable into the global scope of the shell that spawned this job,
+/// or into this shell's global scope when called outside of a job.
+pub fn share(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    if let Some(shell) = shell {
+        let name = match args.get(0) {
+            Some(Value::String(name)) => name.clone(),
+            _ => return Err("share: Expected variable name as first argument".to_string()),
+        };
+        let borrowed_shell = borrow!(shell);
+        let value = match args.get(1) {
+            Some(value) => value.clone(),
+            None => match borrowed_shell.environment().get(&name) {
+                Some(value) => value.clone(),
+                None => return Err(format!("share: {} not found in environment", name)),
+            },
+        };
+        let target = borrowed_shell.parent().unwrap_or_else(|| shell.clone());
+        drop(borrowed_shell);
+        let mut borrowed_target = borrow_mut!(target);
+        borrowed_target.environment_mut().set_global(name, value.clone());
+        return Ok(value);
+    } else {
+        return Err("share: Called from bad context".to_string());
+    }
+}
@@ -0,0 +1,214 @@
+use caat_rust::Value;
+use crate::shell::Shell;
+use crate::shell::channel_manager::{ChannelManager, Received};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::time::{Duration, Instant};
+
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(e) => panic!("error: {:?}", e),
+    }
+}
+
+/// Releases the manager until a value is sent or a channel is closed, or
+/// until the deadline passes.
+fn wait<'a>(guard: MutexGuard<'a, ChannelManager>, deadline: Option<Instant>) -> MutexGuard<'a, ChannelManager> {
+    let signal = guard.signal();
+    let result = match deadline {
+        Some(deadline) => signal.wait_timeout(guard, deadline.saturating_duration_since(Instant::now())).map(|(guard, _)| guard),
+        None => signal.wait(guard),
+    };
+    match result {
+        Ok(guard) => guard,
+        Err(e) => panic!("error: {:?}", e),
+    }
+}
+
+fn channel_manager(shell: Option<Arc<RwLock<Shell>>>, command: &str) -> Result<Arc<Mutex<ChannelManager>>, String> {
+    if let Some(shell) = shell {
+        let borrowed_shell = borrow!(shell);
+        let channels = borrowed_shell.channels();
+        Ok(channels)
+    } else {
+        Err(format!("{}: Called from bad context", command))
+    }
+}
+
+pub fn channel(shell: Option<Arc<RwLock<Shell>>>, _args: &[Value]) -> Result<Value,String> {
+    let manager = channel_manager(shell, "channel")?;
+    let pair = lock(&manager).create();
+    Ok(pair)
+}
+
+pub fn send(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    if args.len() != 2 {
+        return Err("send: Expected a channel sender and a value".to_string());
+    }
+    let manager = channel_manager(shell, "send")?;
+    lock(&manager).send(&args[0], args[1].clone(), "send")?;
+    Ok(Value::Null)
+}
+
+pub fn recv(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    if args.len() != 1 {
+        return Err("recv: Expected 1 argument".to_string());
+    }
+    let manager = channel_manager(shell, "recv")?;
+    let mut guard = lock(&manager);
+    loop {
+        match guard.try_recv(&args[0], "recv")? {
+            Received::Value(value) => return Ok(value),
+            Received::Closed => return Err("recv: channel is closed".to_string()),
+            Received::Empty => guard = wait(guard, None),
+        }
+    }
+}
+
+/// Returns the next value without blocking, or `()` if none is waiting.
+pub fn try_recv(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    if args.len() != 1 {
+        return Err("try_recv: Expected 1 argument".to_string());
+    }
+    let manager = channel_manager(shell, "try_recv")?;
+    let received = lock(&manager).try_recv(&args[0], "try_recv")?;
+    match received {
+        Received::Value(value) => Ok(value),
+        Received::Empty => Ok(Value::Null),
+        Received::Closed => Err("try_recv: channel is closed".to_string()),
+    }
+}
+
+pub fn close(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    if args.len() != 1 {
+        return Err("close: Expected 1 argument".to_string());
+    }
+    let manager = channel_manager(shell, "close")?;
+    lock(&manager).close(&args[0])?;
+    Ok(Value::Null)
+}
+
+/// Waits for the first value on any receiver in a list, with an optional
+/// timeout in seconds. Returns a map of the `index`, `channel` and `value`.
+pub fn select_channel(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    let mut ends = None;
+    let mut timeout = None;
+    for arg in args {
+        match arg {
+            Value::List(list) => ends = Some(list.to_vec()),
+            Value::Integer(i) if *i >= 0 => timeout = Some(Duration::from_secs(*i as u64)),
+            Value::Float(f) if *f >= 0.0 => timeout = Some(Duration::from_secs_f64(*f)),
+            _ => return Err("select_channel: expected a list of receivers and an optional timeout".to_string()),
+        }
+    }
+    let ends = match ends {
+        Some(ends) if !ends.is_empty() => ends,
+        _ => return Err("select_channel: expected a list of receivers".to_string()),
+    };
+    let manager = channel_manager(shell, "select_channel")?;
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut guard = lock(&manager);
+    loop {
+        let mut closed = 0;
+        for (i, end) in ends.iter().enumerate() {
+            match guard.try_recv(end, "select_channel")? {
+                Received::Value(value) => {
+                    let mut output = HashMap::new();
+                    output.insert(String::from("index"), Value::Integer(i as i64));
+                    output.insert(String::from("channel"), end.clone());
+                    output.insert(String::from("value"), value);
+                    return Ok(Value::Map(output, Some(String::from("{value}"))));
+                }
+                Received::Empty => {}
+                Received::Closed => closed += 1,
+            }
+        }
+        if closed == ends.len() {
+            return Err("select_channel: all channels are closed".to_string());
+        }
+        if let Some(deadline) = deadline {
+            if Instant::now() >= deadline {
+                return Ok(Value::Failure("timed out".to_string()));
+            }
+        }
+        guard = wait(guard, deadline);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn pair(shell: &Arc<RwLock<Shell>>) -> (Value, Value) {
+        match channel(Some(shell.clone()), &[]).unwrap() {
+            Value::List(ends) => (ends[0].clone(), ends[1].clone()),
+            _ => panic!("expected a pair"),
+        }
+    }
+
+    fn channel_count(shell: &Arc<RwLock<Shell>>) -> usize {
+        let manager = borrow!(shell).channels();
+        let guard = lock(&manager);
+        guard.channel_count()
+    }
+
+    #[test]
+    fn test_send_recv_close() {
+        let shell = Arc::new(RwLock::new(Shell::new()));
+        let (sender, receiver) = pair(&shell);
+        send(Some(shell.clone()), &[sender.clone(), Value::Integer(1)]).unwrap();
+        send(Some(shell.clone()), &[sender.clone(), Value::Integer(2)]).unwrap();
+        assert_eq!(recv(Some(shell.clone()), &[receiver.clone()]).unwrap(), Value::Integer(1));
+        close(Some(shell.clone()), &[sender.clone()]).unwrap();
+        assert!(send(Some(shell.clone()), &[sender, Value::Integer(3)]).is_err());
+        assert_eq!(try_recv(Some(shell.clone()), &[receiver.clone()]).unwrap(), Value::Integer(2));
+        assert_eq!(recv(Some(shell.clone()), &[receiver.clone()]), Err("recv: channel is closed".to_string()));
+        assert_eq!(channel_count(&shell), 0);
+    }
+
+    #[test]
+    fn test_recv_blocks_until_send() {
+        let shell = Arc::new(RwLock::new(Shell::new()));
+        let (sender, receiver) = pair(&shell);
+        let sending_shell = shell.clone();
+        let sending = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            send(Some(sending_shell.clone()), &[sender.clone(), string("late")]).unwrap();
+            close(Some(sending_shell), &[sender]).unwrap();
+        });
+        assert_eq!(recv(Some(shell.clone()), &[receiver]).unwrap(), string("late"));
+        sending.join().unwrap();
+    }
+
+    #[test]
+    fn test_select_channel() {
+        let shell = Arc::new(RwLock::new(Shell::new()));
+        let (first_sender, first) = pair(&shell);
+        let (second_sender, second) = pair(&shell);
+        let receivers = Value::List(vec![first, second].into());
+        assert_eq!(select_channel(Some(shell.clone()), &[receivers.clone(), Value::Float(0.05)]).unwrap(), Value::Failure("timed out".to_string()));
+        let sending_shell = shell.clone();
+        let sending = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            send(Some(sending_shell), &[second_sender, string("second")]).unwrap();
+        });
+        match select_channel(Some(shell.clone()), &[receivers.clone()]).unwrap() {
+            Value::Map(map, _) => {
+                assert_eq!(map["index"], Value::Integer(1));
+                assert_eq!(map["value"], string("second"));
+            }
+            value => panic!("expected a map, got {:?}", value),
+        }
+        sending.join().unwrap();
+        close(Some(shell.clone()), &[first_sender]).unwrap();
+        assert_eq!(channel_count(&shell), 1);
+    }
+}
@@ -0,0 +1,140 @@
+use caat_rust::Value;
+use crate::shell::Shell;
+use crate::shell::job_manager::JobManager;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+
+fn as_duration(value: &Value) -> Option<Duration> {
+    match value {
+        Value::Integer(i) if *i >= 0 => Some(Duration::from_secs(*i as u64)),
+        Value::Float(f) if *f >= 0.0 => Some(Duration::from_secs_f64(*f)),
+        _ => None,
+    }
+}
+
+/// The longest `retry` waits between attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Doubles a `retry` delay, up to `MAX_RETRY_DELAY`.
+fn next_delay(delay: Duration) -> Duration {
+    delay.saturating_mul(2).min(MAX_RETRY_DELAY)
+}
+
+/// `timeout seconds command args...`
+///
+/// Runs the command in a fork and returns a `timed out` failure once the limit
+/// passes. The fork is cancelled, which stops it before its next statement, and
+/// the external commands it is running are killed.
+pub fn timeout(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    if let Some(shell) = shell {
+        let limit = match args.get(0).and_then(as_duration) {
+            Some(limit) => limit,
+            None => return Err("timeout: expected a number of seconds as first argument".to_string()),
+        };
+        let command = match args.get(1) {
+            Some(command) => command.clone(),
+            None => return Err("timeout: No command provided".to_string()),
+        };
+        let borrowed_shell = borrow!(shell);
+        let job_shell = Arc::new(RwLock::new(borrowed_shell.fork(shell.clone())));
+        drop(borrowed_shell);
+        return JobManager::call_with_timeout(job_shell, command, &args[2..].to_vec(), limit);
+    } else {
+        return Err("timeout: Called from bad context".to_string());
+    }
+}
+
+/// `retry attempts [delay] command args...`
+///
+/// Calls the command until it returns something other than a failure, up to
+/// `attempts` times. When a delay in seconds is given it is waited before the
+/// second attempt and doubled before each one after that, up to
+/// `MAX_RETRY_DELAY`.
+pub fn retry(args: &[Value]) -> Result<Value,String> {
+    let position = match args.iter().position(|arg| matches!(arg, Value::CAATFunction(_))) {
+        Some(position) => position,
+        None => return Err("retry: No command provided".to_string()),
+    };
+    let function = match &args[position] {
+        Value::CAATFunction(f) => f,
+        _ => unreachable!(),
+    };
+    let attempts = match args.get(0) {
+        Some(Value::Integer(n)) if *n > 0 && position > 0 => *n,
+        _ => return Err("retry: expected a positive number of attempts as first argument".to_string()),
+    };
+    let mut delay = match position {
+        1 => None,
+        2 => match as_duration(&args[1]) {
+            Some(delay) => Some(delay),
+            None => return Err("retry: expected a delay in seconds as second argument".to_string()),
+        },
+        _ => return Err("retry: expected attempts and an optional delay before the command".to_string()),
+    };
+    let call_args = &args[position + 1..];
+
+    let mut result = function.call(call_args);
+    for _ in 1..attempts {
+        if let Value::Failure(_) = result {
+            if let Some(wait) = delay {
+                std::thread::sleep(wait);
+                delay = Some(next_delay(wait));
+            }
+            result = function.call(call_args);
+        } else {
+            break;
+        }
+    }
+    Ok(result)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Instant;
+
+    /// The pid of the last process `ExternalSleep` started.
+    static SLEEP_PID: AtomicU32 = AtomicU32::new(0);
+
+    /// Stands in for a foreign command, which runs in a child process.
+    #[derive(Debug)]
+    struct ExternalSleep;
+
+    impl caat_rust::Caat for ExternalSleep {
+        fn call(&self, _args: &[Value]) -> Value {
+            let mut child = match std::process::Command::new("sleep").arg("5").spawn() {
+                Ok(child) => child,
+                Err(e) => return Value::Failure(e.to_string()),
+            };
+            SLEEP_PID.store(child.id(), Ordering::SeqCst);
+            match child.wait() {
+                Ok(_) => Value::Null,
+                Err(e) => Value::Failure(e.to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_timeout_kills_external_command() {
+        let shell = Arc::new(RwLock::new(Shell::new()));
+        let start = Instant::now();
+        let result = timeout(Some(shell.clone()), &[Value::Float(0.2), Value::CAATFunction(Arc::new(ExternalSleep))]).unwrap();
+        assert_eq!(result, Value::Failure("timed out".to_string()));
+        assert!(start.elapsed() < Duration::from_secs(3));
+        let pid = SLEEP_PID.load(Ordering::SeqCst);
+        assert_ne!(pid, 0);
+        // The worker reaps the killed child, so it is gone from /proc.
+        assert!(!std::path::Path::new(&format!("/proc/{}", pid)).exists());
+        assert!(!borrow!(shell).is_cancelled());
+    }
+
+    #[test]
+    fn test_retry_delay_is_capped() {
+        assert_eq!(next_delay(Duration::from_secs(1)), Duration::from_secs(2));
+        assert_eq!(next_delay(Duration::from_secs(40)), MAX_RETRY_DELAY);
+        assert_eq!(next_delay(Duration::MAX), MAX_RETRY_DELAY);
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use caat_rust::Value;
+use crate::eval::format_value_file;
+use crate::formats::{csv, ini, json, toml};
+use super::ls::{dir_entry, EntryFormat, Names};
+
+
+/// The path in a string or a `dir_entry` map.
+fn path_of(command: &str, value: &Value) -> Result<PathBuf, String> {
+    match value {
+        Value::String(path) => Ok(PathBuf::from(path)),
+        Value::Map(map, _) if map.get("type") == Some(&Value::String("dir_entry".to_string())) => {
+            match map.get("full_path") {
+                Some(Value::String(path)) => Ok(PathBuf::from(path)),
+                _ => Err(format!("{}: dir_entry without a full_path", command)),
+            }
+        }
+        _ => Err(format!("{}: expected a path or a dir_entry", command)),
+    }
+}
+
+/// Splits arguments into single letter flags and paths. Lists are flattened so
+/// the output of `ls` and `find` can be passed straight through.
+fn parse_args(command: &str, args: &[Value], allowed: &str) -> Result<(HashSet<char>, Vec<PathBuf>), String> {
+    let mut flags = HashSet::new();
+    let mut paths = Vec::new();
+    for arg in args {
+        match arg {
+            Value::String(flag) if flag.starts_with('-') && flag.len() > 1 => {
+                for c in flag.chars().skip(1) {
+                    if !allowed.contains(c) {
+                        return Err(format!("{}: unknown flag -{}", command, c));
+                    }
+                    flags.insert(c);
+                }
+            }
+            Value::List(list) => {
+                for value in list.iter() {
+                    paths.push(path_of(command, value)?);
+                }
+            }
+            value => paths.push(path_of(command, value)?),
+        }
+    }
+    Ok((flags, paths))
+}
+
+/// Looks for `--format=name` among the arguments, returning the rest.
+fn format_option(args: &[Value]) -> (Option<String>, Vec<Value>) {
+    let mut format = None;
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg {
+            Value::String(option) if option.starts_with("--format=") => format = Some(option["--format=".len()..].to_string()),
+            value => rest.push(value.clone()),
+        }
+    }
+    (format, rest)
+}
+
+/// The format `open` and `save` use for a path they aren't told the format of.
+fn format_of(path: &Path) -> &'static str {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("json") => "json",
+        Some("csv") => "csv",
+        Some("tsv") => "tsv",
+        Some("toml") => "toml",
+        Some("ini") => "ini",
+        _ => "text",
+    }
+}
+
+fn decode(text: &str, format: &str) -> Result<Value, String> {
+    match format {
+        "json" => json::from_str(text),
+        "csv" => csv::from_str(text, &csv::Options::default()),
+        "tsv" => csv::from_str(text, &csv::Options::tsv()),
+        "toml" => toml::from_str(text),
+        "ini" => ini::from_str(text),
+        "lines" => Ok(Value::List(text.lines().map(|line| Value::String(line.to_string())).collect())),
+        "text" => Ok(Value::String(text.to_string())),
+        _ => Err(format!("open: unknown format {}, expected json, csv, tsv, toml, ini, lines or text", format)),
+    }
+}
+
+fn encode(value: &Value, format: &str) -> Result<String, String> {
+    match format {
+        "json" => Ok(json::to_string(value, true)? + "\n"),
+        "csv" => csv::to_string(value, &csv::Options::default()),
+        "tsv" => csv::to_string(value, &csv::Options::tsv()),
+        "toml" => toml::to_string(value),
+        "ini" => Err("save: INI can only be read, save as toml instead".to_string()),
+        "lines" => match value {
+            Value::List(list) => Ok(list.iter().map(|value| format_value_file(value) + "\n").collect()),
+            value => Ok(format_value_file(value) + "\n"),
+        },
+        "text" => Ok(format_value_file(value) + "\n"),
+        _ => Err(format!("save: unknown format {}, expected json, csv, tsv, toml, ini, lines or text", format)),
+    }
+}
+
+/// The `dir_entry` of each path, as a list even when there is only one.
+fn entries(paths: &[PathBuf], format: &EntryFormat) -> Result<Value, String> {
+    let names = Names::load();
+    let mut output = Vec::new();
+    for path in paths {
+        let metadata = path.symlink_metadata().map_err(|e| format!("{}: {}", path.display(), e))?;
+        output.push(dir_entry(path, &metadata, &names, format)?);
+    }
+    Ok(Value::List(output.into()))
+}
+
+/// `open path [--format=name]` reads a file, decoding it by its extension:
+/// json, csv, tsv, toml and ini, or text otherwise. The format can also be `lines`
+/// for a list of lines.
+pub fn open(args: &[Value]) -> Result<Value, String> {
+    let (format, args) = format_option(args);
+    let path = match args.as_slice() {
+        [path] => path_of("open", path)?,
+        _ => return Err("open: expected a path".to_string()),
+    };
+    let text = fs::read_to_string(&path).map_err(|e| format!("open: {}: {}", path.display(), e))?;
+    let format = format.unwrap_or(format_of(&path).to_string());
+    decode(&text, &format)
+}
+
+/// `save path value [--format=name]` writes a value to a file, encoding it by
+/// the extension the same way `open` decodes it. Text is written the way `>`
+/// writes it.
+pub fn save(args: &[Value]) -> Result<Value, String> {
+    let (format, args) = format_option(args);
+    let (path, value) = match args.as_slice() {
+        [path, value] => (path_of("save", path)?, value),
+        _ => return Err("save: expected a path and a value".to_string()),
+    };
+    let format = format.unwrap_or(format_of(&path).to_string());
+    let text = encode(value, &format)?;
+    fs::write(&path, text).map_err(|e| format!("save: {}: {}", path.display(), e))?;
+    entries(&[path], &EntryFormat::default())
+}
+
+/// `append path value` adds a value to the end of a file the way `>>` does.
+pub fn append(args: &[Value]) -> Result<Value, String> {
+    let (path, value) = match args {
+        [path, value] => (path_of("append", path)?, value),
+        _ => return Err("append: expected a path and a value".to_string()),
+    };
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(|e| format!("append: {}: {}", path.display(), e))?;
+    writeln!(file, "{}", format_value_file(value)).map_err(|e| format!("append: {}: {}", path.display(), e))?;
+    entries(&[path], &EntryFormat::default())
+}
+
+/// `mkdir [-p] paths...` creates directories, and with `-p` their parents
+/// without failing on ones that exist.
+pub fn mkdir(args: &[Value]) -> Result<Value, String> {
+    let (flags, paths) = parse_args("mkdir", args, "p")?;
+    if paths.is_empty() {
+        return Err("mkdir: expected a path".to_string());
+    }
+    for path in &paths {
+        let result = if flags.contains(&'p') {
+            fs::create_dir_all(path)
+        } else {
+            fs::create_dir(path)
+        };
+        result.map_err(|e| format!("mkdir: {}: {}", path.display(), e))?;
+    }
+    entries(&paths, &EntryFormat::default())
+}
+
+/// `rm [-rf] paths...` removes files, and directories with `-r`. With `-f`
+/// missing paths are ignored. Returns the paths that were removed.
+pub fn rm(args: &[Value]) -> Result<Value, String> {
+    let (flags, paths) = parse_args("rm", args, "rf")?;
+    if paths.is_empty() && !flags.contains(&'f') {
+        return Err("rm: expected a path".to_string());
+    }
+    let mut removed = Vec::new();
+    for path in paths {
+        let metadata = match path.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(_) if flags.contains(&'f') => continue,
+            Err(e) => return Err(format!("rm: {}: {}", path.display(), e)),
+        };
+        let result = if metadata.is_dir() {
+            if !flags.contains(&'r') {
+                return Err(format!("rm: {} is a directory, use -r", path.display()));
+            }
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        result.map_err(|e| format!("rm: {}: {}", path.display(), e))?;
+        removed.push(Value::String(path.to_string_lossy().to_string()));
+    }
+    Ok(Value::List(removed.into()))
+}
+
+fn copy(from: &Path, to: &Path, recursive: bool) -> Result<(), String> {
+    let metadata = from.symlink_metadata().map_err(|e| format!("cp: {}: {}", from.display(), e))?;
+    if metadata.is_dir() {
+        if !recursive {
+            return Err(format!("cp: {} is a directory, use -r", from.display()));
+        }
+        fs::create_dir_all(to).map_err(|e| format!("cp: {}: {}", to.display(), e))?;
+        let children = fs::read_dir(from).map_err(|e| format!("cp: {}: {}", from.display(), e))?;
+        for child in children {
+            let child = child.map_err(|e| format!("cp: {}: {}", from.display(), e))?;
+            copy(&child.path(), &to.join(child.file_name()), true)?;
+        }
+    } else if metadata.file_type().is_symlink() {
+        let target = fs::read_link(from).map_err(|e| format!("cp: {}: {}", from.display(), e))?;
+        std::os::unix::fs::symlink(target, to).map_err(|e| format!("cp: {}: {}", to.display(), e))?;
+    } else {
+        fs::copy(from, to).map_err(|e| format!("cp: {}: {}", to.display(), e))?;
+    }
+    Ok(())
+}
+
+/// Whether `target` is `source` or somewhere inside it, following links.
+/// The target doesn't have to exist yet.
+fn is_inside(source: &Path, target: &Path) -> bool {
+    let source = match source.canonicalize() {
+        Ok(source) => source,
+        Err(_) => return false,
+    };
+    let target = match (target.canonicalize(), target.parent(), target.file_name()) {
+        (Ok(target), _, _) => target,
+        (Err(_), Some(parent), Some(name)) => {
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            match parent.canonicalize() {
+                Ok(parent) => parent.join(name),
+                Err(_) => return false,
+            }
+        }
+        _ => return false,
+    };
+    target.starts_with(source)
+}
+
+/// Splits `sources... destination`, putting the sources inside the destination
+/// when it is a directory.
+fn destinations(command: &str, mut paths: Vec<PathBuf>) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    if paths.len() < 2 {
+        return Err(format!("{}: expected a source and a destination", command));
+    }
+    let destination = paths.pop().unwrap();
+    if !destination.is_dir() {
+        if paths.len() > 1 {
+            return Err(format!("{}: {} is not a directory", command, destination.display()));
+        }
+        return Ok(vec![(paths.pop().unwrap(), destination)]);
+    }
+    paths.into_iter().map(|source| {
+        match source.file_name() {
+            Some(name) => {
+                let target = destination.join(name);
+                Ok((source, target))
+            }
+            None => Err(format!("{}: {} has no file name", command, source.display())),
+        }
+    }).collect()
+}
+
+/// `cp [-r] sources... destination` copies files, and directories with `-r`.
+/// A directory can't be copied into itself. Returns the `dir_entry` of each
+/// copy.
+pub fn cp(args: &[Value]) -> Result<Value, String> {
+    let (flags, paths) = parse_args("cp", args, "r")?;
+    let mut copies = Vec::new();
+    for (source, target) in destinations("cp", paths)? {
+        if source.is_dir() && is_inside(&source, &target) {
+            return Err(format!("cp: can't copy {} into itself", source.display()));
+        }
+        copy(&source, &target, flags.contains(&'r'))?;
+        copies.push(target);
+    }
+    entries(&copies, &EntryFormat::default())
+}
+
+/// `mv sources... destination` moves files and directories, copying them
+/// when they are on another filesystem.
+pub fn mv(args: &[Value]) -> Result<Value, String> {
+    let (_, paths) = parse_args("mv", args, "")?;
+    let mut moved = Vec::new();
+    for (source, target) in destinations("mv", paths)? {
+        match fs::rename(&source, &target) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                copy(&source, &target, true).map_err(|e| e.replacen("cp:", "mv:", 1))?;
+                let result = if source.is_dir() && !source.is_symlink() {
+                    fs::remove_dir_all(&source)
+                } else {
+                    fs::remove_file(&source)
+                };
+                result.map_err(|e| format!("mv: {}: {}", source.display(), e))?;
+            }
+            Err(e) => return Err(format!("mv: {}: {}", source.display(), e)),
+        }
+        moved.push(target);
+    }
+    entries(&moved, &EntryFormat::default())
+}
+
+/// `touch paths...` creates empty files, or sets the modified time of ones
+/// that exist.
+pub fn touch(args: &[Value]) -> Result<Value, String> {
+    let (_, paths) = parse_args("touch", args, "")?;
+    if paths.is_empty() {
+        return Err("touch: expected a path".to_string());
+    }
+    for path in &paths {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("touch: {}: {}", path.display(), e))?;
+        file.set_modified(SystemTime::now()).map_err(|e| format!("touch: {}: {}", path.display(), e))?;
+    }
+    entries(&paths, &EntryFormat::default())
+}
+
+/// `stat paths...` returns the `dir_entry` of paths without following links,
+/// shown in the long format.
+pub fn stat(args: &[Value]) -> Result<Value, String> {
+    let (_, paths) = parse_args("stat", args, "")?;
+    if paths.is_empty() {
+        return Err("stat: expected a path".to_string());
+    }
+    let format = EntryFormat { long: true, full_path: true, ..EntryFormat::default() };
+    entries(&paths, &format).map_err(|e| format!("stat: {}", e))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_save_and_open() {
+        let dir = std::env::temp_dir().join(format!("caat_files_{}", std::process::id()));
+        let path = |name: &str| string(&dir.join(name).to_string_lossy());
+        mkdir(&[string("-p"), path("sub")]).unwrap();
+        let value = Value::List(vec![Value::Integer(1), string("two")].into());
+        save(&[path("list.json"), value.clone()]).unwrap();
+        assert_eq!(open(&[path("list.json")]).unwrap(), value);
+        append(&[path("log"), string("one")]).unwrap();
+        append(&[path("log"), string("two")]).unwrap();
+        assert_eq!(open(&[path("log")]).unwrap(), string("one\ntwo\n"));
+        assert_eq!(open(&[path("log"), string("--format=lines")]).unwrap(), Value::List(vec![string("one"), string("two")].into()));
+        let entry = stat(&[path("log")]).unwrap();
+        assert!(matches!(&entry, Value::List(list) if list.len() == 1));
+        cp(&[entry, path("sub")]).unwrap();
+        mv(&[path("sub/log"), path("moved")]).unwrap();
+        assert!(dir.join("moved").exists() && !dir.join("sub/log").exists());
+        assert!(rm(&[path("sub")]).is_err());
+        touch(&[path("sub/new")]).unwrap();
+        rm(&[string("-r"), path("sub")]).unwrap();
+        assert!(!dir.join("sub").exists());
+        rm(&[string("-r"), string(&dir.to_string_lossy())]).unwrap();
+    }
+
+    #[test]
+    fn test_cp_into_itself() {
+        let dir = std::env::temp_dir().join(format!("caat_cp_{}", std::process::id()));
+        let path = |name: &str| string(&dir.join(name).to_string_lossy());
+        mkdir(&[string("-p"), path("a/sub")]).unwrap();
+        assert!(cp(&[string("-r"), path("a"), path("a/sub")]).is_err());
+        assert!(cp(&[string("-r"), path("a"), path("a/copy")]).is_err());
+        assert!(cp(&[string("-r"), path("a"), path("a")]).is_err());
+        let copies = cp(&[string("-r"), path("a"), path("b")]).unwrap();
+        assert!(matches!(copies, Value::List(list) if list.len() == 1));
+        assert!(dir.join("b/sub").is_dir());
+        rm(&[string("-r"), string(&dir.to_string_lossy())]).unwrap();
+    }
+}
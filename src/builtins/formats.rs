@@ -0,0 +1,164 @@
+use caat_rust::Value;
+use crate::formats::{csv, ini, json, toml};
+
+
+/// Takes the text to decode, returning values that aren't strings, such as
+/// a file `open` already decoded, as they are.
+fn text_arg<'a>(command: &str, args: &'a [Value]) -> Result<Result<&'a str, Value>, String> {
+    match args {
+        [Value::String(text)] => Ok(Ok(text.as_str())),
+        [value] => Ok(Err(value.clone())),
+        _ => Err(format!("{}: expected text to decode", command)),
+    }
+}
+
+/// `from_json text` parses JSON. `null` becomes `()`, objects become maps and
+/// numbers become integers when they fit and floats otherwise.
+pub fn from_json(args: &[Value]) -> Result<Value, String> {
+    match text_arg("from_json", args)? {
+        Ok(text) => json::from_str(text),
+        Err(value) => Ok(value),
+    }
+}
+
+/// `to_json [--pretty] [--indent=n] value` writes a value as JSON, on one line
+/// unless asked to indent it. `()` becomes `null`, a failure becomes
+/// `{"failure": message}` and functions are an error.
+pub fn to_json(args: &[Value]) -> Result<Value, String> {
+    let mut indent = None;
+    let mut values = Vec::new();
+    for arg in args {
+        match arg {
+            Value::String(option) if option == "--pretty" => indent = Some(2),
+            Value::String(option) if option.starts_with("--indent=") => {
+                let n = &option["--indent=".len()..];
+                indent = Some(n.parse().map_err(|_| format!("to_json: invalid indent {}", n))?);
+            }
+            value => values.push(value),
+        }
+    }
+    let value = match values.as_slice() {
+        [value] => value,
+        _ => return Err("to_json: expected a value".to_string()),
+    };
+    let text = match indent {
+        Some(indent) => json::to_string_indented(value, indent)?,
+        None => json::to_string(value, false)?,
+    };
+    Ok(Value::String(text))
+}
+
+
+/// Reads `--delimiter=c`, `--no-header` and `--no-infer`, returning the
+/// other arguments.
+fn csv_options(command: &str, args: &[Value], mut options: csv::Options) -> Result<(csv::Options, Vec<Value>), String> {
+    let mut rest = Vec::new();
+    for arg in args {
+        match arg {
+            Value::String(option) if option.starts_with("--delimiter=") => {
+                let mut chars = option["--delimiter=".len()..].chars();
+                options.delimiter = match (chars.next(), chars.next()) {
+                    (Some(c), None) if c != '"' && c != '\n' && c != '\r' => c,
+                    _ => return Err(format!("{}: the delimiter must be a single character other than a quote or newline", command)),
+                };
+            }
+            Value::String(option) if option == "--no-header" => options.headers = false,
+            Value::String(option) if option == "--no-infer" => options.infer = false,
+            value => rest.push(value.clone()),
+        }
+    }
+    Ok((options, rest))
+}
+
+fn from_delimited(command: &str, args: &[Value], options: csv::Options) -> Result<Value, String> {
+    let (options, args) = csv_options(command, args, options)?;
+    match text_arg(command, &args)? {
+        Ok(text) => csv::from_str(text, &options),
+        Err(value) => Ok(value),
+    }
+}
+
+fn to_delimited(command: &str, args: &[Value], options: csv::Options) -> Result<Value, String> {
+    let (options, args) = csv_options(command, args, options)?;
+    match args.as_slice() {
+        [value] => Ok(Value::String(csv::to_string(value, &options)?)),
+        _ => Err(format!("{}: expected a list of maps or lists", command)),
+    }
+}
+
+/// `from_csv [--delimiter=c] [--no-header] [--no-infer] text` parses comma
+/// separated values into a list of maps keyed by the header, or a list of
+/// lists with `--no-header`.
+pub fn from_csv(args: &[Value]) -> Result<Value, String> {
+    from_delimited("from_csv", args, csv::Options::default())
+}
+
+/// `to_csv [--delimiter=c] [--no-header] list` writes a list of maps or lists
+/// as comma separated values.
+pub fn to_csv(args: &[Value]) -> Result<Value, String> {
+    to_delimited("to_csv", args, csv::Options::default())
+}
+
+pub fn from_tsv(args: &[Value]) -> Result<Value, String> {
+    from_delimited("from_tsv", args, csv::Options::tsv())
+}
+
+pub fn to_tsv(args: &[Value]) -> Result<Value, String> {
+    to_delimited("to_tsv", args, csv::Options::tsv())
+}
+
+
+/// `from_toml text` parses a TOML document into a map. Tables become nested
+/// maps and datetimes become strings in their TOML form.
+pub fn from_toml(args: &[Value]) -> Result<Value, String> {
+    match text_arg("from_toml", args)? {
+        Ok(text) => toml::from_str(text),
+        Err(value) => Ok(value),
+    }
+}
+
+/// `to_toml map` writes a map as a TOML document, leaving out `()` entries.
+pub fn to_toml(args: &[Value]) -> Result<Value, String> {
+    match args {
+        [value] => Ok(Value::String(toml::to_string(value)?)),
+        _ => Err("to_toml: expected a map".to_string()),
+    }
+}
+
+/// `from_ini text` parses an INI file into a map with a map for each section.
+pub fn from_ini(args: &[Value]) -> Result<Value, String> {
+    match text_arg("from_ini", args)? {
+        Ok(text) => ini::from_str(text),
+        Err(value) => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_to_json() {
+        let list = Value::List(vec![Value::Integer(1), Value::Null].into());
+        assert_eq!(to_json(&[list.clone()]).unwrap(), string("[1,null]"));
+        assert_eq!(to_json(&[string("--indent=4"), list.clone()]).unwrap(), string("[\n    1,\n    null\n]"));
+        assert_eq!(from_json(&[string("[1, null]")]).unwrap(), list);
+        assert_eq!(from_json(&[list.clone()]).unwrap(), list);
+        assert!(to_json(&[string("--pretty")]).is_err());
+    }
+
+    #[test]
+    fn test_csv_options() {
+        let rows = from_csv(&[string("--delimiter=;"), string("--no-infer"), string("a;b\n1;x\n")]).unwrap();
+        let mut map = std::collections::HashMap::new();
+        map.insert("a".to_string(), string("1"));
+        map.insert("b".to_string(), string("x"));
+        assert_eq!(rows, Value::List(vec![Value::Map(map, None)].into()));
+        assert_eq!(to_csv(&[string("--no-header"), rows]).unwrap(), string("\"1\",x\n"));
+        assert!(from_csv(&[string("--delimiter=ab"), string("")]).is_err());
+    }
+}
@@ -0,0 +1,168 @@
+use caat_rust::Value;
+use crate::shell::function::Function;
+use crate::shell::Shell;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use super::Builtin;
+
+
+/// What `help` and `describe` know about a command.
+struct Description {
+    kind: &'static str,
+    name: String,
+    signature: String,
+    help: String,
+    arguments: Vec<String>,
+    examples: Vec<String>,
+}
+
+/// Splits the arguments out of a usage line, keeping `[optional parts]` whole.
+fn signature_arguments(signature: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+    for c in signature.chars() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ' ' if depth == 0 => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+    arguments.into_iter().skip(1).collect()
+}
+
+fn describe_builtin(kind: &'static str, builtin: &dyn Builtin) -> Description {
+    Description {
+        kind,
+        name: builtin.name().to_string(),
+        signature: builtin.signature().to_string(),
+        help: builtin.help().to_string(),
+        arguments: signature_arguments(builtin.signature()),
+        examples: builtin.examples().to_vec(),
+    }
+}
+
+fn describe_function(function: &Function) -> Description {
+    let mut signature = function.name.clone();
+    for argument in &function.arguments {
+        signature.push(' ');
+        signature.push_str(argument);
+    }
+    Description {
+        kind: "function",
+        name: function.name.clone(),
+        signature,
+        help: function.doc.clone().unwrap_or_default(),
+        arguments: function.arguments.clone(),
+        examples: Vec::new(),
+    }
+}
+
+/// Looks a command up the way a pipeline would, user functions first.
+fn lookup(shell: &Option<Arc<RwLock<Shell>>>, name: &str) -> Option<Description> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => return super::default_registry().get(name).map(|builtin| describe_builtin("builtin", builtin.as_ref())),
+    };
+    let borrowed_shell = borrow!(shell);
+    if let Some(function) = borrowed_shell.get_function(name) {
+        return Some(describe_function(&function));
+    }
+    let kind = if borrowed_shell.plugins().contains_key(name) { "plugin" } else { "builtin" };
+    borrowed_shell.builtins().get(name).map(|builtin| describe_builtin(kind, builtin.as_ref()))
+}
+
+/// Every user function, sorted by name, followed by every builtin.
+fn all(shell: &Option<Arc<RwLock<Shell>>>) -> Vec<Description> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => return super::default_registry().list().iter().map(|builtin| describe_builtin("builtin", builtin.as_ref())).collect(),
+    };
+    let borrowed_shell = borrow!(shell);
+    let mut names = borrowed_shell.function_names();
+    names.sort();
+    let mut output: Vec<Description> = names.iter()
+        .filter_map(|name| borrowed_shell.get_function(name))
+        .map(|function| describe_function(&function))
+        .collect();
+    for builtin in borrowed_shell.builtins().list() {
+        let kind = if borrowed_shell.plugins().contains_key(builtin.name()) { "plugin" } else { "builtin" };
+        output.push(describe_builtin(kind, builtin.as_ref()));
+    }
+    output
+}
+
+fn find(shell: &Option<Arc<RwLock<Shell>>>, command: &str, args: &[Value]) -> Result<Description, String> {
+    match args.get(0) {
+        Some(Value::String(name)) => lookup(shell, name).ok_or(format!("{}: no command named {}", command, name)),
+        _ => Err(format!("{}: expected a command name as a string", command)),
+    }
+}
+
+fn strings(strings: &[String]) -> Value {
+    Value::List(strings.iter().map(|s| Value::String(s.clone())).collect())
+}
+
+/// `help [name]` shows the usage, arguments and examples of a command, or a
+/// one line summary of every command without a name.
+pub fn help(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value, String> {
+    if args.is_empty() {
+        let lines: Vec<String> = all(&shell).iter()
+            .map(|description| format!("{} - {}", description.signature, description.help.lines().next().unwrap_or("")))
+            .collect();
+        return Ok(Value::String(lines.join("\n")));
+    }
+    let description = find(&shell, "help", args)?;
+    let mut output = format!("usage: {}", description.signature);
+    if !description.help.is_empty() {
+        output.push_str("\n\n");
+        output.push_str(&description.help);
+    }
+    if !description.arguments.is_empty() {
+        output.push_str("\n\narguments: ");
+        output.push_str(&description.arguments.join(" "));
+    }
+    if !description.examples.is_empty() {
+        output.push_str("\n\nexamples:");
+        for example in &description.examples {
+            output.push_str("\n    ");
+            output.push_str(example);
+        }
+    }
+    Ok(Value::String(output))
+}
+
+/// `describe name` returns what `help` shows as a map.
+pub fn describe(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value, String> {
+    let description = find(&shell, "describe", args)?;
+    let mut map = HashMap::new();
+    map.insert("type".to_string(), Value::String(description.kind.to_string()));
+    map.insert("name".to_string(), Value::String(description.name));
+    map.insert("signature".to_string(), Value::String(description.signature));
+    map.insert("help".to_string(), Value::String(description.help));
+    map.insert("arguments".to_string(), strings(&description.arguments));
+    map.insert("examples".to_string(), strings(&description.examples));
+    Ok(Value::Map(map, Some(String::from("{signature} {help}"))))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature_arguments() {
+        assert_eq!(signature_arguments("par_map [-j n] list function"), vec!["[-j n]", "list", "function"]);
+        assert_eq!(signature_arguments("args"), Vec::<String>::new());
+    }
+}
@@ -1,11 +1,16 @@
 
 use caat_rust::Value;
 use rand::seq::SliceRandom;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::shell::Shell;
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 
 
 
 
-fn find_function<'input>(list: &'input Vec<Value>) -> Result<&'input Value, String> {
+fn find_function<'input>(list: &'input [Value]) -> Result<&'input Value, String> {
     for value in list {
         match value {
             Value::CAATFunction(_) => return Ok(&value),
@@ -15,7 +20,7 @@ fn find_function<'input>(list: &'input Vec<Value>) -> Result<&'input Value, Stri
     Err("No function found".to_string())
 }
 
-fn find_list<'input>(list: &'input Vec<Value>) -> Result<&'input Value, String> {
+fn find_list<'input>(list: &'input [Value]) -> Result<&'input Value, String> {
     for value in list {
         match value {
             Value::List(_) => return Ok(&value),
@@ -25,9 +30,58 @@ fn find_list<'input>(list: &'input Vec<Value>) -> Result<&'input Value, String>
     Err("No list found".to_string())
 }
 
+/// Splits a list from the other arguments. The list is the last argument, as
+/// a pipeline passes it, or the first.
+pub fn list_args<'a>(command: &str, args: &'a [Value]) -> Result<(&'a [Value], &'a [Value]), String> {
+    match (args.first(), args.last()) {
+        (_, Some(Value::List(list))) => Ok((&list[..], &args[..args.len() - 1])),
+        (Some(Value::List(list)), _) => Ok((&list[..], &args[1..])),
+        _ => Err(format!("{}: expected a list", command)),
+    }
+}
+
+fn type_rank(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Boolean(_) => 1,
+        Value::Integer(_) | Value::Float(_) => 2,
+        Value::String(_) => 3,
+        Value::List(_) => 4,
+        Value::Map(_, _) => 5,
+        Value::Failure(_) => 6,
+        Value::CAATFunction(_) => 7,
+    }
+}
+
+/// Orders values for sorting. Values of different types are ordered by type,
+/// `()` first, and integers and floats are compared as numbers. Lists compare
+/// item by item and maps by their size.
+pub fn compare_values(a: &Value, b: &Value) -> cmp::Ordering {
+    match (a, b) {
+        (Value::Boolean(a), Value::Boolean(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+        (Value::Integer(a), Value::Float(b)) => (*a as f64).total_cmp(b),
+        (Value::Float(a), Value::Integer(b)) => a.total_cmp(&(*b as f64)),
+        (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Failure(a), Value::Failure(b)) => a.cmp(b),
+        (Value::List(a), Value::List(b)) => {
+            for (a, b) in a.iter().zip(b.iter()) {
+                match compare_values(a, b) {
+                    cmp::Ordering::Equal => continue,
+                    ordering => return ordering,
+                }
+            }
+            a.len().cmp(&b.len())
+        }
+        (Value::Map(a, _), Value::Map(b, _)) => a.len().cmp(&b.len()),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
 
 
-pub fn map(args: &Vec<Value>) -> Result<Value,String> {
+
+pub fn map(args: &[Value]) -> Result<Value,String> {
     let list = match find_list(&args)? {
         Value::List(l) => l,
         _ => return Err("No list found".to_string()),
@@ -46,7 +100,7 @@ pub fn map(args: &Vec<Value>) -> Result<Value,String> {
 }
 
 
-fn fold_find_list(list: &Vec<Value>) -> Result<&Value, String> {
+fn fold_find_list(list: &[Value]) -> Result<&Value, String> {
     let mut iter = list.iter();
     let front = iter.next();
     let last = iter.next_back();
@@ -60,7 +114,7 @@ fn fold_find_list(list: &Vec<Value>) -> Result<&Value, String> {
 }
 
 
-fn fold_find_function(list: &Vec<Value>) -> Result<&Value, String> {
+fn fold_find_function(list: &[Value]) -> Result<&Value, String> {
     let mut iter = list.iter();
     let front = iter.next();
     let last = iter.next_back();
@@ -73,7 +127,7 @@ fn fold_find_function(list: &Vec<Value>) -> Result<&Value, String> {
     }
 }
 
-pub fn fold(args: &Vec<Value>) -> Result<Value, String> {
+pub fn fold(args: &[Value]) -> Result<Value, String> {
     let list = fold_find_list(args)?;
     let function = fold_find_function(args)?;
     let start = match args.get(1) {
@@ -91,7 +145,7 @@ pub fn fold(args: &Vec<Value>) -> Result<Value, String> {
     Ok(acc)
 }
 
-pub fn concat(args: &Vec<Value>) -> Result<Value, String> {
+pub fn concat(args: &[Value]) -> Result<Value, String> {
     let mut output = Vec::new();
     for arg in args.iter() {
         match arg {
@@ -102,7 +156,7 @@ pub fn concat(args: &Vec<Value>) -> Result<Value, String> {
     Ok(Value::List(output.into()))
 }
 
-pub fn filter(args: &Vec<Value>) -> Result<Value, String> {
+pub fn filter(args: &[Value]) -> Result<Value, String> {
     let list = find_list(args)?;
     let function = find_function(args)?;
     let mut output = Vec::new();
@@ -121,7 +175,115 @@ pub fn filter(args: &Vec<Value>) -> Result<Value, String> {
 
 
 
-pub fn shuf(args: &Vec<Value>) -> Result<Value,String> {
+/// Reads a `"-j" N` option, defaulting to the number of available cores.
+fn find_jobs(list: &[Value], command: &str) -> Result<usize, String> {
+    let mut iter = list.iter();
+    while let Some(value) = iter.next() {
+        if let Value::String(s) = value {
+            if s == "-j" {
+                return match iter.next() {
+                    Some(Value::Integer(n)) if *n > 0 => Ok(*n as usize),
+                    _ => Err(format!("{}: -j expects a positive integer", command)),
+                };
+            }
+        }
+    }
+    Ok(std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+}
+
+/// Calls `call` on every item of `list` using at most `jobs` worker threads and
+/// returns the results in the same order as the input.
+///
+/// Each worker runs in its own fork of `shell`, so functions called from
+/// different workers never share a scope stack. Without a shell there is
+/// nothing to fork, so the caller gets an error instead of workers racing on
+/// the scopes of the shell the function was defined in.
+fn parallel_calls<F>(command: &str, shell: Option<Arc<RwLock<Shell>>>, list: &[Value], jobs: usize, call: F) -> Result<Vec<Value>, String>
+where
+    F: Fn(&Value) -> Value + Sync,
+{
+    let shell = match shell {
+        Some(shell) => shell,
+        None => return Err(format!("{}: Called from bad context", command)),
+    };
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Value>>> = Mutex::new(vec![None; list.len()]);
+    let next = &next;
+    let results = &results;
+    let call = &call;
+    let panicked = std::thread::scope(|scope| {
+        let mut workers = Vec::new();
+        for _ in 0..jobs.min(list.len()) {
+            let worker_shell = {
+                let borrowed_shell = borrow!(shell);
+                Arc::new(RwLock::new(borrowed_shell.fork(shell.clone())))
+            };
+            workers.push(scope.spawn(move || crate::shell::run_in(worker_shell, || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= list.len() {
+                    break;
+                }
+                let value = call(&list[i]);
+                match results.lock() {
+                    Ok(mut results) => results[i] = Some(value),
+                    Err(_) => break,
+                }
+            })));
+        }
+        workers.into_iter().fold(false, |panicked, worker| worker.join().is_err() || panicked)
+    });
+    let results = match results.lock() {
+        Ok(mut results) if !panicked => std::mem::take(&mut *results),
+        _ => return Err(format!("{}: a worker thread panicked", command)),
+    };
+    Ok(results.into_iter()
+        .map(|value| value.unwrap_or_else(|| Value::Failure("worker stopped before finishing".to_string())))
+        .collect())
+}
+
+/// Like `map`, but runs the calls concurrently. Items whose call fails are
+/// left in place as failures so the other results are kept.
+pub fn par_map(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    let list = match find_list(&args)? {
+        Value::List(l) => l,
+        _ => return Err("No list found".to_string()),
+    };
+    let function = match find_function(&args)? {
+        Value::CAATFunction(f) => f,
+        _ => return Err("No function found".to_string()),
+    };
+    let jobs = find_jobs(args, "par_map")?;
+    let output = parallel_calls("par_map", shell, &list.to_vec(), jobs, |value| function.call(&[value.clone()]))?;
+    Ok(Value::List(output.into()))
+}
+
+/// Like `filter`, but runs the calls concurrently. An item whose call fails
+/// is kept in place as a failure naming it, like `par_map` does, so the items
+/// that passed aren't lost.
+pub fn par_filter(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    let list = match find_list(&args)? {
+        Value::List(l) => l.to_vec(),
+        _ => return Err("No list found".to_string()),
+    };
+    let function = match find_function(&args)? {
+        Value::CAATFunction(f) => f,
+        _ => return Err("No function found".to_string()),
+    };
+    let jobs = find_jobs(args, "par_filter")?;
+    let results = parallel_calls("par_filter", shell, &list, jobs, |value| function.call(&[value.clone()]))?;
+    let mut output = Vec::new();
+    for (i, (value, result)) in list.into_iter().zip(results).enumerate() {
+        match result {
+            Value::Boolean(true) => output.push(value),
+            Value::Failure(msg) => output.push(Value::Failure(format!("par_filter: item {} failed: {}", i, msg))),
+            _ => {}
+        }
+    }
+    Ok(Value::List(output.into()))
+}
+
+
+pub fn shuf(args: &[Value]) -> Result<Value,String> {
     let mut rng = rand::thread_rng();
     let list = match args.get(0) {
         Some(Value::List(list)) => list.clone(),
@@ -132,7 +294,7 @@ pub fn shuf(args: &Vec<Value>) -> Result<Value,String> {
     Ok(Value::List(output.clone()))
 }
 
-pub fn head(args: &Vec<Value>) -> Result<Value,String> {
+pub fn head(args: &[Value]) -> Result<Value,String> {
     let list = match args.get(0) {
         Some(Value::List(list)) => list,
         _ => return Err("head: expected list as first argument".to_string()),
@@ -145,7 +307,7 @@ pub fn head(args: &Vec<Value>) -> Result<Value,String> {
     }
 }
 
-pub fn tail(args: &Vec<Value>) -> Result<Value, String> {
+pub fn tail(args: &[Value]) -> Result<Value, String> {
     let list = match args.get(0) {
         Some(Value::List(list)) => list,
         _ => return Err("tail: expected list as first argument".to_string()),
@@ -158,7 +320,7 @@ pub fn tail(args: &Vec<Value>) -> Result<Value, String> {
     }
 }
 
-pub fn rest(args: &Vec<Value>) -> Result<Value, String> {
+pub fn rest(args: &[Value]) -> Result<Value, String> {
     let list = match args.get(0) {
         Some(Value::List(list)) => list,
         _ => return Err("rest: expected list as first argument".to_string()),
@@ -167,12 +329,374 @@ pub fn rest(args: &Vec<Value>) -> Result<Value, String> {
     Ok(Value::List(output))
 }
 
-pub fn length(args: &Vec<Value>) -> Result<Value, String> {
-    let list = match args.get(0) {
-        Some(Value::List(list)) => list,
-        _ => return Err("length: expected list as first argument".to_string()),
+/// `length list` returns the number of items in a list, or of characters in
+/// a string.
+pub fn length(args: &[Value]) -> Result<Value, String> {
+    match args.get(0) {
+        Some(Value::List(list)) => Ok(Value::Integer(list.len() as i64)),
+        Some(Value::String(s)) => Ok(Value::Integer(s.graphemes(true).count() as i64)),
+        _ => Err("length: expected list or string as first argument".to_string()),
+    }
+}
+
+/// A stable merge sort that stops at the first error. Comparisons made by
+/// script functions can fail, or be inconsistent, which `sort_by` panics on.
+/// `before(a, b)` says whether `b`, from the right half, has to come before
+/// `a` from the left; equal items keep their order.
+fn merge_sort<F>(mut list: Vec<Value>, before: &mut F) -> Result<Vec<Value>, String>
+where
+    F: FnMut(&Value, &Value) -> Result<bool, String>,
+{
+    if list.len() <= 1 {
+        return Ok(list);
+    }
+    let right = list.split_off(list.len() / 2);
+    let left = merge_sort(list, before)?;
+    let right = merge_sort(right, before)?;
+    let mut output = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if before(a, b)? {
+            output.push(right.next().unwrap());
+        } else {
+            output.push(left.next().unwrap());
+        }
+    }
+    output.extend(left);
+    output.extend(right);
+    Ok(output)
+}
+
+/// Reads what a script comparator returns as whether its first argument has
+/// to come before its second: a negative integer, or true. Zero, a positive
+/// integer and false all leave the items where they are.
+fn comes_first(value: Value) -> Result<bool, String> {
+    match value {
+        Value::Integer(i) => Ok(i < 0),
+        Value::Boolean(b) => Ok(b),
+        Value::Failure(msg) => Err(msg),
+        _ => Err("sort: the comparator must return an integer or a boolean".to_string()),
+    }
+}
+
+/// `sort [function] list` sorts a list, keeping equal items in order. The
+/// function is called with two items and returns a negative, zero or positive
+/// integer, or true when the first should come before the second.
+pub fn sort(args: &[Value]) -> Result<Value, String> {
+    let (list, args) = list_args("sort", args)?;
+    let output = match args {
+        [] => merge_sort(list.to_vec(), &mut |a, b| Ok(compare_values(b, a) == cmp::Ordering::Less))?,
+        [Value::CAATFunction(function)] => merge_sort(list.to_vec(), &mut |a, b| comes_first(function.call(&[b.clone(), a.clone()])))?,
+        _ => return Err("sort: expected a list and an optional function".to_string()),
+    };
+    Ok(Value::List(output.into()))
+}
+
+/// `reverse list` reverses a list, or the characters of a string, keeping
+/// accents and emoji sequences together.
+pub fn reverse(args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::List(list)] => Ok(Value::List(list.iter().rev().cloned().collect())),
+        [Value::String(s)] => Ok(Value::String(s.graphemes(true).rev().collect())),
+        _ => Err("reverse: expected a list or a string".to_string()),
+    }
+}
+
+/// `zip lists...` pairs up the items of lists, stopping at the shortest.
+pub fn zip(args: &[Value]) -> Result<Value, String> {
+    let mut lists = Vec::new();
+    for arg in args {
+        match arg {
+            Value::List(list) => lists.push(list),
+            _ => return Err("zip: expected lists".to_string()),
+        }
+    }
+    let len = lists.iter().map(|list| list.len()).min().unwrap_or(0);
+    let output: Vec<Value> = (0..len)
+        .map(|i| Value::List(lists.iter().map(|list| list[i].clone()).collect()))
+        .collect();
+    Ok(Value::List(output.into()))
+}
+
+/// `enumerate list` pairs each item with its index.
+pub fn enumerate(args: &[Value]) -> Result<Value, String> {
+    match args {
+        [Value::List(list)] => {
+            let output: Vec<Value> = list.iter().enumerate()
+                .map(|(i, value)| Value::List(vec![Value::Integer(i as i64), value.clone()].into()))
+                .collect();
+            Ok(Value::List(output.into()))
+        }
+        _ => Err("enumerate: expected a list".to_string()),
+    }
+}
+
+/// The most items `range` makes, so a typo can't use up all memory.
+const MAX_RANGE: i128 = 100_000_000;
+
+/// `range [start] end [step]` counts from start, 0 by default, up to but not
+/// including end.
+pub fn range(args: &[Value]) -> Result<Value, String> {
+    let (start, end, step) = match args {
+        [Value::Integer(end)] => (0, *end, 1),
+        [Value::Integer(start), Value::Integer(end)] => (*start, *end, 1),
+        [Value::Integer(start), Value::Integer(end), Value::Integer(step)] => (*start, *end, *step),
+        _ => return Err("range: expected integers: [start] end [step]".to_string()),
+    };
+    if step == 0 {
+        return Err("range: step can't be 0".to_string());
+    }
+    let count = (end as i128 - start as i128 + step as i128 - step.signum() as i128) / step as i128;
+    if count > MAX_RANGE {
+        return Err(format!("range: {} items is more than the limit of {}", count, MAX_RANGE));
+    }
+    let mut output = Vec::with_capacity(count.max(0) as usize);
+    let mut i = start;
+    while (step > 0 && i < end) || (step < 0 && i > end) {
+        output.push(Value::Integer(i));
+        i = match i.checked_add(step) {
+            Some(i) => i,
+            None => break,
+        };
+    }
+    Ok(Value::List(output.into()))
+}
+
+fn flatten_into(list: &[Value], depth: usize, output: &mut Vec<Value>) {
+    for value in list {
+        match value {
+            Value::List(inner) if depth > 0 => flatten_into(inner, depth - 1, output),
+            value => output.push(value.clone()),
+        }
+    }
+}
+
+/// `flatten [depth] list` splices nested lists into their parent, one level
+/// deep by default.
+pub fn flatten(args: &[Value]) -> Result<Value, String> {
+    let (list, args) = list_args("flatten", args)?;
+    let depth = match args {
+        [] => 1,
+        [Value::Integer(depth)] if *depth >= 0 => *depth as usize,
+        _ => return Err("flatten: expected a list and an optional depth".to_string()),
+    };
+    let mut output = Vec::new();
+    flatten_into(list, depth, &mut output);
+    Ok(Value::List(output.into()))
+}
+
+fn count_arg(command: &str, args: &[Value]) -> Result<usize, String> {
+    match args {
+        [Value::Integer(n)] if *n >= 0 => Ok(*n as usize),
+        _ => Err(format!("{}: expected a list and a count that is not negative", command)),
+    }
+}
+
+/// `take n list` returns the first n items.
+pub fn take(args: &[Value]) -> Result<Value, String> {
+    let (list, args) = list_args("take", args)?;
+    let n = count_arg("take", args)?;
+    Ok(Value::List(list.iter().take(n).cloned().collect()))
+}
+
+/// `drop n list` returns every item but the first n.
+pub fn drop(args: &[Value]) -> Result<Value, String> {
+    let (list, args) = list_args("drop", args)?;
+    let n = count_arg("drop", args)?;
+    Ok(Value::List(list.iter().skip(n).cloned().collect()))
+}
+
+/// `slice start [end] list` returns the items from start up to but not
+/// including end. Negative indices count from the end.
+pub fn slice(args: &[Value]) -> Result<Value, String> {
+    let (list, args) = list_args("slice", args)?;
+    let index = |i: i64| if i < 0 {
+        (list.len() as i64 + i).max(0) as usize
+    } else {
+        (i as usize).min(list.len())
+    };
+    let (start, end) = match args {
+        [Value::Integer(start)] => (index(*start), list.len()),
+        [Value::Integer(start), Value::Integer(end)] => (index(*start), index(*end)),
+        _ => return Err("slice: expected a list, a start and an optional end".to_string()),
+    };
+    let output = if start < end { list[start..end].to_vec() } else { Vec::new() };
+    Ok(Value::List(output.into()))
+}
+
+/// Calls a predicate for `any`, `all` and `position`, or compares with a value.
+fn matches(command: &str, test: &Value, value: &Value) -> Result<bool, String> {
+    match test {
+        Value::CAATFunction(function) => match function.call(&[value.clone()]) {
+            Value::Boolean(b) => Ok(b),
+            Value::Failure(msg) => Err(msg),
+            _ => Err(format!("{}: the function must return a boolean", command)),
+        },
+        test => Ok(test == value),
+    }
+}
+
+fn test_arg<'a>(command: &str, args: &'a [Value]) -> Result<(&'a [Value], &'a Value), String> {
+    match list_args(command, args)? {
+        (list, [test]) => Ok((list, test)),
+        _ => Err(format!("{}: expected a list and a function or value", command)),
+    }
+}
+
+/// `any function list` returns whether the function is true for any item.
+pub fn any(args: &[Value]) -> Result<Value, String> {
+    let (list, test) = test_arg("any", args)?;
+    for value in list {
+        if matches("any", test, value)? {
+            return Ok(Value::Boolean(true));
+        }
+    }
+    Ok(Value::Boolean(false))
+}
+
+/// `all function list` returns whether the function is true for every item.
+pub fn all(args: &[Value]) -> Result<Value, String> {
+    let (list, test) = test_arg("all", args)?;
+    for value in list {
+        if !matches("all", test, value)? {
+            return Ok(Value::Boolean(false));
+        }
+    }
+    Ok(Value::Boolean(true))
+}
+
+/// `position test list` returns the index of the first item equal to a value
+/// or passing a function, or `()` if there is none.
+pub fn position(args: &[Value]) -> Result<Value, String> {
+    let (list, test) = test_arg("position", args)?;
+    for (i, value) in list.iter().enumerate() {
+        if matches("position", test, value)? {
+            return Ok(Value::Integer(i as i64));
+        }
+    }
+    Ok(Value::Null)
+}
+
+/// `chunks n list` splits a list into lists of n items, the last may be shorter.
+pub fn chunks(args: &[Value]) -> Result<Value, String> {
+    let (list, args) = list_args("chunks", args)?;
+    let n = match count_arg("chunks", args)? {
+        0 => return Err("chunks: the size must be at least 1".to_string()),
+        n => n,
     };
-    Ok(Value::Integer(list.len() as i64))
+    let output: Vec<Value> = list.chunks(n).map(|chunk| Value::List(chunk.to_vec().into())).collect();
+    Ok(Value::List(output.into()))
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(values: &[i64]) -> Value {
+        Value::List(values.iter().map(|i| Value::Integer(*i)).collect())
+    }
+
+    #[derive(Debug)]
+    struct ConstantTrue;
+
+    impl caat_rust::Caat for ConstantTrue {
+        fn call(&self, _args: &[Value]) -> Value {
+            Value::Boolean(true)
+        }
+    }
+
+    /// Compares `[key, name]` pairs by key, returning whether the first is less.
+    #[derive(Debug)]
+    struct ByKey;
+
+    impl caat_rust::Caat for ByKey {
+        fn call(&self, args: &[Value]) -> Value {
+            match args {
+                [Value::List(a), Value::List(b)] => Value::Boolean(compare_values(&a[0], &b[0]) == cmp::Ordering::Less),
+                _ => Value::Failure("expected two pairs".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sort_is_stable() {
+        let pair = |key: i64, name: &str| Value::List(vec![Value::Integer(key), Value::String(name.to_string())].into());
+        let items = Value::List(vec![pair(1, "a"), pair(0, "b"), pair(1, "c"), pair(0, "d"), pair(1, "e")].into());
+        let sorted = Value::List(vec![pair(0, "b"), pair(0, "d"), pair(1, "a"), pair(1, "c"), pair(1, "e")].into());
+        assert_eq!(sort(&[Value::CAATFunction(Arc::new(ByKey)), items.clone()]).unwrap(), sorted);
+        let same = Value::List(vec![pair(1, "a"), pair(1, "b"), pair(1, "c")].into());
+        assert_eq!(sort(&[Value::CAATFunction(Arc::new(ByKey)), same.clone()]).unwrap(), same);
+        assert_eq!(eval("sort fn(a, b) {return sub $a[0] $b[0]} [[1, \"a\"], [0, \"b\"], [1, \"c\"]]").unwrap(),
+            Value::List(vec![pair(0, "b"), pair(1, "a"), pair(1, "c")].into()));
+    }
+
+    #[test]
+    fn test_range_limit() {
+        assert_eq!(range(&[Value::Integer(0), Value::Integer(7), Value::Integer(3)]).unwrap(), list(&[0, 3, 6]));
+        assert_eq!(range(&[Value::Integer(3), Value::Integer(0)]).unwrap(), list(&[]));
+        assert!(range(&[Value::Integer(i64::MIN), Value::Integer(i64::MAX)]).is_err());
+    }
+
+    #[test]
+    fn test_compare_values() {
+        assert_eq!(compare_values(&Value::Integer(2), &Value::Float(1.5)), cmp::Ordering::Greater);
+        assert_eq!(compare_values(&Value::Null, &Value::Integer(0)), cmp::Ordering::Less);
+        assert_eq!(compare_values(&list(&[1, 2]), &list(&[1, 2, 0])), cmp::Ordering::Less);
+    }
+
+    fn eval(script: &str) -> Result<Value, crate::Error> {
+        crate::Interpreter::new().eval_str(script)
+    }
+
+    #[test]
+    fn test_par_map() {
+        let doubled = list(&[2, 4, 6, 8, 10]);
+        assert_eq!(eval("par_map [1, 2, 3, 4, 5] fn(x) {return mul $x 2}").unwrap(), doubled);
+        assert_eq!(eval("par_map \"-j\" 1 [1, 2, 3, 4, 5] fn(x) {return mul $x 2}").unwrap(), doubled);
+        assert_eq!(eval("par_map \"-j\" 3 [1, 2, 3, 4, 5] fn(x) {return mul $x 2}").unwrap(), doubled);
+        match eval("par_map [1, 0] fn(x) {return div 1 $x}").unwrap() {
+            Value::List(results) => assert!(matches!(results[1], Value::Failure(_))),
+            value => panic!("expected a list, got {:?}", value),
+        }
+        assert!(par_map(None, &[list(&[1]), Value::CAATFunction(Arc::new(ConstantTrue))]).is_err());
+    }
+
+    #[test]
+    fn test_par_filter() {
+        let names = "[\"caat\", \"shell\", \"cat\"]";
+        assert_eq!(eval(&format!("par_filter \"-j\" 2 {} fn(name) {{return contains $name \"at\"}}", names)).unwrap(),
+            Value::List(vec![Value::String("caat".to_string()), Value::String("cat".to_string())].into()));
+        match eval("par_filter [1, 0, 2] fn(x) {return any 1 (range (div 2 $x) 3)}").unwrap() {
+            Value::List(results) => {
+                assert_eq!(results.len(), 2);
+                assert!(matches!(&results[0], Value::Failure(msg) if msg.starts_with("par_filter: item 1 failed")));
+                assert_eq!(results[1], Value::Integer(2));
+            }
+            value => panic!("expected a list, got {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_find_jobs() {
+        let j = Value::String("-j".to_string());
+        assert_eq!(find_jobs(&[j.clone(), Value::Integer(3)], "par_map").unwrap(), 3);
+        assert!(find_jobs(&[j.clone(), Value::Integer(0)], "par_map").is_err());
+        assert!(find_jobs(&[j], "par_map").is_err());
+    }
+
+    #[test]
+    fn test_list_utilities() {
+        assert_eq!(sort(&[list(&[3, 1, 2])]).unwrap(), list(&[1, 2, 3]));
+        assert_eq!(range(&[Value::Integer(5), Value::Integer(0), Value::Integer(-2)]).unwrap(), list(&[5, 3, 1]));
+        assert_eq!(take(&[Value::Integer(2), list(&[1, 2, 3])]).unwrap(), list(&[1, 2]));
+        assert_eq!(drop(&[list(&[1, 2, 3]), Value::Integer(2)]).unwrap(), list(&[3]));
+        assert_eq!(slice(&[Value::Integer(-2), list(&[1, 2, 3])]).unwrap(), list(&[2, 3]));
+        assert_eq!(flatten(&[Value::List(vec![list(&[1]), list(&[2, 3])].into())]).unwrap(), list(&[1, 2, 3]));
+        assert_eq!(chunks(&[Value::Integer(2), list(&[1, 2, 3])]).unwrap(), Value::List(vec![list(&[1, 2]), list(&[3])].into()));
+        assert_eq!(zip(&[list(&[1, 2]), list(&[3])]).unwrap(), Value::List(vec![list(&[1, 3])].into()));
+        assert_eq!(position(&[Value::Integer(3), list(&[1, 2, 3])]).unwrap(), Value::Integer(2));
+        assert_eq!(any(&[Value::Integer(4), list(&[1, 2, 3])]).unwrap(), Value::Boolean(false));
+    }
+}
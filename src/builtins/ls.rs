@@ -1,14 +1,74 @@
 use std::fs;
 use std::fs::Metadata;
+use std::path::{Path, PathBuf};
 use caat_rust::Value;
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
 use chrono::prelude::*;
 use chrono::Utc;
+use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use nix::unistd::{Gid, Group, Uid, User};
+
+#[derive(Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Time,
+    Extension,
+}
+
+/// Which fields an entry shows when printed. Every field is in the map either
+/// way.
+#[derive(Default, Clone, Copy)]
+pub struct EntryFormat {
+    pub long: bool,
+    pub human: bool,
+    pub inode: bool,
+    pub markers: bool,
+    /// Show the full path rather than the name, for listings that span more
+    /// than one directory.
+    pub full_path: bool,
+}
+
+/// User and group names by id, looked up through the system's name service
+/// (`getpwuid_r` and `getgrgid_r`), so LDAP and other NSS sources work too.
+/// Each id is looked up once per listing. Ids without a name are shown as
+/// numbers.
+pub struct Names {
+    users: RefCell<HashMap<u32, String>>,
+    groups: RefCell<HashMap<u32, String>>,
+}
+
+impl Names {
+    pub fn load() -> Names {
+        Names {
+            users: RefCell::new(HashMap::new()),
+            groups: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn user(&self, uid: u32) -> String {
+        self.users.borrow_mut().entry(uid).or_insert_with(|| match User::from_uid(Uid::from_raw(uid)) {
+            Ok(Some(user)) => user.name,
+            _ => uid.to_string(),
+        }).clone()
+    }
+
+    fn group(&self, gid: u32) -> String {
+        self.groups.borrow_mut().entry(gid).or_insert_with(|| match Group::from_gid(Gid::from_raw(gid)) {
+            Ok(Some(group)) => group.name,
+            _ => gid.to_string(),
+        }).clone()
+    }
+}
 
 struct Ls {
     show_all: bool,
-    show_long: bool,
+    recursive: bool,
+    sort: SortKey,
+    reverse: bool,
+    format: EntryFormat,
     path: Option<String>,
 }
 
@@ -16,21 +76,46 @@ impl Ls {
     fn new() -> Ls {
         Ls {
             show_all: false,
-            show_long: false,
+            recursive: false,
+            sort: SortKey::Name,
+            reverse: false,
+            format: EntryFormat::default(),
             path: None,
         }
     }
-    
-    fn parse_arguments(&mut self, args: &Vec<Value>) {
+
+    /// Flags can be combined, as in `-la`. An unknown flag is an error rather
+    /// than being taken for a path.
+    fn parse_arguments(&mut self, args: &[Value]) -> Result<(), String> {
         for arg in args {
             match arg {
-                Value::String(s) => {
-                    match s.as_str() {
-                        "-a" => self.show_all = true,
-                        "-l" => self.show_long = true,
-                        _ => self.path = Some(s.to_string()),
+                Value::String(s) if s.starts_with("--sort=") => {
+                    self.sort = match &s["--sort=".len()..] {
+                        "name" => SortKey::Name,
+                        "size" => SortKey::Size,
+                        "time" => SortKey::Time,
+                        "extension" => SortKey::Extension,
+                        key => return Err(format!("ls: unknown sort key {}, expected name, size, time or extension", key)),
+                    }
+                },
+                Value::String(s) if s.starts_with('-') && s.len() > 1 => {
+                    for flag in s.chars().skip(1) {
+                        match flag {
+                            'a' => self.show_all = true,
+                            'l' => self.format.long = true,
+                            'r' => self.reverse = true,
+                            'R' => self.recursive = true,
+                            'S' => self.sort = SortKey::Size,
+                            't' => self.sort = SortKey::Time,
+                            'X' => self.sort = SortKey::Extension,
+                            'h' => self.format.human = true,
+                            'i' => self.format.inode = true,
+                            'F' => self.format.markers = true,
+                            _ => return Err(format!("ls: unknown flag -{}", flag)),
+                        }
                     }
                 },
+                Value::String(s) => self.path = Some(s.to_string()),
                 Value::Map(map, _) => {
                     if let Some(s) = map.get("type") {
                         if let Value::String(s) = s {
@@ -47,61 +132,86 @@ impl Ls {
                 _ => {}
             }
         }
+        Ok(())
     }
-    
-    fn output(&self) -> Result<Value, String> {
-        let paths = if let Some(path) = &self.path {
-            fs::read_dir(path).map_err(|e| e.to_string())?
-        } else {
-            fs::read_dir(".").map_err(|e| e.to_string())?
+
+    /// Orders entries by the sort key. Size and time put the largest and
+    /// newest first, like `ls -S` and `ls -t`.
+    fn compare(&self, a: &(PathBuf, Metadata), b: &(PathBuf, Metadata)) -> Ordering {
+        let by_name = a.0.file_name().cmp(&b.0.file_name());
+        let ordering = match self.sort {
+            SortKey::Name => by_name,
+            SortKey::Size => b.1.len().cmp(&a.1.len()).then(by_name),
+            SortKey::Time => b.1.modified().ok().cmp(&a.1.modified().ok()).then(by_name),
+            SortKey::Extension => a.0.extension().cmp(&b.0.extension()).then(by_name),
         };
-        let mut result = Vec::new();
-        for path in paths {
+        if self.reverse {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    fn list(&self, dir: &Path, names: &Names, output: &mut Vec<Value>) -> Result<(), String> {
+        let mut entries = Vec::new();
+        for path in fs::read_dir(dir).map_err(|e| e.to_string())? {
             let path = path.map_err(|e| e.to_string())?.path();
-            let metadata = path.metadata().map_err(|e| e.to_string())?;
-            let modified_time = metadata.modified().map_err(|e| e.to_string())?;
-            let modified_time = DateTime::<Utc>::from(modified_time);
-            let modified_time = modified_time.format("%Y-%m-%d %H:%M").to_string();
-            let size = metadata.len();
-            let metadata = Self::metadata_string(metadata);
             let name = path.file_name().ok_or("unable to get file_name".to_string())?.to_str().ok_or("unable to get str".to_string())?;
             if name.starts_with(".") && !self.show_all {
                 continue;
             }
-            let metadata = Value::String(metadata);
-            let size = Value::Integer(size as i64);
-            let modified_time = Value::String(modified_time);
-            let name = Value::String(name.to_string());
-
-
-            let mut obj_hash = HashMap::new();
-            obj_hash.insert("type".to_string(), Value::String("dir_entry".to_string()));
-            obj_hash.insert("metadata".to_string(), metadata);
-            obj_hash.insert("size".to_string(), size);
-            obj_hash.insert("modified_time".to_string(), modified_time);
-            obj_hash.insert("name".to_string(), name);
-            obj_hash.insert("full_path".to_string(), Value::String(path.to_str().ok_or(String::from("bad path"))?.to_string()));
-
-            let format = if self.show_long {
-                Some(String::from("{metadata} {size} {modified_time} {name}"))
-            } else {
-                Some(String::from("{name}"))
-            };
-            
-            
-            result.push(Value::Map(obj_hash, format));
+            let metadata = path.symlink_metadata().map_err(|e| e.to_string())?;
+            entries.push((path, metadata));
+        }
+        entries.sort_by(|a, b| self.compare(a, b));
+        for (path, metadata) in &entries {
+            output.push(dir_entry(path, metadata, names, &self.format)?);
+        }
+        if self.recursive {
+            // Like `ls -R`, a directory that can't be read is reported and
+            // the rest of the listing goes on.
+            for (path, metadata) in &entries {
+                if metadata.is_dir() {
+                    if let Err(e) = self.list(path, names, output) {
+                        eprintln!("ls: {}: {}", path.display(), e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn output(&mut self) -> Result<Value, String> {
+        let path = PathBuf::from(self.path.as_deref().unwrap_or("."));
+        let names = Names::load();
+        let metadata = path.symlink_metadata().map_err(|e| e.to_string())?;
+        let mut result = Vec::new();
+        if metadata.is_dir() || (metadata.file_type().is_symlink() && path.is_dir()) {
+            self.format.full_path = self.recursive;
+            self.list(&path, &names, &mut result)?;
+        } else {
+            result.push(dir_entry(&path, &metadata, &names, &self.format)?);
         }
         return Ok(result.into())
     }
-    
-    
 
-    fn metadata_string(metadata: Metadata) -> String {
+
+
+    fn metadata_string(metadata: &Metadata) -> String {
         let mut output = String::new();
-        if metadata.is_dir() {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
             output.push('d');
-        } else if metadata.is_symlink() {
+        } else if file_type.is_symlink() {
             output.push('l');
+        } else if file_type.is_fifo() {
+            output.push('p');
+        } else if file_type.is_socket() {
+            output.push('s');
+        } else if file_type.is_block_device() {
+            output.push('b');
+        } else if file_type.is_char_device() {
+            output.push('c');
         } else {
             output.push('-');
         }
@@ -155,10 +265,207 @@ impl Ls {
     }
 }
 
+/// A size like `ls -h` shows it: bytes under 1K, then one decimal place below
+/// 10 of a unit and whole units above.
+pub fn human_size(size: u64) -> String {
+    if size < 1024 {
+        return size.to_string();
+    }
+    let mut value = size as f64;
+    let mut unit = 'B';
+    for next in ['K', 'M', 'G', 'T', 'P', 'E'] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    if value < 10.0 {
+        format!("{:.1}{}", value, unit)
+    } else {
+        format!("{:.0}{}", value, unit)
+    }
+}
+
+/// The kind of file and the marker `ls -F` puts after its name.
+fn file_kind(metadata: &Metadata) -> (&'static str, &'static str) {
+    let file_type = metadata.file_type();
+    if file_type.is_dir() {
+        ("dir", "/")
+    } else if file_type.is_symlink() {
+        ("symlink", "@")
+    } else if file_type.is_fifo() {
+        ("fifo", "|")
+    } else if file_type.is_socket() {
+        ("socket", "=")
+    } else if file_type.is_block_device() {
+        ("block_device", "")
+    } else if file_type.is_char_device() {
+        ("char_device", "")
+    } else if metadata.permissions().mode() & 0o111 != 0 {
+        ("file", "*")
+    } else {
+        ("file", "")
+    }
+}
+
+/// Builds the `dir_entry` map that `ls` and `find` return for a path. The
+/// metadata should not follow symbolic links, unless the caller means to.
+pub fn dir_entry(path: &Path, metadata: &Metadata, names: &Names, format: &EntryFormat) -> Result<Value, String> {
+    let modified_time = metadata.modified().map_err(|e| e.to_string())?;
+    let modified_time = DateTime::<Utc>::from(modified_time);
+    let modified_time = modified_time.format("%Y-%m-%d %H:%M").to_string();
+    let full_path = path.to_str().ok_or(String::from("bad path"))?;
+    let name = match path.file_name() {
+        Some(name) => name.to_str().ok_or("unable to get str".to_string())?,
+        None => full_path,
+    };
+    let (file_type, marker) = file_kind(metadata);
+    let link_target = if metadata.file_type().is_symlink() {
+        let target = fs::read_link(path).map_err(|e| e.to_string())?;
+        Value::String(target.to_string_lossy().to_string())
+    } else {
+        Value::Null
+    };
+    let extension = path.extension().map_or(String::new(), |extension| extension.to_string_lossy().to_string());
 
+    let mut obj_hash = HashMap::new();
+    obj_hash.insert("type".to_string(), Value::String("dir_entry".to_string()));
+    obj_hash.insert("metadata".to_string(), Value::String(Ls::metadata_string(metadata)));
+    obj_hash.insert("size".to_string(), Value::Integer(metadata.len() as i64));
+    obj_hash.insert("human_size".to_string(), Value::String(human_size(metadata.len())));
+    obj_hash.insert("modified_time".to_string(), Value::String(modified_time));
+    obj_hash.insert("name".to_string(), Value::String(name.to_string()));
+    obj_hash.insert("full_path".to_string(), Value::String(full_path.to_string()));
+    obj_hash.insert("extension".to_string(), Value::String(extension));
+    obj_hash.insert("file_type".to_string(), Value::String(file_type.to_string()));
+    obj_hash.insert("marker".to_string(), Value::String(marker.to_string()));
+    obj_hash.insert("owner".to_string(), Value::String(names.user(metadata.uid())));
+    obj_hash.insert("group".to_string(), Value::String(names.group(metadata.gid())));
+    obj_hash.insert("uid".to_string(), Value::Integer(metadata.uid() as i64));
+    obj_hash.insert("gid".to_string(), Value::Integer(metadata.gid() as i64));
+    obj_hash.insert("inode".to_string(), Value::Integer(metadata.ino() as i64));
+    obj_hash.insert("links".to_string(), Value::Integer(metadata.nlink() as i64));
+    obj_hash.insert("link_target".to_string(), link_target.clone());
 
-pub fn ls(args: &Vec<Value>) -> Result<Value,String> {
+    let mut shown = if format.full_path { String::from("{full_path}") } else { String::from("{name}") };
+    if format.markers {
+        shown.push_str("{marker}");
+    }
+    let mut template = if format.long {
+        let size = if format.human { "{human_size}" } else { "{size}" };
+        let mut template = format!("{{metadata}} {{links}} {{owner}} {{group}} {} {{modified_time}} {}", size, shown);
+        if link_target != Value::Null {
+            template.push_str(" -> {link_target}");
+        }
+        template
+    } else {
+        shown
+    };
+    if format.inode {
+        template.insert_str(0, "{inode} ");
+    }
+    Ok(Value::Map(obj_hash, Some(template)))
+}
+
+pub fn ls(args: &[Value]) -> Result<Value,String> {
     let mut ls = Ls::new();
-    ls.parse_arguments(args);
+    ls.parse_arguments(args)?;
     return ls.output();
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn field(entry: &Value, key: &str) -> Value {
+        match entry {
+            Value::Map(map, _) => map.get(key).cloned().unwrap_or(Value::Null),
+            _ => panic!("expected a dir_entry"),
+        }
+    }
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512");
+        assert_eq!(human_size(1536), "1.5K");
+        assert_eq!(human_size(20 * 1024 * 1024), "20M");
+    }
+
+    #[test]
+    fn test_sort_and_links() {
+        let dir = std::env::temp_dir().join(format!("caat_ls_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), "12345").unwrap();
+        fs::write(dir.join("a.rs"), "1").unwrap();
+        fs::write(dir.join("sub/c.txt"), "").unwrap();
+        std::os::unix::fs::symlink("b.txt", dir.join("link")).unwrap();
+        let path = Value::String(dir.to_string_lossy().to_string());
+        let names = |args: &[&str]| -> Vec<Value> {
+            let mut args: Vec<Value> = args.iter().map(|arg| Value::String(arg.to_string())).collect();
+            args.push(path.clone());
+            match ls(&args).unwrap() {
+                Value::List(list) => list.iter().map(|entry| field(entry, "name")).collect(),
+                _ => panic!("expected a list"),
+            }
+        };
+        let string = |s: &str| Value::String(s.to_string());
+        assert_eq!(names(&[]), vec![string("a.rs"), string("b.txt"), string("link"), string("sub")]);
+        let position = |names: &[Value], name: &str| names.iter().position(|n| *n == string(name)).unwrap();
+        let by_size = names(&["-S"]);
+        assert!(position(&by_size, "b.txt") < position(&by_size, "a.rs"));
+        let by_size = names(&["-Sr"]);
+        assert!(position(&by_size, "a.rs") < position(&by_size, "b.txt"));
+        assert_eq!(names(&["-X"])[0], string("link"));
+        assert_eq!(names(&["-R"]).len(), 5);
+        assert!(ls(&[string("-z")]).is_err());
+
+        let entries = match ls(&[path.clone()]).unwrap() {
+            Value::List(list) => list.to_vec(),
+            _ => panic!("expected a list"),
+        };
+        let link = entries.iter().find(|entry| field(entry, "name") == string("link")).unwrap();
+        assert_eq!(field(link, "file_type"), string("symlink"));
+        assert_eq!(field(link, "link_target"), string("b.txt"));
+        assert!(matches!(field(link, "metadata"), Value::String(s) if s.starts_with('l')));
+        assert_eq!(field(&entries[0], "link_target"), Value::Null);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recursive_skips_unreadable() {
+        let dir = std::env::temp_dir().join(format!("caat_ls_unreadable_{}", std::process::id()));
+        fs::create_dir_all(dir.join("locked")).unwrap();
+        fs::write(dir.join("locked/hidden.txt"), "").unwrap();
+        fs::write(dir.join("z.txt"), "").unwrap();
+        fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+        // Root reads the directory anyway, so there is nothing to skip.
+        if fs::read_dir(dir.join("locked")).is_ok() {
+            fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+            fs::remove_dir_all(&dir).unwrap();
+            eprintln!("skipping test_recursive_skips_unreadable: the directory is still readable");
+            return;
+        }
+        let result = ls(&[Value::String("-R".to_string()), Value::String(dir.to_string_lossy().to_string())]);
+        fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        let names: Vec<Value> = match result.unwrap() {
+            Value::List(list) => list.iter().map(|entry| field(entry, "name")).collect(),
+            _ => panic!("expected a list"),
+        };
+        assert!(names.contains(&Value::String("locked".to_string())));
+        assert!(names.contains(&Value::String("z.txt".to_string())));
+        assert!(!names.contains(&Value::String("hidden.txt".to_string())));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_names() {
+        let names = Names::load();
+        let user = User::from_uid(Uid::from_raw(0)).unwrap().map_or(String::from("0"), |user| user.name);
+        let group = Group::from_gid(Gid::from_raw(0)).unwrap().map_or(String::from("0"), |group| group.name);
+        assert_eq!(names.user(0), user);
+        assert_eq!(names.group(0), group);
+        assert_eq!(names.user(u32::MAX - 1), (u32::MAX - 1).to_string());
+    }
+}
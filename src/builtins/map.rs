@@ -1,44 +1,214 @@
 use caat_rust::Value;
+use std::collections::HashMap;
 
 
+/// Splits a map from the other arguments. The map is the last argument, as a
+/// pipeline passes it, or the first.
+fn map_args<'a>(command: &str, args: &'a [Value]) -> Result<(&'a HashMap<String, Value>, &'a Option<String>, &'a [Value]), String> {
+    match (args.first(), args.last()) {
+        (_, Some(Value::Map(map, format))) => Ok((map, format, &args[..args.len() - 1])),
+        (Some(Value::Map(map, format)), _) => Ok((map, format, &args[1..])),
+        _ => Err(format!("{}: expected a map", command)),
+    }
+}
+
+fn key_arg<'a>(command: &str, key: &'a Value) -> Result<&'a str, String> {
+    match key {
+        Value::String(key) => Ok(key),
+        _ => Err(format!("{}: keys must be strings", command)),
+    }
+}
+
+fn sorted_keys(map: &HashMap<String, Value>) -> Vec<&String> {
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort();
+    keys
+}
+
+/// `keys map` returns the keys of a map in sorted order.
+pub fn keys(args: &[Value]) -> Result<Value, String> {
+    let (map, _, _) = map_args("keys", args)?;
+    Ok(Value::List(sorted_keys(map).into_iter().map(|key| Value::String(key.clone())).collect()))
+}
+
+/// `values map` returns the values of a map in the order of their keys.
+pub fn values(args: &[Value]) -> Result<Value, String> {
+    let (map, _, _) = map_args("values", args)?;
+    Ok(Value::List(sorted_keys(map).into_iter().map(|key| map[key].clone()).collect()))
+}
 
-fn find_function<'input>(list: &'input Vec<Value>) -> Result<&'input Value, String> {
-    for value in list {
-        match value {
-            Value::CAATFunction(_) => return Ok(&value),
-            _ => continue,
+/// `entries map` returns `[key, value]` pairs in the order of their keys.
+pub fn entries(args: &[Value]) -> Result<Value, String> {
+    let (map, _, _) = map_args("entries", args)?;
+    let output: Vec<Value> = sorted_keys(map).into_iter()
+        .map(|key| Value::List(vec![Value::String(key.clone()), map[key].clone()].into()))
+        .collect();
+    Ok(Value::List(output.into()))
+}
+
+/// `insert key value map` returns a copy of a map with a key set.
+pub fn insert(args: &[Value]) -> Result<Value, String> {
+    let (map, format, args) = map_args("insert", args)?;
+    let (key, value) = match args {
+        [key, value] => (key_arg("insert", key)?, value),
+        _ => return Err("insert: expected a key, a value and a map".to_string()),
+    };
+    let mut map = map.clone();
+    map.insert(key.to_string(), value.clone());
+    Ok(Value::Map(map, format.clone()))
+}
+
+/// `remove keys... map` returns a copy of a map without some keys.
+pub fn remove(args: &[Value]) -> Result<Value, String> {
+    let (map, format, args) = map_args("remove", args)?;
+    let mut map = map.clone();
+    for key in args {
+        map.remove(key_arg("remove", key)?);
+    }
+    Ok(Value::Map(map, format.clone()))
+}
+
+fn merge_into(target: &mut HashMap<String, Value>, source: &HashMap<String, Value>, deep: bool) {
+    for (key, value) in source {
+        if deep {
+            if let (Some(Value::Map(inner, _)), Value::Map(source, _)) = (target.get_mut(key), value) {
+                merge_into(inner, source, true);
+                continue;
+            }
         }
+        target.insert(key.clone(), value.clone());
     }
-    Err("No function found".to_string())
 }
 
-fn find_list<'input>(list: &'input Vec<Value>) -> Result<&'input Value, String> {
-    for value in list {
-        match value {
-            Value::List(_) => return Ok(&value),
-            _ => continue,
+/// `merge [--deep] maps...` combines maps, with the keys of later maps
+/// replacing those of earlier ones. With `--deep`, maps under the same key
+/// are merged too. The result keeps the format of the first map.
+pub fn merge(args: &[Value]) -> Result<Value, String> {
+    let mut deep = false;
+    let mut output: Option<(HashMap<String, Value>, Option<String>)> = None;
+    for arg in args {
+        let (map, format) = match arg {
+            Value::String(flag) if flag == "--deep" => {
+                deep = true;
+                continue;
+            }
+            Value::Map(map, format) => (map, format),
+            _ => return Err("merge: expected maps".to_string()),
+        };
+        match &mut output {
+            Some((target, _)) => merge_into(target, map, deep),
+            None => output = Some((map.clone(), format.clone())),
         }
     }
-    Err("No list found".to_string())
+    match output {
+        Some((map, format)) => Ok(Value::Map(map, format)),
+        None => Err("merge: expected maps".to_string()),
+    }
 }
 
+/// `has_key key map` returns whether a map has a key.
+pub fn has_key(args: &[Value]) -> Result<Value, String> {
+    let (map, _, args) = map_args("has_key", args)?;
+    match args {
+        [key] => Ok(Value::Boolean(map.contains_key(key_arg("has_key", key)?))),
+        _ => Err("has_key: expected a key and a map".to_string()),
+    }
+}
 
+/// `get key [default] map` returns the value of a key, or the default when
+/// the map doesn't have it.
+pub fn get(args: &[Value]) -> Result<Value, String> {
+    let (map, _, args) = map_args("get", args)?;
+    let (key, default) = match args {
+        [key] => (key_arg("get", key)?, None),
+        [key, default] => (key_arg("get", key)?, Some(default)),
+        _ => return Err("get: expected a key, an optional default and a map".to_string()),
+    };
+    match (map.get(key), default) {
+        (Some(value), _) => Ok(value.clone()),
+        (None, Some(default)) => Ok(default.clone()),
+        (None, None) => Err(format!("get: no key {}", key)),
+    }
+}
 
-pub fn map(args: &Vec<Value>) -> Result<Value,String> {
-    let list = match find_list(&args)? {
-        Value::List(l) => l,
-        _ => return Err("No list found".to_string()),
+/// `map_values function map` calls a function on every value of a map.
+pub fn map_values(args: &[Value]) -> Result<Value, String> {
+    let (map, format, args) = map_args("map_values", args)?;
+    let function = match args {
+        [Value::CAATFunction(function)] => function,
+        _ => return Err("map_values: expected a function and a map".to_string()),
     };
-    let function = match find_function(&args)? {
-        Value::CAATFunction(f) => f,
-        _ => return Err("No function found".to_string()),
+    let mut output = HashMap::new();
+    for (key, value) in map {
+        match function.call(&[value.clone()]) {
+            Value::Failure(msg) => return Err(msg),
+            value => output.insert(key.clone(), value),
+        };
+    }
+    Ok(Value::Map(output, format.clone()))
+}
+
+/// `from_entries list` builds a map from `[key, value]` pairs, the inverse of
+/// `entries`.
+pub fn from_entries(args: &[Value]) -> Result<Value, String> {
+    let list = match args {
+        [Value::List(list)] => list,
+        _ => return Err("from_entries: expected a list of [key, value] pairs".to_string()),
     };
-    let mut output = Vec::new();
-    for value in list.iter() {
-        let result = function.call(&vec![value.clone()]);
-        eprintln!("result: {:#?}", result);
-        output.push(result);
-    }
-   
-    return Ok(Value::List(output.into()));
+    let mut map = HashMap::new();
+    for entry in list.iter() {
+        match entry {
+            Value::List(pair) if pair.len() == 2 => {
+                map.insert(key_arg("from_entries", &pair[0])?.to_string(), pair[1].clone());
+            }
+            _ => return Err("from_entries: expected a list of [key, value] pairs".to_string()),
+        }
+    }
+    Ok(Value::Map(map, None))
+}
+
+/// `set_format template map` changes how a map is displayed. Keys in braces
+/// are replaced by their values, and `--clear` or `()` removes the template.
+pub fn set_format(args: &[Value]) -> Result<Value, String> {
+    let (map, _, args) = map_args("set_format", args)?;
+    match args {
+        [Value::String(flag)] if flag == "--clear" => Ok(Value::Map(map.clone(), None)),
+        [Value::Null] => Ok(Value::Map(map.clone(), None)),
+        [Value::String(template)] => Ok(Value::Map(map.clone(), Some(template.clone()))),
+        _ => Err("set_format: expected a template string or --clear and a map".to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn map(pairs: &[(&str, Value)]) -> Value {
+        Value::Map(pairs.iter().map(|(key, value)| (key.to_string(), value.clone())).collect(), None)
+    }
+
+    #[test]
+    fn test_maps() {
+        let config = map(&[("name", string("caat")), ("server", map(&[("port", Value::Integer(80)), ("host", string("localhost"))]))]);
+        let overrides = map(&[("server", map(&[("port", Value::Integer(8080))]))]);
+        let deep = merge(&[string("--deep"), config.clone(), overrides.clone()]).unwrap();
+        assert_eq!(get(&[string("server"), deep]).unwrap(), map(&[("port", Value::Integer(8080)), ("host", string("localhost"))]));
+        let shallow = merge(&[config.clone(), overrides]).unwrap();
+        assert_eq!(get(&[string("server"), shallow]).unwrap(), map(&[("port", Value::Integer(8080))]));
+        assert_eq!(keys(&[config.clone()]).unwrap(), Value::List(vec![string("name"), string("server")].into()));
+        assert_eq!(from_entries(&[entries(&[config.clone()]).unwrap()]).unwrap(), config);
+        assert_eq!(get(&[string("missing"), Value::Integer(0), config.clone()]).unwrap(), Value::Integer(0));
+        assert!(get(&[string("missing"), config.clone()]).is_err());
+        let removed = remove(&[string("server"), config.clone()]).unwrap();
+        assert_eq!(has_key(&[string("server"), removed]).unwrap(), Value::Boolean(false));
+        match set_format(&[string("{name}"), config]).unwrap() {
+            Value::Map(_, format) => assert_eq!(format, Some("{name}".to_string())),
+            _ => panic!("expected a map"),
+        }
+    }
 }
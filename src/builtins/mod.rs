@@ -1,49 +1,442 @@
 use caat_rust::Value;
 use crate::shell::Shell;
-use std::sync::{Arc, RwLock};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
 
+mod assert;
 mod echo;
+mod help;
 mod cd;
 mod ls;
+mod files;
+mod formats;
 mod background;
+mod channels;
+mod control;
 mod list_utils;
+mod map;
+mod table;
 mod search;
 mod numbers;
 mod strings;
+pub mod plugin;
 
 
+/// What a builtin can reach besides its arguments.
+pub struct Context {
+    /// The shell the command was called from. This is `None` when a command
+    /// runs inside a `{...}` block outside of any job.
+    pub shell: Option<Arc<RwLock<Shell>>>,
+}
+
+/// A command implemented in Rust.
+///
+/// Builtins live in a `BuiltinRegistry` on each `Shell`, so embedders and
+/// plugins can add, replace or remove them at runtime.
+pub trait Builtin: Send + Sync {
+    fn name(&self) -> &str;
+    /// One line describing what the command does.
+    fn help(&self) -> &str;
+    /// Usage line, for example `map list function`.
+    fn signature(&self) -> &str;
+    /// Example calls shown by `help`, one line of script each.
+    fn examples(&self) -> &[String] {
+        &[]
+    }
+    fn call(&self, context: &mut Context, args: &[Value]) -> Result<Value, String>;
+}
+
+/// A builtin backed by a plain function.
+pub struct FnBuiltin {
+    name: String,
+    signature: String,
+    help: String,
+    examples: Vec<String>,
+    function: fn(&mut Context, &[Value]) -> Result<Value, String>,
+}
+
+impl FnBuiltin {
+    pub fn new(name: &str, signature: &str, help: &str, function: fn(&mut Context, &[Value]) -> Result<Value, String>) -> Self {
+        FnBuiltin {
+            name: name.to_string(),
+            signature: signature.to_string(),
+            help: help.to_string(),
+            examples: Vec::new(),
+            function,
+        }
+    }
+    pub fn with_examples(mut self, examples: &[&str]) -> Self {
+        self.examples = examples.iter().map(|example| example.to_string()).collect();
+        self
+    }
+}
+
+impl Builtin for FnBuiltin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn help(&self) -> &str {
+        &self.help
+    }
+    fn signature(&self) -> &str {
+        &self.signature
+    }
+    fn examples(&self) -> &[String] {
+        &self.examples
+    }
+    fn call(&self, context: &mut Context, args: &[Value]) -> Result<Value, String> {
+        (self.function)(context, args)
+    }
+}
+
+#[derive(Clone)]
+pub struct BuiltinRegistry {
+    builtins: HashMap<String, Arc<dyn Builtin>>,
+}
+
+impl BuiltinRegistry {
+    /// Creates a registry with no commands in it.
+    pub fn empty() -> Self {
+        BuiltinRegistry {
+            builtins: HashMap::new(),
+        }
+    }
+    /// Creates a registry holding the shell's own builtins.
+    pub fn new() -> Self {
+        let mut registry = BuiltinRegistry::empty();
+        for builtin in defaults() {
+            registry.register(Arc::new(builtin));
+        }
+        registry
+    }
+    /// Adds a builtin, returning the one it replaced if the name was taken.
+    pub fn register(&mut self, builtin: Arc<dyn Builtin>) -> Option<Arc<dyn Builtin>> {
+        self.builtins.insert(builtin.name().to_string(), builtin)
+    }
+    pub fn remove(&mut self, name: &str) -> Option<Arc<dyn Builtin>> {
+        self.builtins.remove(name)
+    }
+    pub fn get(&self, name: &str) -> Option<Arc<dyn Builtin>> {
+        self.builtins.get(name).cloned()
+    }
+    /// Every registered builtin, sorted by name.
+    pub fn list(&self) -> Vec<Arc<dyn Builtin>> {
+        let mut builtins: Vec<Arc<dyn Builtin>> = self.builtins.values().cloned().collect();
+        builtins.sort_by(|a, b| a.name().cmp(b.name()));
+        builtins
+    }
+}
+
+impl fmt::Debug for BuiltinRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.builtins.keys().collect();
+        names.sort();
+        f.debug_struct("BuiltinRegistry").field("builtins", &names).finish()
+    }
+}
+
+/// The registry used when a command runs without a shell.
+fn default_registry() -> &'static BuiltinRegistry {
+    static REGISTRY: OnceLock<BuiltinRegistry> = OnceLock::new();
+    REGISTRY.get_or_init(BuiltinRegistry::new)
+}
+
+
+pub fn run_builtin(shell: Option<Arc<RwLock<Shell>>>, command_name: &str, args: &[Value]) -> Result<Value,Result<(),String>> {
+    let shell = shell.or_else(crate::shell::current_shell);
+    let builtin = match &shell {
+        Some(shell) => {
+            let borrowed_shell = borrow!(shell);
+            let builtin = borrowed_shell.builtins().get(command_name);
+            builtin
+        }
+        None => default_registry().get(command_name),
+    };
+    match builtin {
+        Some(builtin) => {
+            let mut context = Context { shell };
+            builtin.call(&mut context, args).map_err(|msg| Err(msg))
+        }
+        None => Err(Ok(())),
+    }
+}
+
+fn defaults() -> Vec<FnBuiltin> {
+    vec![
+        FnBuiltin::new("args", "args", "Returns the arguments the script was called with",
+            |_, _| Ok(get_args())),
+        FnBuiltin::new("sleep", "sleep seconds", "Pauses for a number of seconds",
+            |_, args| sleep(args)),
+        FnBuiltin::new("trace", "trace value", "Prints the debug form of a value and returns it",
+            |_, args| Ok(echo::trace(args))),
+        FnBuiltin::new("echo", "echo value", "Returns its argument",
+            |_, args| Ok(echo::echo(args))),
+        FnBuiltin::new("cd", "cd [path]", "Changes the working directory, or goes home without a path",
+            |_, args| cd::cd(args)),
+        FnBuiltin::new("ls", "ls [-alrRStXhiF] [--sort=name|size|time|extension] [path]", "Lists a directory as dir_entry maps, sorted by name unless -S, -t or -X is given",
+            |_, args| ls::ls(args))
+            .with_examples(&["ls \"-la\" \"/tmp\"", "ls \"-lhSr\"", "ls \"-R\" \"--sort=extension\" \"src\"", "ls | filter fn(entry) {return contains $entry[\"name\"] \".png\"}"]),
+        FnBuiltin::new("open", "open path [--format=json|csv|tsv|toml|ini|lines|text]", "Reads a file, decoding it by its extension",
+            |_, args| files::open(args))
+            .with_examples(&["open \"config.toml\"", "open \"data.csv\" | filter fn(row) {return contains $row[\"name\"] \"caat\"}", "open \"notes.txt\" \"--format=lines\""]),
+        FnBuiltin::new("save", "save path value [--format=json|csv|tsv|toml|lines|text]", "Writes a value to a file, encoding it by its extension",
+            |_, args| files::save(args))
+            .with_examples(&["ls | save \"listing.json\""]),
+        FnBuiltin::new("append", "append path value", "Adds a value to the end of a file the way >> does",
+            |_, args| files::append(args)),
+        FnBuiltin::new("from_json", "from_json text", "Parses JSON: null becomes (), objects become maps and whole numbers become integers",
+            |_, args| formats::from_json(args))
+            .with_examples(&["from_json '{\"name\": \"caat\"}'", "open \"x.json\" \"--format=text\" | from_json"]),
+        FnBuiltin::new("to_json", "to_json [--pretty] [--indent=n] value", "Writes a value as JSON: () becomes null, a failure becomes {\"failure\": message} and functions are an error",
+            |_, args| formats::to_json(args))
+            .with_examples(&["ls | to_json \"--pretty\"", "to_json \"--indent=4\" [1, 2]"]),
+        FnBuiltin::new("from_csv", "from_csv [--delimiter=c] [--no-header] [--no-infer] text", "Parses comma separated values into a list of maps keyed by the header row",
+            |_, args| formats::from_csv(args))
+            .with_examples(&["open \"export.csv\" \"--format=text\" | from_csv", "from_csv \"--delimiter=;\" \"--no-header\" $text"]),
+        FnBuiltin::new("to_csv", "to_csv [--delimiter=c] [--no-header] list", "Writes a list of maps or lists as comma separated values",
+            |_, args| formats::to_csv(args))
+            .with_examples(&["ls | to_csv"]),
+        FnBuiltin::new("from_tsv", "from_tsv [--no-header] [--no-infer] text", "Parses tab separated values into a list of maps keyed by the header row",
+            |_, args| formats::from_tsv(args)),
+        FnBuiltin::new("to_tsv", "to_tsv [--no-header] list", "Writes a list of maps or lists as tab separated values",
+            |_, args| formats::to_tsv(args)),
+        FnBuiltin::new("from_toml", "from_toml text", "Parses a TOML document into nested maps, with datetimes as strings",
+            |_, args| formats::from_toml(args))
+            .with_examples(&["open \"config\" | from_toml"]),
+        FnBuiltin::new("to_toml", "to_toml map", "Writes a map as a TOML document, leaving out () entries",
+            |_, args| formats::to_toml(args)),
+        FnBuiltin::new("from_ini", "from_ini text", "Parses an INI file into a map with a map for each section",
+            |_, args| formats::from_ini(args))
+            .with_examples(&["open \"settings.conf\" | from_ini"]),
+        FnBuiltin::new("mkdir", "mkdir [-p] paths...", "Creates directories and returns their dir_entry maps",
+            |_, args| files::mkdir(args)),
+        FnBuiltin::new("rm", "rm [-rf] paths...", "Removes files and directories and returns the removed paths",
+            |_, args| files::rm(args))
+            .with_examples(&["find \".\" \"-name\" \"*.tmp\" | rm"]),
+        FnBuiltin::new("cp", "cp [-r] sources... destination", "Copies files and directories and returns the dir_entry maps of the copies",
+            |_, args| files::cp(args)),
+        FnBuiltin::new("mv", "mv sources... destination", "Moves files and directories and returns their new dir_entry maps",
+            |_, args| files::mv(args)),
+        FnBuiltin::new("touch", "touch paths...", "Creates files or updates their modified time",
+            |_, args| files::touch(args)),
+        FnBuiltin::new("stat", "stat paths...", "Returns the dir_entry maps of paths without following links",
+            |_, args| files::stat(args)),
+        FnBuiltin::new("background", "background command args...", "Runs a command as a job in a forked shell",
+            |context, args| background::background(context.shell.clone(), args)),
+        // A job is a map, so a list argument means the string join.
+        FnBuiltin::new("join", "join job | join list [separator]", "Waits for a job to finish and returns its value, or joins the items of a list into a string",
+            |context, args| if args.iter().any(|arg| matches!(arg, Value::List(_))) {
+                strings::join(args)
+            } else {
+                background::join(context.shell.clone(), args)
+            })
+            .with_examples(&["ls | map fn(entry) {return $entry[\"name\"]} | join \", \""]),
+        FnBuiltin::new("jobs", "jobs", "Lists the jobs that have not been joined",
+            |context, args| background::jobs(context.shell.clone(), args)),
+        FnBuiltin::new("share", "share name [value]", "Copies a variable into the global scope of the shell that spawned this job",
+            |context, args| background::share(context.shell.clone(), args)),
+        FnBuiltin::new("timeout", "timeout seconds command args...", "Runs a command, failing with \"timed out\" if it takes too long and killing the external commands it started",
+            |context, args| control::timeout(context.shell.clone(), args))
+            .with_examples(&["timeout 5 {curl \"https://example.com\"}"]),
+        FnBuiltin::new("retry", "retry attempts [delay] command args...", "Calls a command until it stops returning a failure, doubling the delay between attempts up to a minute",
+            |_, args| control::retry(args))
+            .with_examples(&["retry 3 1 {curl \"https://example.com\"}"]),
+        FnBuiltin::new("channel", "channel", "Creates a channel and returns its [sender, receiver] pair",
+            |context, args| channels::channel(context.shell.clone(), args)),
+        FnBuiltin::new("send", "send sender value", "Sends a value over a channel",
+            |context, args| channels::send(context.shell.clone(), args)),
+        FnBuiltin::new("recv", "recv receiver", "Waits for the next value on a channel",
+            |context, args| channels::recv(context.shell.clone(), args)),
+        FnBuiltin::new("try_recv", "try_recv receiver", "Returns the next value on a channel, or () if none is waiting",
+            |context, args| channels::try_recv(context.shell.clone(), args)),
+        FnBuiltin::new("close", "close sender", "Closes the sending side of a channel",
+            |context, args| channels::close(context.shell.clone(), args)),
+        FnBuiltin::new("select_channel", "select_channel receivers [seconds]", "Waits for a value on any of several channels",
+            |context, args| channels::select_channel(context.shell.clone(), args)),
+        FnBuiltin::new("map", "map list function", "Calls a function on every item of a list",
+            |_, args| list_utils::map(args))
+            .with_examples(&["map [1, 2, 3] fn(x) {return mul $x 2}"]),
+        FnBuiltin::new("fold", "fold list start function", "Combines the items of a list into one value",
+            |_, args| list_utils::fold(args))
+            .with_examples(&["fold [1, 2, 3] 0 {add}"]),
+        FnBuiltin::new("filter", "filter list function", "Keeps the items of a list for which a function returns true",
+            |_, args| list_utils::filter(args))
+            .with_examples(&["filter [1, 2, 3] fn(x) {return contains $x 2}"]),
+        FnBuiltin::new("par_map", "par_map [-j n] list function", "Like map, but calls the function on several items at once",
+            |context, args| list_utils::par_map(context.shell.clone(), args))
+            .with_examples(&["par_map \"-j\" 4 $files fn(file) {return convert $file}"]),
+        FnBuiltin::new("par_filter", "par_filter [-j n] list function", "Like filter, but calls the function on several items at once. Items whose call fails are kept as failures",
+            |context, args| list_utils::par_filter(context.shell.clone(), args)),
+        FnBuiltin::new("concat", "concat lists...", "Joins lists end to end",
+            |_, args| list_utils::concat(args)),
+        FnBuiltin::new("shuf", "shuf list", "Shuffles a list",
+            |_, args| list_utils::shuf(args)),
+        FnBuiltin::new("head", "head list", "Returns the first item of a list",
+            |_, args| list_utils::head(args)),
+        FnBuiltin::new("tail", "tail list", "Returns the last item of a list",
+            |_, args| list_utils::tail(args)),
+        FnBuiltin::new("rest", "rest list", "Returns every item of a list but the first",
+            |_, args| list_utils::rest(args)),
+        FnBuiltin::new("length", "length list|string", "Returns the number of items in a list or characters in a string",
+            |_, args| list_utils::length(args)),
+        FnBuiltin::new("select", "select columns... rows", "Keeps only the given columns of each row",
+            |_, args| table::select(args))
+            .with_examples(&["ls | select \"name\" \"size\""]),
+        FnBuiltin::new("where", "where column test rows", "Keeps the rows whose column equals a value or passes a function",
+            |_, args| table::where_command(args))
+            .with_examples(&["ls | where \"file_type\" \"dir\"", "ls | where \"name\" fn(name) {return contains $name \".png\"}"]),
+        FnBuiltin::new("sort_by", "sort_by [--desc] columns... rows", "Sorts rows by one or more columns",
+            |_, args| table::sort_by(args))
+            .with_examples(&["ls \"-l\" | sort_by \"--desc\" \"size\" | first 5", "jobs | sort_by \"id\""]),
+        FnBuiltin::new("group_by", "group_by column rows", "Returns a map from each value of a column to the rows that have it",
+            |_, args| table::group_by(args))
+            .with_examples(&["ls | group_by \"extension\""]),
+        FnBuiltin::new("uniq", "uniq list", "Removes repeated items, keeping the first of each",
+            |_, args| table::uniq(args)),
+        FnBuiltin::new("uniq_by", "uniq_by columns... rows", "Keeps the first row for each distinct value of the columns",
+            |_, args| table::uniq_by(args)),
+        FnBuiltin::new("first", "first [n] list", "Returns the first item of a list, or a list of the first n",
+            |_, args| table::first(args)),
+        FnBuiltin::new("last", "last [n] list", "Returns the last item of a list, or a list of the last n",
+            |_, args| table::last(args)),
+        FnBuiltin::new("count", "count [function] list", "Counts the items of a list, or those a function returns true for",
+            |_, args| table::count(args))
+            .with_examples(&["ls | count fn(entry) {return contains $entry[\"name\"] \".png\"}"]),
+        FnBuiltin::new("sort", "sort [function] list", "Sorts a list, keeping equal items in order. The function compares two items, returning an integer or whether the first comes first",
+            |_, args| list_utils::sort(args))
+            .with_examples(&["sort [3, 1, 2]", "sort fn(a, b) {return sub $b $a} [3, 1, 2]"]),
+        FnBuiltin::new("reverse", "reverse list|string", "Reverses a list or the characters of a string",
+            |_, args| list_utils::reverse(args)),
+        FnBuiltin::new("zip", "zip lists...", "Pairs up the items of lists, stopping at the shortest",
+            |_, args| list_utils::zip(args)),
+        FnBuiltin::new("enumerate", "enumerate list", "Pairs each item of a list with its index",
+            |_, args| list_utils::enumerate(args)),
+        FnBuiltin::new("range", "range [start] end [step]", "Returns the integers from start up to but not including end",
+            |_, args| list_utils::range(args))
+            .with_examples(&["range 5", "range 10 0 -2 | take 3"]),
+        FnBuiltin::new("flatten", "flatten [depth] list", "Splices nested lists into their parent, one level deep by default",
+            |_, args| list_utils::flatten(args)),
+        FnBuiltin::new("take", "take n list", "Returns the first n items of a list",
+            |_, args| list_utils::take(args)),
+        FnBuiltin::new("drop", "drop n list", "Returns every item of a list but the first n",
+            |_, args| list_utils::drop(args)),
+        FnBuiltin::new("slice", "slice start [end] list", "Returns the items from start up to but not including end, counting negative indices from the end",
+            |_, args| list_utils::slice(args)),
+        FnBuiltin::new("any", "any test list", "Returns whether any item equals a value or passes a function",
+            |_, args| list_utils::any(args)),
+        FnBuiltin::new("all", "all test list", "Returns whether every item equals a value or passes a function",
+            |_, args| list_utils::all(args)),
+        FnBuiltin::new("position", "position test list", "Returns the index of the first item that equals a value or passes a function, or ()",
+            |_, args| list_utils::position(args)),
+        FnBuiltin::new("chunks", "chunks n list", "Splits a list into lists of n items",
+            |_, args| list_utils::chunks(args)),
+        FnBuiltin::new("keys", "keys map", "Returns the keys of a map in sorted order",
+            |_, args| map::keys(args)),
+        FnBuiltin::new("values", "values map", "Returns the values of a map in the order of their keys",
+            |_, args| map::values(args)),
+        FnBuiltin::new("entries", "entries map", "Returns the [key, value] pairs of a map in the order of their keys",
+            |_, args| map::entries(args)),
+        FnBuiltin::new("insert", "insert key value map", "Returns a copy of a map with a key set",
+            |_, args| map::insert(args))
+            .with_examples(&["$entry | insert \"seen\" true"]),
+        FnBuiltin::new("remove", "remove keys... map", "Returns a copy of a map without some keys",
+            |_, args| map::remove(args)),
+        FnBuiltin::new("merge", "merge [--deep] maps...", "Combines maps, later keys replacing earlier ones. With --deep, nested maps are merged too",
+            |_, args| map::merge(args))
+            .with_examples(&["merge \"--deep\" $defaults $config"]),
+        FnBuiltin::new("has_key", "has_key key map", "Returns whether a map has a key",
+            |_, args| map::has_key(args)),
+        FnBuiltin::new("get", "get key [default] map", "Returns the value of a key, or the default when the map doesn't have it",
+            |_, args| map::get(args))
+            .with_examples(&["$config | get \"port\" 8080"]),
+        FnBuiltin::new("map_values", "map_values function map", "Calls a function on every value of a map",
+            |_, args| map::map_values(args)),
+        FnBuiltin::new("from_entries", "from_entries list", "Builds a map from [key, value] pairs",
+            |_, args| map::from_entries(args)),
+        FnBuiltin::new("set_format", "set_format template|--clear map", "Changes how a map is displayed, replacing {key} with its value, or removes the template with --clear",
+            |_, args| map::set_format(args))
+            .with_examples(&["ls | map fn(entry) {return set_format \"{name} {size}\" $entry}"]),
+        FnBuiltin::new("find", "find [paths...] [tests...] [function]", "Walks directories and returns dir_entry maps for the paths that pass the tests: -name, -iname, -regex, -type, -size, -mtime, -not, -or, -mindepth, -maxdepth and -L",
+            |_, args| search::find(args))
+            .with_examples(&["find \".\" \"-name\" \"*.png\" \"-or\" \"-name\" \"*.jpg\"", "find \"-type\" \"f\" \"-size\" \"+1M\" \"-mtime\" \"-7\"", "find \"src\" fn(entry) {return contains $entry[\"name\"] \"test\"}"]),
+        FnBuiltin::new("add", "add numbers...", "Adds numbers",
+            |_, args| numbers::add(args)),
+        FnBuiltin::new("sub", "sub numbers...", "Subtracts numbers",
+            |_, args| numbers::sub(args)),
+        FnBuiltin::new("mul", "mul numbers...", "Multiplies numbers",
+            |_, args| numbers::mult(args)),
+        FnBuiltin::new("div", "div number numbers...", "Divides the first number by the rest",
+            |_, args| numbers::div(args)),
+        FnBuiltin::new("contains", "contains string substring", "Checks whether a string contains another",
+            |_, args| strings::contains(args)),
+        FnBuiltin::new("split", "split string [separator]", "Splits a string on a separator, a space by default",
+            |_, args| strings::split(args))
+            .with_examples(&["split \"a,b,c\" \",\""]),
+        FnBuiltin::new("trim", "trim string", "Removes whitespace from both ends of a string",
+            |_, args| strings::trim(args)),
+        FnBuiltin::new("upper", "upper string", "Converts a string to upper case",
+            |_, args| strings::upper(args)),
+        FnBuiltin::new("lower", "lower string", "Converts a string to lower case",
+            |_, args| strings::lower(args)),
+        FnBuiltin::new("replace", "replace from to string", "Replaces every occurrence of from in a string",
+            |_, args| strings::replace(args))
+            .with_examples(&["replace \"-\" \"_\" \"a-b-c\"", "echo \"a-b-c\" | replace \"-\" \"_\""]),
+        FnBuiltin::new("starts_with", "starts_with prefix string", "Checks whether a string starts with a prefix",
+            |_, args| strings::starts_with(args)),
+        FnBuiltin::new("ends_with", "ends_with suffix string", "Checks whether a string ends with a suffix",
+            |_, args| strings::ends_with(args)),
+        FnBuiltin::new("pad_left", "pad_left width [fill] string", "Pads the start of a string to a width in characters",
+            |_, args| strings::pad_left(args))
+            .with_examples(&["pad_left 3 \"0\" \"7\""]),
+        FnBuiltin::new("pad_right", "pad_right width [fill] string", "Pads the end of a string to a width in characters",
+            |_, args| strings::pad_right(args)),
+        FnBuiltin::new("substring", "substring start [end] string", "Returns the characters from start up to but not including end, counting negative indices from the end",
+            |_, args| strings::substring(args)),
+        FnBuiltin::new("char_at", "char_at index string", "Returns the character at an index, counting negative indices from the end",
+            |_, args| strings::char_at(args)),
+        FnBuiltin::new("lines", "lines string", "Splits a string into lines",
+            |_, args| strings::lines(args)),
+        FnBuiltin::new("words", "words string", "Splits a string on runs of whitespace",
+            |_, args| strings::words(args)),
+        FnBuiltin::new("repeat", "repeat n string", "Repeats a string n times",
+            |_, args| strings::repeat(args)),
+        FnBuiltin::new("assert", "assert condition [message]", "Fails unless the condition is true",
+            |_, args| assert::assert(args)),
+        FnBuiltin::new("assert_eq", "assert_eq left right [message]", "Fails unless two values are equal, listing where they differ",
+            |_, args| assert::assert_eq(args)),
+        FnBuiltin::new("help", "help [name]", "Shows how to use a command, or lists every command without a name",
+            |context, args| help::help(context.shell.clone(), args))
+            .with_examples(&["help \"fold\""]),
+        FnBuiltin::new("describe", "describe name", "Returns the usage, help, arguments and examples of a command as a map",
+            |context, args| help::describe(context.shell.clone(), args))
+            .with_examples(&["describe \"fold\""]),
+        FnBuiltin::new("builtins", "builtins", "Lists every builtin command",
+            |context, _| Ok(list_builtins(context))),
+        FnBuiltin::new("plugin", "plugin list | add path [name] | remove name | reload", "Manages commands loaded from the plugin directory",
+            |context, args| plugin::plugin(context.shell.clone(), args)),
+    ]
+}
 
-pub fn run_builtin(shell: Option<Arc<RwLock<Shell>>>, command_name: &str, args: &Vec<Value>) -> Result<Value,Result<(),String>> {
-    let output = match command_name {
-        "args" => get_args(),
-        "sleep" => sleep(args).map_err(|msg| Err(msg))?,
-        "trace" => echo::trace(args),
-        "echo" => echo::echo(args), 
-        "cd" => cd::cd(args).map_err(|msg| Err(msg))?,
-        "ls" => ls::ls(args).map_err(|msg| Err(msg))?,
-        //"if" => conditionals::if_command(args).map_err(|msg| Err(msg))?,
-        "background" => background::background(shell, args).map_err(|msg| Err(msg))?,
-        "join" => background::join(shell, args).map_err(|msg| Err(msg))?,
-        "jobs" => background::jobs(shell, args).map_err(|msg| Err(msg))?,
-        "map" => list_utils::map(args).map_err(|msg| Err(msg))?,
-        "fold" => list_utils::fold(args).map_err(|msg| Err(msg))?,
-        "filter" => list_utils::filter(args).map_err(|msg| Err(msg))?,
-        "concat" => list_utils::concat(args).map_err(|msg| Err(msg))?,
-        "shuf" => list_utils::shuf(args).map_err(|msg| Err(msg))?,
-        "head" => list_utils::head(args).map_err(|msg| Err(msg))?,
-        "tail" => list_utils::tail(args).map_err(|msg| Err(msg))?,
-        "rest" => list_utils::rest(args).map_err(|msg| Err(msg))?,
-        "length" => list_utils::length(args).map_err(|msg| Err(msg))?,
-        "find" => search::find(args).map_err(|msg| Err(msg))?,
-        "add" => numbers::add(args).map_err(|msg| Err(msg))?,
-        "sub" => numbers::sub(args).map_err(|msg| Err(msg))?,
-        "mul" => numbers::mult(args).map_err(|msg| Err(msg))?,
-        "div" => numbers::div(args).map_err(|msg| Err(msg))?,
-        "contains" => strings::contains(args).map_err(|msg| Err(msg))?,
-        "split" => strings::split(args).map_err(|msg| Err(msg))?,
-        _ => return Err(Ok(())),
+fn list_builtins(context: &mut Context) -> Value {
+    let builtins = match &context.shell {
+        Some(shell) => {
+            let borrowed_shell = borrow!(shell);
+            let builtins = borrowed_shell.builtins().list();
+            builtins
+        }
+        None => default_registry().list(),
     };
-    return Ok(output);
+    let mut output = Vec::new();
+    for builtin in builtins {
+        let mut map = HashMap::new();
+        map.insert("type".to_string(), Value::String("builtin".to_string()));
+        map.insert("name".to_string(), Value::String(builtin.name().to_string()));
+        map.insert("signature".to_string(), Value::String(builtin.signature().to_string()));
+        map.insert("help".to_string(), Value::String(builtin.help().to_string()));
+        output.push(Value::Map(map, Some(String::from("{signature} {help}"))));
+    }
+    Value::List(output.into())
 }
 
 fn get_args() -> Value {
@@ -56,7 +449,7 @@ fn get_args() -> Value {
     return Value::List(output.into());
 }
 
-fn sleep(args: &Vec<Value>) -> Result<Value, String> {
+fn sleep(args: &[Value]) -> Result<Value, String> {
     let duration = match args.get(0) {
         Some(Value::Integer(n)) => *n as u64,
         _ => return Err("Invalid argument to sleep".to_string()),
@@ -64,3 +457,26 @@ fn sleep(args: &Vec<Value>) -> Result<Value, String> {
     std::thread::sleep(std::time::Duration::from_secs(duration));
     return Ok(Value::Null);
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_names_are_unique() {
+        let mut names: Vec<String> = defaults().iter().map(|builtin| builtin.name().to_string()).collect();
+        names.sort();
+        let count = names.len();
+        names.dedup();
+        assert_eq!(names.len(), count, "register replaces builtins with the same name");
+    }
+
+    #[test]
+    fn test_join_dispatches_on_argument() {
+        let interpreter = crate::Interpreter::new();
+        assert_eq!(interpreter.eval_str("join \", \" [\"a\", \"b\"]").unwrap(), Value::String("a, b".to_string()));
+        let value = interpreter.eval_str("job = background {echo \"done\"}\njoin $job").unwrap();
+        assert_eq!(value, Value::String("done".to_string()));
+    }
+}
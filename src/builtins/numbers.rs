@@ -6,7 +6,7 @@ use caat_rust::Value;
 
 
 
-pub fn add(args: &Vec<Value>) -> Result<Value,String> {
+pub fn add(args: &[Value]) -> Result<Value,String> {
     let mut sum: Either<i64,f64> = Either::Left(0);
     for arg in args.iter() {
         match arg {
@@ -35,7 +35,7 @@ pub fn add(args: &Vec<Value>) -> Result<Value,String> {
 }
 
 
-pub fn sub(args: &Vec<Value>) -> Result<Value,String> {
+pub fn sub(args: &[Value]) -> Result<Value,String> {
     let mut sum: Either<i64,f64> = Either::Left(0);
     for arg in args.iter() {
         match arg {
@@ -65,7 +65,7 @@ pub fn sub(args: &Vec<Value>) -> Result<Value,String> {
 
 
 
-pub fn mult(args: &Vec<Value>) -> Result<Value,String> {
+pub fn mult(args: &[Value]) -> Result<Value,String> {
     let mut sum: Either<i64,f64> = Either::Left(1);
     for arg in args.iter() {
         match arg {
@@ -94,7 +94,7 @@ pub fn mult(args: &Vec<Value>) -> Result<Value,String> {
 }
 
 
-pub fn div(args: &Vec<Value>) -> Result<Value,String> {
+pub fn div(args: &[Value]) -> Result<Value,String> {
     let mut args = args.iter();
     let mut sum: Either<i64,f64> = match args.next() {
         Some(Value::Integer(i)) => Either::Left(*i),
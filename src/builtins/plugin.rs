@@ -0,0 +1,286 @@
+use caat_rust::{Caat, Value};
+use crate::shell::Shell;
+use std::collections::HashMap;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Arc, RwLock};
+use std::time::Duration;
+use super::{Builtin, Context};
+
+
+/// How long a plugin gets to answer `--help` before it is killed.
+const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// An executable from the plugin directory, called through the CAAT protocol.
+///
+/// Its usage and help text come from running it once with `--help` when it is
+/// registered: the first line is used as the signature and the rest as help.
+pub struct PluginBuiltin {
+    name: String,
+    path: PathBuf,
+    signature: String,
+    help: String,
+}
+
+impl PluginBuiltin {
+    pub fn new(name: &str, path: PathBuf) -> Self {
+        let text = read_help(&path, HELP_TIMEOUT).unwrap_or_default();
+        let mut lines = text.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+        let (signature, help) = match lines.next() {
+            Some(signature) => (signature.to_string(), lines.collect::<Vec<&str>>().join(" ")),
+            None => (name.to_string(), format!("Plugin at {}", path.display())),
+        };
+        PluginBuiltin {
+            name: name.to_string(),
+            path,
+            signature,
+            help,
+        }
+    }
+}
+
+/// Runs `path --help`, returning what it printed if it succeeded in time.
+fn read_help(path: &Path, timeout: Duration) -> Option<String> {
+    let mut child = Command::new(path)
+        .arg("--help")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let mut stdout = child.stdout.take()?;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut text = String::new();
+        let _ = stdout.read_to_string(&mut text);
+        let _ = sender.send(text);
+    });
+    match receiver.recv_timeout(timeout) {
+        Ok(text) => match child.wait() {
+            Ok(status) if status.success() => Some(text),
+            _ => None,
+        },
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            None
+        }
+    }
+}
+
+impl Builtin for PluginBuiltin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn help(&self) -> &str {
+        &self.help
+    }
+    fn signature(&self) -> &str {
+        &self.signature
+    }
+    fn call(&self, _context: &mut Context, args: &[Value]) -> Result<Value, String> {
+        let path = self.path.to_str().ok_or(format!("{}: bad plugin path", self.name))?;
+        match caat_rust::ForeignFunction::new(path).call(args) {
+            Value::Failure(msg) => Err(msg),
+            value => Ok(value),
+        }
+    }
+}
+
+/// `$CAAT_PLUGIN_DIR`, or `~/.config/caat_shell/plugins` when it is not set.
+pub fn plugin_dir() -> Option<PathBuf> {
+    if let Ok(dir) = std::env::var("CAAT_PLUGIN_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    std::env::var("HOME").ok().map(|home| Path::new(&home).join(".config/caat_shell/plugins"))
+}
+
+fn is_executable(path: &Path) -> bool {
+    match path.metadata() {
+        Ok(metadata) => metadata.is_file() && metadata.permissions().mode() & 0o111 != 0,
+        Err(_) => false,
+    }
+}
+
+/// Registers `path` as the command `name`, or as its file stem without a name.
+pub fn add_plugin(shell: &mut Shell, path: PathBuf, name: Option<String>) -> Result<String, String> {
+    if !is_executable(&path) {
+        return Err(format!("plugin: {} is not an executable file", path.display()));
+    }
+    let name = match name {
+        Some(name) => name,
+        None => path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .ok_or(format!("plugin: bad file name {}", path.display()))?
+            .to_string(),
+    };
+    shell.builtins_mut().register(Arc::new(PluginBuiltin::new(&name, path.clone())));
+    shell.plugins_mut().insert(name.clone(), path);
+    Ok(name)
+}
+
+/// Unregisters a plugin, putting back the builtin it replaced if there was one.
+pub fn remove_plugin(shell: &mut Shell, name: &str) -> Result<(), String> {
+    if shell.plugins_mut().remove(name).is_none() {
+        return Err(format!("plugin: no plugin named {}", name));
+    }
+    shell.builtins_mut().remove(name);
+    if let Some(builtin) = super::default_registry().get(name) {
+        shell.builtins_mut().register(builtin);
+    }
+    Ok(())
+}
+
+/// Registers every executable in the plugin directory. A missing directory is
+/// not an error, and a plugin that can't be added is reported on stderr and
+/// skipped so the others still load.
+pub fn load_plugins(shell: &mut Shell) -> Result<(), String> {
+    match plugin_dir() {
+        Some(dir) if dir.is_dir() => load_plugins_from(shell, &dir),
+        _ => Ok(()),
+    }
+}
+
+fn load_plugins_from(shell: &mut Shell, dir: &Path) -> Result<(), String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("plugin: {}", e))?;
+    for entry in entries {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            Err(e) => {
+                eprintln!("plugin: {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        if is_executable(&path) {
+            if let Err(msg) = add_plugin(shell, path, None) {
+                eprintln!("{}", msg);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The commands `load_plugins` would register, found without running any of
+/// the plugins.
+pub fn plugin_names() -> Vec<String> {
+    match plugin_dir() {
+        Some(dir) => plugin_names_in(&dir),
+        None => Vec::new(),
+    }
+}
+
+fn plugin_names_in(dir: &Path) -> Vec<String> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    entries.flatten()
+        .map(|entry| entry.path())
+        .filter(|path| is_executable(path))
+        .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.to_string()))
+        .collect()
+}
+
+fn list_plugins(shell: &Shell) -> Value {
+    let mut plugins: Vec<(&String, &PathBuf)> = shell.plugins().iter().collect();
+    plugins.sort();
+    let mut output = Vec::new();
+    for (name, path) in plugins {
+        let mut map = HashMap::new();
+        map.insert("type".to_string(), Value::String("plugin".to_string()));
+        map.insert("name".to_string(), Value::String(name.clone()));
+        map.insert("path".to_string(), Value::String(path.to_string_lossy().to_string()));
+        output.push(Value::Map(map, Some(String::from("{name} {path}"))));
+    }
+    Value::List(output.into())
+}
+
+/// `plugin list`, `plugin add path [name]`, `plugin remove name` or `plugin reload`.
+pub fn plugin(shell: Option<Arc<RwLock<Shell>>>, args: &[Value]) -> Result<Value,String> {
+    let shell = match shell {
+        Some(shell) => shell,
+        None => return Err("plugin: Called from bad context".to_string()),
+    };
+    let mut borrowed_shell = borrow_mut!(shell);
+    match (args.get(0), args.get(1), args.get(2)) {
+        (Some(Value::String(command)), None, None) if command == "list" => {
+            Ok(list_plugins(&borrowed_shell))
+        }
+        (Some(Value::String(command)), Some(Value::String(path)), name) if command == "add" => {
+            let name = match name {
+                Some(Value::String(name)) => Some(name.clone()),
+                Some(_) => return Err("plugin: expected a string name".to_string()),
+                None => None,
+            };
+            let name = add_plugin(&mut borrowed_shell, PathBuf::from(path), name)?;
+            Ok(Value::String(name))
+        }
+        (Some(Value::String(command)), Some(Value::String(name)), None) if command == "remove" => {
+            remove_plugin(&mut borrowed_shell, name)?;
+            Ok(Value::Null)
+        }
+        (Some(Value::String(command)), None, None) if command == "reload" => {
+            let names: Vec<String> = borrowed_shell.plugins().keys().cloned().collect();
+            for name in names {
+                remove_plugin(&mut borrowed_shell, &name)?;
+            }
+            load_plugins(&mut borrowed_shell)?;
+            Ok(list_plugins(&borrowed_shell))
+        }
+        _ => Err("plugin: expected list, add path [name], remove name or reload".to_string()),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn write_plugin(path: &Path, script: &str) {
+        std::fs::write(path, format!("#!/bin/sh\n{}\n", script)).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_load_plugins() {
+        let dir = std::env::temp_dir().join(format!("caat_plugins_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        write_plugin(&dir.join("greet.sh"), "echo 'greet name'\necho 'Says hello'");
+        write_plugin(&dir.join("quiet"), "exit 1");
+        write_plugin(&dir.join(OsStr::from_bytes(b"bad\xff")), "exit 0");
+        std::fs::write(dir.join("notes.txt"), "not a plugin").unwrap();
+        write_plugin(&dir.join("marker"), &format!("touch {}", dir.join("ran").display()));
+        let mut listed = plugin_names_in(&dir);
+        listed.sort();
+        let ran = dir.join("ran").exists();
+        std::fs::remove_file(dir.join("marker")).unwrap();
+        let mut shell = Shell::new();
+        let result = load_plugins_from(&mut shell, &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        result.unwrap();
+        assert_eq!(listed, vec!["greet", "marker", "quiet"]);
+        assert!(!ran);
+        let mut names: Vec<&String> = shell.plugins().keys().collect();
+        names.sort();
+        assert_eq!(names, vec!["greet", "quiet"]);
+        let greet = shell.builtins().get("greet").unwrap();
+        assert_eq!(greet.signature(), "greet name");
+        assert_eq!(greet.help(), "Says hello");
+        assert_eq!(shell.builtins().get("quiet").unwrap().signature(), "quiet");
+    }
+
+    #[test]
+    fn test_help_timeout() {
+        let path = std::env::temp_dir().join(format!("caat_plugin_slow_{}", std::process::id()));
+        write_plugin(&path, "sleep 5");
+        let start = std::time::Instant::now();
+        let help = read_help(&path, Duration::from_millis(100));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(help, None);
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
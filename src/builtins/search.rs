@@ -1,33 +1,375 @@
-use std::process::Command;
-
-use caat_rust::Value;
+use caat_rust::{Caat, Value};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use super::ls::{dir_entry, EntryFormat, Names};
 
 
+#[derive(Clone, Copy)]
+enum Comparison {
+    Less,
+    Equal,
+    Greater,
+}
 
+impl Comparison {
+    fn holds(&self, value: u64, target: u64) -> bool {
+        match self {
+            Comparison::Less => value < target,
+            Comparison::Equal => value == target,
+            Comparison::Greater => value > target,
+        }
+    }
+}
 
+enum Test {
+    Name(Regex),
+    Regex(Regex),
+    Type(char),
+    /// Size in units of the given number of bytes, rounded up.
+    Size(Comparison, u64, u64),
+    /// Whole days since the last modification.
+    Mtime(Comparison, u64),
+    Function(Arc<dyn Caat>),
+    Not(Box<Test>),
+}
 
+/// `find [paths...] [tests...] [function]`
+///
+/// Tests follow `find(1)`: `-name` and `-iname` match the file name against a
+/// glob, `-regex` matches the whole path, `-type` is `f`, `d` or `l`, `-size`
+/// is `[+-]n[ckMG]` in bytes unless a unit is given, and `-mtime` is `[+-]days`.
+/// A `+` means more than and a `-` less than. Tests must all pass unless
+/// separated by `-or`, and `-not` negates the test after it. A function is a
+/// test too, called with the entry and passing if it returns true.
+///
+/// `-mindepth` and `-maxdepth` limit how deep results are taken from, where
+/// the starting paths are at depth 0. Symbolic links are not followed unless
+/// `-L` is given.
+struct Find {
+    paths: Vec<PathBuf>,
+    alternatives: Vec<Vec<Test>>,
+    min_depth: usize,
+    max_depth: Option<usize>,
+    follow_links: bool,
+    visited: HashSet<(u64, u64)>,
+    now: SystemTime,
+    names: Names,
+}
 
-pub fn find(args: &Vec<Value>) -> Result<Value,String> {
-    let mut command = Command::new("find");
-    for arg in args {
-        match arg {
-            Value::String(s) => command.arg(s),
-            _ => {
-                return Err("find: expected string arguments".to_string());
+/// Translates a shell glob into an anchored regex. `*` and `?` match any
+/// characters and `[...]` is a character class, negated by a leading `!`.
+fn glob_to_regex(glob: &str, case_insensitive: bool) -> Result<Regex, String> {
+    let mut pattern = String::from(if case_insensitive { "(?i)^" } else { "^" });
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            '[' => {
+                pattern.push('[');
+                if chars.peek() == Some(&'!') {
+                    chars.next();
+                    pattern.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                pattern.push(']');
             }
-        };
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
     }
-    let output = command.output().map_err(|e| format!("find: {}", e))?;
-    let output = String::from_utf8(output.stdout).map_err(|e| format!("find: {}", e))?;
-    let output = output.split("\n").map(|s| Value::String(s.to_string())).collect();
+    pattern.push('$');
+    Regex::new(&pattern).map_err(|e| format!("find: bad pattern {}: {}", glob, e))
+}
+
+/// Splits a leading `+` or `-` off a number, as in `+10k` or `-3`.
+fn parse_comparison(value: &Value, option: &str) -> Result<(Comparison, String), String> {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => i.to_string(),
+        _ => return Err(format!("find: {} expects a number", option)),
+    };
+    if let Some(rest) = text.strip_prefix('+') {
+        Ok((Comparison::Greater, rest.to_string()))
+    } else if let Some(rest) = text.strip_prefix('-') {
+        Ok((Comparison::Less, rest.to_string()))
+    } else {
+        Ok((Comparison::Equal, text))
+    }
+}
 
-    Ok(Value::List(output))
+fn parse_number(text: &str, option: &str) -> Result<u64, String> {
+    text.parse().map_err(|_| format!("find: {} expects a number, got {}", option, text))
 }
 
+fn as_string<'a>(value: Option<&'a Value>, option: &str) -> Result<&'a str, String> {
+    match value {
+        Some(Value::String(s)) => Ok(s),
+        _ => Err(format!("find: {} expects a string", option)),
+    }
+}
+
+fn as_depth(value: Option<&Value>, option: &str) -> Result<usize, String> {
+    match value {
+        Some(Value::Integer(i)) if *i >= 0 => Ok(*i as usize),
+        Some(Value::String(s)) => s.parse().map_err(|_| format!("find: {} expects a number, got {}", option, s)),
+        _ => Err(format!("find: {} expects a number", option)),
+    }
+}
+
+impl Find {
+    fn new(args: &[Value]) -> Result<Find, String> {
+        let mut find = Find {
+            paths: Vec::new(),
+            alternatives: vec![Vec::new()],
+            min_depth: 0,
+            max_depth: None,
+            follow_links: false,
+            visited: HashSet::new(),
+            now: SystemTime::now(),
+            names: Names::load(),
+        };
+        let mut negate = false;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let test = match arg {
+                Value::String(option) if option.starts_with('-') && option.len() > 1 => {
+                    match option.as_str() {
+                        "-name" | "-iname" => Test::Name(glob_to_regex(as_string(args.next(), option)?, option == "-iname")?),
+                        "-regex" => {
+                            let pattern = as_string(args.next(), option)?;
+                            Test::Regex(Regex::new(&format!("^(?:{})$", pattern)).map_err(|e| format!("find: bad regex {}: {}", pattern, e))?)
+                        }
+                        "-type" => match as_string(args.next(), option)? {
+                            kind @ ("f" | "d" | "l") => Test::Type(kind.chars().next().unwrap()),
+                            kind => return Err(format!("find: unknown type {}, expected f, d or l", kind)),
+                        },
+                        "-size" => {
+                            let (comparison, text) = parse_comparison(args.next().unwrap_or(&Value::Null), option)?;
+                            let (number, unit) = match text.chars().last() {
+                                Some('c') => (&text[..text.len() - 1], 1),
+                                Some('k') => (&text[..text.len() - 1], 1024),
+                                Some('M') => (&text[..text.len() - 1], 1024 * 1024),
+                                Some('G') => (&text[..text.len() - 1], 1024 * 1024 * 1024),
+                                _ => (text.as_str(), 1),
+                            };
+                            Test::Size(comparison, parse_number(number, option)?, unit)
+                        }
+                        "-mtime" => {
+                            let (comparison, text) = parse_comparison(args.next().unwrap_or(&Value::Null), option)?;
+                            Test::Mtime(comparison, parse_number(&text, option)?)
+                        }
+                        "-mindepth" => {
+                            find.min_depth = as_depth(args.next(), option)?;
+                            continue;
+                        }
+                        "-maxdepth" => {
+                            find.max_depth = Some(as_depth(args.next(), option)?);
+                            continue;
+                        }
+                        "-L" => {
+                            find.follow_links = true;
+                            continue;
+                        }
+                        "-not" => {
+                            negate = !negate;
+                            continue;
+                        }
+                        "-or" => {
+                            find.alternatives.push(Vec::new());
+                            continue;
+                        }
+                        "-and" => continue,
+                        _ => return Err(format!("find: unknown option {}", option)),
+                    }
+                }
+                Value::String(path) => {
+                    find.paths.push(PathBuf::from(path));
+                    continue;
+                }
+                Value::Map(map, _) if map.get("type") == Some(&Value::String("dir_entry".to_string())) => {
+                    match map.get("full_path") {
+                        Some(Value::String(path)) => find.paths.push(PathBuf::from(path)),
+                        _ => return Err("find: dir_entry without a full_path".to_string()),
+                    }
+                    continue;
+                }
+                Value::CAATFunction(function) => Test::Function(function.clone()),
+                _ => return Err("find: expected paths, options or a function".to_string()),
+            };
+            let test = if negate { Test::Not(Box::new(test)) } else { test };
+            negate = false;
+            find.alternatives.last_mut().unwrap().push(test);
+        }
+        if find.paths.is_empty() {
+            find.paths.push(PathBuf::from("."));
+        }
+        Ok(find)
+    }
+
+    fn passes(&self, test: &Test, path: &Path, metadata: &Metadata, entry: &Value) -> Result<bool, String> {
+        Ok(match test {
+            Test::Name(pattern) => {
+                let name = path.file_name().map_or(path.to_string_lossy(), |name| name.to_string_lossy());
+                pattern.is_match(&name)
+            }
+            Test::Regex(pattern) => pattern.is_match(&path.to_string_lossy()),
+            Test::Type(kind) => match kind {
+                'f' => metadata.file_type().is_file(),
+                'd' => metadata.file_type().is_dir(),
+                _ => metadata.file_type().is_symlink(),
+            },
+            Test::Size(comparison, target, unit) => comparison.holds((metadata.len() + unit - 1) / unit, *target),
+            Test::Mtime(comparison, days) => {
+                let modified = metadata.modified().map_err(|e| format!("find: {}", e))?;
+                let age = self.now.duration_since(modified).map_or(0, |age| age.as_secs() / 86400);
+                comparison.holds(age, *days)
+            }
+            Test::Function(function) => match function.call(&[entry.clone()]) {
+                Value::Boolean(result) => result,
+                Value::Failure(msg) => return Err(msg),
+                _ => false,
+            },
+            Test::Not(test) => !self.passes(test, path, metadata, entry)?,
+        })
+    }
+
+    fn matches(&self, path: &Path, metadata: &Metadata, entry: &Value) -> Result<bool, String> {
+        for alternative in &self.alternatives {
+            let mut all = true;
+            for test in alternative {
+                if !self.passes(test, path, metadata, entry)? {
+                    all = false;
+                    break;
+                }
+            }
+            if all {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
 
+    fn walk(&mut self, path: &Path, depth: usize, output: &mut Vec<Value>) -> Result<(), String> {
+        let metadata = if self.follow_links { fs::metadata(path) } else { fs::symlink_metadata(path) };
+        let metadata = match metadata {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                eprintln!("find: {}: {}", path.display(), e);
+                return Ok(());
+            }
+        };
+        if depth >= self.min_depth {
+            // An entry that can't be described, such as one whose name isn't
+            // UTF-8, is skipped like a directory that can't be read.
+            let entry = match dir_entry(path, &metadata, &self.names, &EntryFormat::default()) {
+                Ok(entry) => entry,
+                Err(msg) => {
+                    eprintln!("find: {}: {}", path.display(), msg);
+                    return Ok(());
+                }
+            };
+            if self.matches(path, &metadata, &entry)? {
+                output.push(entry);
+            }
+        }
+        if !metadata.is_dir() || self.max_depth.map_or(false, |max| depth >= max) {
+            return Ok(());
+        }
+        // Following links can lead back into a directory already walked.
+        if self.follow_links && !self.visited.insert((metadata.dev(), metadata.ino())) {
+            return Ok(());
+        }
+        let mut children: Vec<PathBuf> = match fs::read_dir(path) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect(),
+            Err(e) => {
+                eprintln!("find: {}: {}", path.display(), e);
+                return Ok(());
+            }
+        };
+        children.sort();
+        for child in children {
+            self.walk(&child, depth + 1, output)?;
+        }
+        Ok(())
+    }
+}
 
+pub fn find(args: &[Value]) -> Result<Value,String> {
+    let mut find = Find::new(args)?;
+    let mut output = Vec::new();
+    for path in find.paths.clone() {
+        find.walk(&path, 0, &mut output)?;
+    }
+    Ok(Value::List(output.into()))
+}
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn test_glob_to_regex() {
+        assert!(glob_to_regex("*.png", false).unwrap().is_match("wall.png"));
+        assert!(!glob_to_regex("*.png", false).unwrap().is_match("wall.png.bak"));
+        assert!(glob_to_regex("IMG_??.[jp]*", false).unwrap().is_match("IMG_01.jpeg"));
+        assert!(!glob_to_regex("[!a]*", false).unwrap().is_match("abc"));
+        assert!(glob_to_regex("*.PNG", true).unwrap().is_match("wall.png"));
+    }
 
+    #[test]
+    fn test_find() {
+        let dir = std::env::temp_dir().join(format!("caat_find_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.png"), "png").unwrap();
+        fs::write(dir.join("sub/b.jpg"), "jpg").unwrap();
+        fs::write(dir.join("sub/c.txt"), "text").unwrap();
+        let names = |args: Vec<Value>| -> Vec<String> {
+            let mut args = args;
+            args.insert(0, Value::String(dir.to_string_lossy().to_string()));
+            match find(&args).unwrap() {
+                Value::List(list) => list.iter().map(|entry| match entry {
+                    Value::Map(map, _) => format!("{:?}", map.get("name").unwrap()),
+                    _ => panic!("expected a dir_entry"),
+                }).collect(),
+                _ => panic!("expected a list"),
+            }
+        };
+        let string = |s: &str| Value::String(s.to_string());
+        assert_eq!(names(vec![string("-name"), string("*.png"), string("-or"), string("-name"), string("*.jpg")]).len(), 2);
+        assert_eq!(names(vec![string("-type"), string("f"), string("-maxdepth"), Value::Integer(1)]).len(), 1);
+        assert_eq!(names(vec![string("-type"), string("d"), string("-mindepth"), Value::Integer(1)]).len(), 1);
+        assert_eq!(names(vec![string("-type"), string("f"), string("-size"), string("+3c")]).len(), 1);
+        assert_eq!(names(vec![string("-type"), string("f"), string("-not"), string("-name"), string("*.txt")]).len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 
+    #[test]
+    fn test_find_skips_bad_names() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let dir = std::env::temp_dir().join(format!("caat_find_bad_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(OsStr::from_bytes(b"bad\xff")), "bad").unwrap();
+        fs::write(dir.join("good"), "good").unwrap();
+        let result = find(&[Value::String(dir.to_string_lossy().to_string()), Value::String("-type".to_string()), Value::String("f".to_string())]);
+        fs::remove_dir_all(&dir).unwrap();
+        match result.unwrap() {
+            Value::List(list) => assert_eq!(list.len(), 1),
+            value => panic!("expected a list, got {:?}", value),
+        }
+    }
+}
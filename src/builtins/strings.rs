@@ -1,6 +1,8 @@
 use caat_rust::Value;
+use crate::eval::format_value_file;
+use unicode_segmentation::UnicodeSegmentation;
 
-pub fn contains(args: &Vec<Value>) -> Result<Value,String> {
+pub fn contains(args: &[Value]) -> Result<Value,String> {
     if args.len() != 2 {
         return Err("Expected 2 arguments".to_string());
     }
@@ -16,22 +18,253 @@ pub fn contains(args: &Vec<Value>) -> Result<Value,String> {
     }
 }
 
-pub fn split(args: &Vec<Value>) -> Result<Value,String> {
-    let space = Value::String(String::from(" "));
-    let split = if args.len() != 2 {
-        &space
-    } else {
-        match &args[1] {
-            Value::String(_) => &args[1],
-            _ => return Err("Expected string".to_string()),
-        }
+pub fn split(args: &[Value]) -> Result<Value,String> {
+    let (s, separator) = match args {
+        [Value::String(s)] => (s, " "),
+        [Value::String(s), Value::String(separator)] => (s, separator.as_str()),
+        [Value::String(_), separator] => return Err(format!("split: the separator must be a string, not {}", type_name(separator))),
+        _ => return Err("split: expected a string and an optional separator".to_string()),
     };
+    let v: Vec<Value> = s.split(separator).map(|s| Value::String(s.to_string())).collect();
+    Ok(Value::List(v.into()))
+}
 
-    match (&args[0],split) {
-        (Value::String(s),Value::String(t)) => {
-            let v: Vec<Value> = s.split(t).map(|s| Value::String(s.to_string())).collect();
-            Ok(Value::List(v.into()))
-        }
-        _ => Err("Expected strings".to_string()),
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "()",
+        Value::String(_) => "a string",
+        Value::Integer(_) => "an integer",
+        Value::Float(_) => "a float",
+        Value::Boolean(_) => "a boolean",
+        Value::List(_) => "a list",
+        Value::Map(_, _) => "a map",
+        Value::Failure(_) => "a failure",
+        Value::CAATFunction(_) => "a function",
+    }
+}
+
+/// Splits the string a command works on from its other arguments. The string
+/// is the last argument, where a pipeline puts it.
+fn string_arg<'a>(command: &str, args: &'a [Value]) -> Result<(&'a str, &'a [Value]), String> {
+    match args.split_last() {
+        Some((Value::String(s), rest)) => Ok((s, rest)),
+        _ => Err(format!("{}: expected a string as the last argument", command)),
+    }
+}
+
+fn only_string<'a>(command: &str, args: &'a [Value]) -> Result<&'a str, String> {
+    match string_arg(command, args)? {
+        (s, []) => Ok(s),
+        _ => Err(format!("{}: expected a string", command)),
+    }
+}
+
+/// Makes an empty string with room for `len` bytes, or an error when that is
+/// more than can be allocated.
+fn string_with_capacity(command: &str, len: Option<usize>) -> Result<String, String> {
+    let mut output = String::new();
+    match len {
+        Some(len) if output.try_reserve(len).is_ok() => Ok(output),
+        _ => Err(format!("{}: the result would be too long", command)),
+    }
+}
+
+/// Converts a character index, counting from the end when negative, to a byte
+/// offset, clamped to the string. Characters are grapheme clusters, so an
+/// accented letter or an emoji sequence counts as one.
+fn char_offset(s: &str, index: i64) -> usize {
+    let len = s.graphemes(true).count() as i64;
+    let index = if index < 0 { (len + index).max(0) } else { index.min(len) };
+    s.grapheme_indices(true).nth(index as usize).map_or(s.len(), |(offset, _)| offset)
+}
+
+pub fn trim(args: &[Value]) -> Result<Value,String> {
+    Ok(Value::String(only_string("trim", args)?.trim().to_string()))
+}
+
+pub fn upper(args: &[Value]) -> Result<Value,String> {
+    Ok(Value::String(only_string("upper", args)?.to_uppercase()))
+}
+
+pub fn lower(args: &[Value]) -> Result<Value,String> {
+    Ok(Value::String(only_string("lower", args)?.to_lowercase()))
+}
+
+/// `replace from to string` replaces every occurrence of `from`.
+pub fn replace(args: &[Value]) -> Result<Value,String> {
+    match string_arg("replace", args)? {
+        (s, [Value::String(from), Value::String(to)]) => Ok(Value::String(s.replace(from.as_str(), to))),
+        _ => Err("replace: expected the text to replace, its replacement and a string".to_string()),
+    }
+}
+
+pub fn starts_with(args: &[Value]) -> Result<Value,String> {
+    match string_arg("starts_with", args)? {
+        (s, [Value::String(prefix)]) => Ok(Value::Boolean(s.starts_with(prefix.as_str()))),
+        _ => Err("starts_with: expected a prefix and a string".to_string()),
+    }
+}
+
+pub fn ends_with(args: &[Value]) -> Result<Value,String> {
+    match string_arg("ends_with", args)? {
+        (s, [Value::String(suffix)]) => Ok(Value::Boolean(s.ends_with(suffix.as_str()))),
+        _ => Err("ends_with: expected a suffix and a string".to_string()),
+    }
+}
+
+/// `join list [separator]` joins the items of a list into a string. Items that
+/// aren't strings are written the way `>` writes them.
+pub fn join(args: &[Value]) -> Result<Value,String> {
+    let (list, separator) = match args {
+        [Value::List(list)] => (list, ""),
+        [Value::List(list), Value::String(separator)] | [Value::String(separator), Value::List(list)] => (list, separator.as_str()),
+        _ => return Err("join: expected a list and an optional separator".to_string()),
+    };
+    let items: Vec<String> = list.iter().map(|value| match value {
+        Value::String(s) => s.clone(),
+        value => format_value_file(value),
+    }).collect();
+    Ok(Value::String(items.join(separator)))
+}
+
+fn pad(command: &str, args: &[Value], left: bool) -> Result<Value,String> {
+    let (s, width, fill) = match string_arg(command, args)? {
+        (s, [Value::Integer(width)]) => (s, *width, " "),
+        (s, [Value::Integer(width), Value::String(fill)]) if fill.graphemes(true).count() == 1 => (s, *width, fill.as_str()),
+        _ => return Err(format!("{}: expected a width, an optional fill character and a string", command)),
+    };
+    let count = usize::try_from(width).unwrap_or(0).saturating_sub(s.graphemes(true).count());
+    let len = fill.len().checked_mul(count).and_then(|len| len.checked_add(s.len()));
+    let mut output = string_with_capacity(command, len)?;
+    if !left {
+        output.push_str(s);
+    }
+    for _ in 0..count {
+        output.push_str(fill);
+    }
+    if left {
+        output.push_str(s);
+    }
+    Ok(Value::String(output))
+}
+
+/// `pad_left width [fill] string` pads a string to a width in characters.
+pub fn pad_left(args: &[Value]) -> Result<Value,String> {
+    pad("pad_left", args, true)
+}
+
+pub fn pad_right(args: &[Value]) -> Result<Value,String> {
+    pad("pad_right", args, false)
+}
+
+/// `substring start [end] string` returns the characters from start up to but
+/// not including end. Negative indices count from the end.
+pub fn substring(args: &[Value]) -> Result<Value,String> {
+    let (s, start, end) = match string_arg("substring", args)? {
+        (s, [Value::Integer(start)]) => (s, char_offset(s, *start), s.len()),
+        (s, [Value::Integer(start), Value::Integer(end)]) => (s, char_offset(s, *start), char_offset(s, *end)),
+        _ => return Err("substring: expected a start, an optional end and a string".to_string()),
+    };
+    Ok(Value::String(if start < end { s[start..end].to_string() } else { String::new() }))
+}
+
+/// `char_at index string` returns the character at an index, counting from the
+/// end when negative.
+pub fn char_at(args: &[Value]) -> Result<Value,String> {
+    let (s, index) = match string_arg("char_at", args)? {
+        (s, [Value::Integer(index)]) => (s, *index),
+        _ => return Err("char_at: expected an index and a string".to_string()),
+    };
+    let len = s.graphemes(true).count() as i64;
+    let position = if index < 0 { len + index } else { index };
+    if position < 0 || position >= len {
+        return Err(format!("char_at: index {} is out of range for a string of {} characters", index, len));
+    }
+    Ok(Value::String(s.graphemes(true).nth(position as usize).unwrap().to_string()))
+}
+
+pub fn lines(args: &[Value]) -> Result<Value,String> {
+    let s = only_string("lines", args)?;
+    Ok(Value::List(s.lines().map(|line| Value::String(line.to_string())).collect()))
+}
+
+/// `words string` splits a string on any run of whitespace.
+pub fn words(args: &[Value]) -> Result<Value,String> {
+    let s = only_string("words", args)?;
+    Ok(Value::List(s.split_whitespace().map(|word| Value::String(word.to_string())).collect()))
+}
+
+pub fn repeat(args: &[Value]) -> Result<Value,String> {
+    let (s, n) = match string_arg("repeat", args)? {
+        (s, [Value::Integer(n)]) if *n >= 0 => (s, *n as usize),
+        _ => return Err("repeat: expected a count that is not negative and a string".to_string()),
+    };
+    let mut output = string_with_capacity("repeat", s.len().checked_mul(n))?;
+    for _ in 0..n {
+        output.push_str(s);
+    }
+    Ok(Value::String(output))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn eval(script: &str) -> Result<Value, crate::Error> {
+        crate::Interpreter::new().eval_str(script)
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(&[string("a b")]).unwrap(), Value::List(vec![string("a"), string("b")].into()));
+        assert!(split(&[string("a1b"), Value::Integer(1)]).is_err());
+        assert!(split(&[string("a b"), string(" "), string("extra")]).is_err());
+    }
+
+    #[test]
+    fn test_unicode() {
+        assert_eq!(substring(&[Value::Integer(2), Value::Integer(-2), string("naïve café")]).unwrap(), string("ïve ca"));
+        assert_eq!(char_at(&[Value::Integer(-1), string("café")]).unwrap(), string("é"));
+        assert_eq!(pad_left(&[Value::Integer(3), string("·"), string("é")]).unwrap(), string("··é"));
+        assert_eq!(upper(&[string("straße")]).unwrap(), string("STRASSE"));
+        assert!(char_at(&[Value::Integer(3), string("abc")]).is_err());
+    }
+
+    #[test]
+    fn test_graphemes() {
+        // "e" followed by a combining acute accent, and a family emoji.
+        let s = "cafe\u{301} 👨\u{200d}👩\u{200d}👧";
+        assert_eq!(char_at(&[Value::Integer(3), string(s)]).unwrap(), string("e\u{301}"));
+        assert_eq!(char_at(&[Value::Integer(-1), string(s)]).unwrap(), string("👨\u{200d}👩\u{200d}👧"));
+        assert_eq!(substring(&[Value::Integer(0), Value::Integer(4), string(s)]).unwrap(), string("cafe\u{301}"));
+        assert_eq!(pad_right(&[Value::Integer(6), string("cafe\u{301}")]).unwrap(), string("cafe\u{301}  "));
+    }
+
+    #[test]
+    fn test_subject_last() {
+        assert_eq!(eval("echo \"a-b\" | replace \"-\" \"_\"").unwrap(), string("a_b"));
+        assert_eq!(eval("echo \"caat\" | starts_with \"ca\"").unwrap(), Value::Boolean(true));
+        assert_eq!(eval("echo \"7\" | pad_left 3 \"0\"").unwrap(), string("007"));
+        assert_eq!(eval("echo \"ab\" | repeat 2").unwrap(), string("abab"));
+    }
+
+    #[test]
+    fn test_too_long() {
+        assert!(repeat(&[Value::Integer(i64::MAX), string("ab")]).is_err());
+        assert!(repeat(&[Value::Integer(i64::MAX / 2), string("a")]).is_err());
+        assert!(pad_left(&[Value::Integer(i64::MAX), string("ab")]).is_err());
+        assert!(pad_right(&[Value::Integer(i64::MAX), string("é"), string("ab")]).is_err());
+        assert_eq!(repeat(&[Value::Integer(0), string("ab")]).unwrap(), string(""));
+    }
+
+    #[test]
+    fn test_join() {
+        let list = Value::List(vec![string("a"), Value::Integer(1)].into());
+        assert_eq!(join(&[string(", "), list]).unwrap(), string("a, 1"));
     }
 }
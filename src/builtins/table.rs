@@ -0,0 +1,237 @@
+use caat_rust::Value;
+use crate::eval::format_value_file;
+use std::collections::HashMap;
+use super::list_utils::{compare_values, list_args};
+
+
+fn column_names(command: &str, args: &[Value]) -> Result<Vec<String>, String> {
+    args.iter().map(|arg| match arg {
+        Value::String(column) => Ok(column.clone()),
+        _ => Err(format!("{}: expected column names as strings", command)),
+    }).collect()
+}
+
+/// The value of a column, `()` when the row doesn't have it.
+fn column(row: &Value, name: &str) -> Value {
+    match row {
+        Value::Map(map, _) => map.get(name).cloned().unwrap_or(Value::Null),
+        _ => Value::Null,
+    }
+}
+
+/// The key a value is grouped under: strings as they are, anything else the
+/// way it would be written to a file.
+fn group_key(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => format_value_file(value),
+    }
+}
+
+/// `select columns... rows` keeps only the given columns of each row, in the
+/// order of the columns, with `()` for those a row lacks.
+pub fn select(args: &[Value]) -> Result<Value, String> {
+    let (rows, columns) = list_args("select", args)?;
+    let columns = column_names("select", columns)?;
+    let output: Vec<Value> = rows.iter().map(|row| {
+        let map = columns.iter().map(|name| (name.clone(), column(row, name))).collect();
+        Value::Map(map, None)
+    }).collect();
+    Ok(Value::List(output.into()))
+}
+
+/// `where column test rows` keeps the rows whose column passes a test. The
+/// test is a function called with the column's value, or a value the column
+/// has to equal.
+pub fn where_command(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("where", args)?;
+    let (name, test) = match args {
+        [Value::String(name), test] => (name, test),
+        _ => return Err("where: expected a column name and a test".to_string()),
+    };
+    let mut output = Vec::new();
+    for row in rows {
+        let value = column(row, name);
+        let keep = match test {
+            Value::CAATFunction(function) => match function.call(&[value]) {
+                Value::Boolean(keep) => keep,
+                Value::Failure(msg) => return Err(msg),
+                _ => return Err("where: the test must return a boolean".to_string()),
+            },
+            test => value == *test,
+        };
+        if keep {
+            output.push(row.clone());
+        }
+    }
+    Ok(Value::List(output.into()))
+}
+
+/// `sort_by [--desc] columns... rows` sorts rows by their columns, comparing
+/// later columns only when the earlier ones are equal. The sort is stable.
+pub fn sort_by(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("sort_by", args)?;
+    let mut descending = false;
+    let mut columns = Vec::new();
+    for arg in args {
+        match arg {
+            Value::String(flag) if flag == "--desc" => descending = true,
+            Value::String(flag) if flag == "--asc" => descending = false,
+            Value::String(name) => columns.push(name.clone()),
+            _ => return Err("sort_by: expected column names as strings".to_string()),
+        }
+    }
+    if columns.is_empty() {
+        return Err("sort_by: expected a column to sort by".to_string());
+    }
+    let mut output = rows.to_vec();
+    output.sort_by(|a, b| {
+        let ordering = columns.iter()
+            .map(|name| compare_values(&column(a, name), &column(b, name)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or(std::cmp::Ordering::Equal);
+        if descending { ordering.reverse() } else { ordering }
+    });
+    Ok(Value::List(output.into()))
+}
+
+/// `group_by column rows` returns a map from each value of a column to the
+/// rows that have it.
+pub fn group_by(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("group_by", args)?;
+    let name = match column_names("group_by", args)?.as_slice() {
+        [name] => name.clone(),
+        _ => return Err("group_by: expected one column".to_string()),
+    };
+    let mut groups: HashMap<String, Vec<Value>> = HashMap::new();
+    for row in rows {
+        groups.entry(group_key(&column(row, &name))).or_default().push(row.clone());
+    }
+    let map = groups.into_iter().map(|(key, rows)| (key, Value::List(rows.into()))).collect();
+    Ok(Value::Map(map, None))
+}
+
+/// `uniq list` removes repeated items, keeping the first of each.
+pub fn uniq(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("uniq", args)?;
+    if !args.is_empty() {
+        return Err("uniq: expected only a list".to_string());
+    }
+    let mut output: Vec<Value> = Vec::new();
+    for row in rows {
+        if !output.contains(row) {
+            output.push(row.clone());
+        }
+    }
+    Ok(Value::List(output.into()))
+}
+
+/// `uniq_by columns... rows` keeps the first row for each distinct
+/// combination of values in the columns.
+pub fn uniq_by(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("uniq_by", args)?;
+    let columns = column_names("uniq_by", args)?;
+    let mut seen: Vec<Vec<Value>> = Vec::new();
+    let mut output = Vec::new();
+    for row in rows {
+        let key: Vec<Value> = columns.iter().map(|name| column(row, name)).collect();
+        if !seen.contains(&key) {
+            seen.push(key);
+            output.push(row.clone());
+        }
+    }
+    Ok(Value::List(output.into()))
+}
+
+fn count_arg(command: &str, args: &[Value]) -> Result<Option<usize>, String> {
+    match args {
+        [] => Ok(None),
+        [Value::Integer(n)] if *n >= 0 => Ok(Some(*n as usize)),
+        _ => Err(format!("{}: expected a count that is not negative", command)),
+    }
+}
+
+/// `first [n] list` returns the first item, or a list of the first `n`.
+pub fn first(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("first", args)?;
+    match count_arg("first", args)? {
+        Some(n) => Ok(Value::List(rows.iter().take(n).cloned().collect())),
+        None => rows.first().cloned().ok_or("first: empty list".to_string()),
+    }
+}
+
+/// `last [n] list` returns the last item, or a list of the last `n`.
+pub fn last(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("last", args)?;
+    match count_arg("last", args)? {
+        Some(n) => Ok(Value::List(rows[rows.len().saturating_sub(n)..].iter().cloned().collect())),
+        None => rows.last().cloned().ok_or("last: empty list".to_string()),
+    }
+}
+
+/// `count [function] list` returns the number of items, or of the items a
+/// function returns true for.
+pub fn count(args: &[Value]) -> Result<Value, String> {
+    let (rows, args) = list_args("count", args)?;
+    let count = match args {
+        [] => rows.len(),
+        [Value::CAATFunction(function)] => {
+            let mut count = 0;
+            for row in rows {
+                match function.call(&[row.clone()]) {
+                    Value::Boolean(true) => count += 1,
+                    Value::Boolean(false) => {}
+                    Value::Failure(msg) => return Err(msg),
+                    _ => return Err("count: the function must return a boolean".to_string()),
+                }
+            }
+            count
+        }
+        _ => return Err("count: expected a list and an optional function".to_string()),
+    };
+    Ok(Value::Integer(count as i64))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    fn row(name: &str, size: i64) -> Value {
+        let mut map = HashMap::new();
+        map.insert("name".to_string(), string(name));
+        map.insert("size".to_string(), Value::Integer(size));
+        Value::Map(map, Some("{name}".to_string()))
+    }
+
+    fn names(rows: Value) -> Vec<Value> {
+        match rows {
+            Value::List(rows) => rows.iter().map(|row| column(row, "name")).collect(),
+            _ => panic!("expected a list"),
+        }
+    }
+
+    #[test]
+    fn test_table() {
+        let rows = Value::List(vec![row("b", 2), row("a", 2), row("c", 1)].into());
+        assert_eq!(names(sort_by(&[string("size"), string("name"), rows.clone()]).unwrap()), vec![string("c"), string("a"), string("b")]);
+        assert_eq!(names(sort_by(&[string("--desc"), string("size"), rows.clone()]).unwrap()), vec![string("b"), string("a"), string("c")]);
+        assert_eq!(names(where_command(&[string("size"), Value::Integer(2), rows.clone()]).unwrap()), vec![string("b"), string("a")]);
+        assert_eq!(names(uniq_by(&[string("size"), rows.clone()]).unwrap()), vec![string("b"), string("c")]);
+        assert_eq!(names(first(&[Value::Integer(2), rows.clone()]).unwrap()), vec![string("b"), string("a")]);
+        assert_eq!(last(&[rows.clone()]).unwrap(), row("c", 1));
+        assert_eq!(count(&[rows.clone()]).unwrap(), Value::Integer(3));
+        match group_by(&[string("size"), rows.clone()]).unwrap() {
+            Value::Map(groups, _) => assert_eq!(names(groups["2"].clone()), vec![string("b"), string("a")]),
+            _ => panic!("expected a map"),
+        }
+        match select(&[string("name"), rows]).unwrap() {
+            Value::List(rows) => assert_eq!(rows[0], Value::Map(HashMap::from([("name".to_string(), string("b"))]), None)),
+            _ => panic!("expected a list"),
+        }
+    }
+}
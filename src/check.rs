@@ -0,0 +1,415 @@
+//! Finds mistakes in a script without running it.
+//!
+//! Variable checks are flow-insensitive: a name counts as defined in a scope
+//! if it is assigned anywhere in that scope, since `loop` bodies can use a
+//! variable before the statement that assigns it. Functions see the variables
+//! of the top level as well as their own, like they do when called from it.
+
+use crate::parser::{Expression, File, MatchArm, Pipeline, PipelinePart, Redirect, Span, SpanKind, Statement};
+use crate::shell::Shell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The function the problem is in, or `None` at the top level.
+    pub function: Option<String>,
+    /// Where the problem is: the index among the spans of that kind and name
+    /// that `parse_file_with_spans` returns. Problems with a variable or
+    /// command point at that use of the name, the rest at their statement.
+    pub occurrence: Option<(SpanKind, String, usize)>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+        if let Some(function) = &self.function {
+            write!(f, " (in function {})", function)?;
+        }
+        Ok(())
+    }
+}
+
+impl Diagnostic {
+    /// The byte range of the problem, from the spans of the file it was found in.
+    pub fn range(&self, spans: &[Span]) -> Option<(usize, usize)> {
+        let (kind, name, index) = self.occurrence.as_ref()?;
+        let span = spans.iter().filter(|span| span.kind == *kind && span.name == *name).nth(*index)?;
+        // A variable's span is its name, after the `$`.
+        let start = if *kind == SpanKind::Variable { span.start.saturating_sub(1) } else { span.start };
+        Some((start, span.end))
+    }
+}
+
+/// The commands a script can call besides its own functions and what is on
+/// `PATH`: the builtins and the plugins, which are listed but not run.
+pub fn known_commands() -> HashSet<String> {
+    let mut commands: HashSet<String> = Shell::new().builtins().list().iter().map(|builtin| builtin.name().to_string()).collect();
+    commands.extend(crate::builtins::plugin::plugin_names());
+    commands
+}
+
+fn on_path(name: &str) -> bool {
+    if name.contains('/') {
+        return std::path::Path::new(name).exists();
+    }
+    match std::env::var_os("PATH") {
+        Some(path) => std::env::split_paths(&path).any(|dir| dir.join(name).is_file()),
+        None => false,
+    }
+}
+
+/// Names assigned directly in `file`, including inside `loop` bodies, which
+/// share the scope they are in.
+fn assigned_names(file: &File, names: &mut HashSet<String>) {
+    for statement in file.statements.iter().flatten() {
+        match statement {
+            Statement::Assignment(assignment) => {
+                names.insert(assignment.target.clone());
+            }
+            Statement::Loop(body) => assigned_names(body, names),
+            _ => {}
+        }
+    }
+}
+
+struct Checker {
+    functions: HashMap<String, usize>,
+    commands: HashSet<String>,
+    scopes: Vec<HashSet<String>>,
+    function: Option<String>,
+    loop_depth: usize,
+    uses: HashMap<(SpanKind, String), usize>,
+    /// The statement being checked.
+    statement: Option<(SpanKind, String, usize)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    /// Reports a problem with the statement being checked.
+    fn report(&mut self, severity: Severity, message: String) {
+        let occurrence = self.statement.clone();
+        self.report_use(severity, message, occurrence);
+    }
+
+    fn report_use(&mut self, severity: Severity, message: String, occurrence: Option<(SpanKind, String, usize)>) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            message,
+            function: self.function.clone(),
+            occurrence,
+        });
+    }
+
+    /// Counts a use of `name`. Uses are visited in source order, so this is
+    /// its index among the spans of the same kind and name. Statements are
+    /// counted the same way, with an empty name.
+    fn next_use(&mut self, kind: SpanKind, name: &str) -> (SpanKind, String, usize) {
+        let count = self.uses.entry((kind, name.to_string())).or_insert(0);
+        *count += 1;
+        (kind, name.to_string(), *count - 1)
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.functions.contains_key(name) || self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn check_body(&mut self, file: &File, mut scope: HashSet<String>) {
+        assigned_names(file, &mut scope);
+        self.scopes.push(scope);
+        self.check_statements(file);
+        self.scopes.pop();
+    }
+
+    fn check_statements(&mut self, file: &File) {
+        let outer_statement = self.statement.take();
+        let mut returned = false;
+        for statement in file.statements.iter().flatten() {
+            match statement {
+                Statement::Blank => continue,
+                Statement::Comment(_) => {
+                    self.next_use(SpanKind::Statement, "");
+                    continue;
+                }
+                _ => {}
+            }
+            self.statement = Some(self.next_use(SpanKind::Statement, ""));
+            if returned {
+                self.report(Severity::Warning, String::from("unreachable code after return"));
+                returned = false;
+            }
+            self.check_statement(statement);
+            if let Statement::Return(_) = statement {
+                returned = true;
+            }
+        }
+        self.statement = outer_statement;
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Assignment(assignment) => self.check_expression(&assignment.value),
+            Statement::Expression(expression) => self.check_expression(expression),
+            Statement::Return(expression) => self.check_expression(expression),
+            Statement::FunctionDef(function) => {
+                let outer_function = self.function.replace(function.name.clone());
+                let outer_depth = std::mem::replace(&mut self.loop_depth, 0);
+                let outer_scopes = self.scopes.split_off(self.scopes.len().min(1));
+                self.check_body(&function.body, function.args.iter().cloned().collect());
+                self.scopes.extend(outer_scopes);
+                self.loop_depth = outer_depth;
+                self.function = outer_function;
+            }
+            Statement::Break => {
+                if self.loop_depth == 0 {
+                    self.report(Severity::Error, String::from("break outside of a loop"));
+                }
+            }
+            Statement::Continue => {
+                if self.loop_depth == 0 {
+                    self.report(Severity::Error, String::from("continue outside of a loop"));
+                }
+            }
+            Statement::Loop(body) => {
+                self.loop_depth += 1;
+                self.check_statements(body);
+                self.loop_depth -= 1;
+            }
+            Statement::Comment(_) | Statement::Blank => {}
+        }
+    }
+
+    fn check_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Literal(_) => {}
+            Expression::Variable(name) => {
+                let occurrence = self.next_use(SpanKind::Variable, name);
+                if !self.is_defined(name) {
+                    self.report_use(Severity::Warning, format!("undefined variable ${}", name), Some(occurrence));
+                }
+            }
+            Expression::Pipeline(pipeline) => self.check_pipeline(pipeline, true),
+            Expression::HigherOrder(pipeline) => self.check_pipeline(pipeline, false),
+            Expression::Parenthesized(expression) => self.check_expression(expression),
+            Expression::If(cond, then, else_) => {
+                self.check_expression(cond);
+                self.check_expression(then);
+                self.check_expression(else_);
+            }
+            Expression::Access(thing, index) => {
+                self.check_expression(thing);
+                self.check_expression(index);
+            }
+            Expression::Concat(a, b) => {
+                self.check_expression(a);
+                self.check_expression(b);
+            }
+            Expression::Lambda(args, body) => {
+                let outer_depth = std::mem::replace(&mut self.loop_depth, 0);
+                self.check_body(body, args.iter().cloned().collect());
+                self.loop_depth = outer_depth;
+            }
+            Expression::Match(expr, arms) => {
+                self.check_expression(expr);
+                let mut has_wildcard = false;
+                for arm in arms {
+                    match arm {
+                        MatchArm::Expression(pattern, body) => {
+                            self.check_expression(pattern);
+                            self.check_expression(body);
+                        }
+                        MatchArm::WildcardDiscard(body) => {
+                            has_wildcard = true;
+                            self.check_expression(body);
+                        }
+                        MatchArm::WildcardBind(name, body) => {
+                            has_wildcard = true;
+                            self.scopes.push(HashSet::from([name.clone()]));
+                            self.check_expression(body);
+                            self.scopes.pop();
+                        }
+                    }
+                }
+                if !has_wildcard {
+                    self.report(Severity::Warning, String::from("match has no wildcard arm"));
+                }
+            }
+        }
+    }
+
+    /// Argument counts are only checked for commands called directly, since a
+    /// `{...}` block gets more arguments from whatever calls it.
+    fn check_pipeline(&mut self, pipeline: &Pipeline, check_arity: bool) {
+        self.check_pipeline_part(&pipeline.pipeline, check_arity, false);
+        if let Some(redirect) = &pipeline.redirect {
+            match redirect {
+                Redirect::Input(expression) | Redirect::Output(expression) | Redirect::Append(expression) => {
+                    self.check_expression(expression);
+                }
+            }
+        }
+    }
+
+    fn check_pipeline_part(&mut self, part: &PipelinePart, check_arity: bool, piped: bool) {
+        let name = &part.command.name;
+        let occurrence = self.next_use(SpanKind::Command, name);
+        for argument in &part.command.arguments {
+            self.check_expression(argument);
+        }
+        if let Some(&arity) = self.functions.get(name) {
+            let count = part.command.arguments.len() + if piped { 1 } else { 0 };
+            if check_arity && count != arity {
+                self.report_use(Severity::Error, format!("{} takes {} arguments but is given {}", name, arity, count), Some(occurrence));
+            }
+        } else if !self.commands.contains(name) && !self.is_defined(name) && !on_path(name) {
+            self.report_use(Severity::Warning, format!("unknown command {}", name), Some(occurrence));
+        }
+        if let Some(next) = &part.next {
+            let piped = part.operator == Some(crate::parser::Operator::Pipe);
+            self.check_pipeline_part(next, check_arity, piped);
+        }
+    }
+}
+
+fn collect_functions(file: &File, functions: &mut HashMap<String, usize>) {
+    for statement in file.statements.iter().flatten() {
+        match statement {
+            Statement::FunctionDef(function) => {
+                functions.insert(function.name.clone(), function.args.len());
+                collect_functions(&function.body, functions);
+            }
+            Statement::Loop(body) => collect_functions(body, functions),
+            _ => {}
+        }
+    }
+}
+
+/// Checks a parsed script, returning its problems in the order they were
+/// found. `commands` are the names it can call, usually `known_commands()`.
+pub fn check_file(file: &File, commands: &HashSet<String>) -> Vec<Diagnostic> {
+    let mut functions = HashMap::new();
+    collect_functions(file, &mut functions);
+    let mut checker = Checker {
+        functions,
+        commands: commands.clone(),
+        scopes: Vec::new(),
+        function: None,
+        loop_depth: 0,
+        uses: HashMap::new(),
+        statement: None,
+        diagnostics: Vec::new(),
+    };
+    let globals = crate::shell::Environment::create_global().into_keys().collect();
+    checker.check_body(file, globals);
+    checker.diagnostics
+}
+
+/// Parses and checks `source`, returning each problem with its byte range. A
+/// parse error becomes an error diagnostic at the place parsing stopped.
+pub fn check_source(source: &str, commands: &HashSet<String>) -> Vec<(Diagnostic, (usize, usize))> {
+    match crate::parser::parse_file_with_spans(source) {
+        Ok((file, spans)) => check_file(&file, commands).into_iter()
+            .map(|diagnostic| {
+                let range = diagnostic.range(&spans).unwrap_or((0, 0));
+                (diagnostic, range)
+            })
+            .collect(),
+        Err(e) => {
+            let offset = e.location.offset.min(source.len());
+            let diagnostic = Diagnostic {
+                severity: Severity::Error,
+                message: format!("parse error: {}", e),
+                function: None,
+                occurrence: None,
+            };
+            vec![(diagnostic, (offset, offset))]
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<String> {
+        check_source(source, &known_commands()).into_iter().map(|(diagnostic, _)| diagnostic.message).collect()
+    }
+
+    /// The line each problem starts on and the text its range covers.
+    fn ranges(source: &str) -> Vec<(usize, &str)> {
+        check_source(source, &known_commands()).into_iter()
+            .map(|(_, (start, end))| (source[..start].matches('\n').count(), &source[start..end]))
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_script() {
+        let source = "x = 1\nfunction f(a) {\n    return add $a $x\n}\ny = f 2\n";
+        assert_eq!(messages(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_undefined_variable() {
+        assert_eq!(messages("echo $missing\n"), vec!["undefined variable $missing"]);
+    }
+
+    #[test]
+    fn test_arity() {
+        let source = "function f(a, b) {\n    return $a\n}\nf 1\n";
+        assert_eq!(messages(source), vec!["f takes 2 arguments but is given 1"]);
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        assert_eq!(messages("break\n"), vec!["break outside of a loop"]);
+        assert_eq!(messages("loop {\n    break\n}\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let source = "function f() {\n    return 1\n    echo 2\n}\n";
+        assert_eq!(messages(source), vec!["unreachable code after return"]);
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(ranges("function f() {\n    return $nope\n}\n"), vec![(1, "$nope")]);
+        assert_eq!(ranges("x = 1\necho \"$xy\"\n# $xy\necho $xy\n"), vec![(3, "$xy")]);
+        assert_eq!(ranges("echo 1\nloop {\n    break\n}\nbreak\n"), vec![(4, "break")]);
+        let source = "function f() {\n    # return early\n    return 1\n    echo 2\n}\n";
+        assert_eq!(ranges(source), vec![(3, "echo 2")]);
+        let (diagnostic, range) = check_source("x = 1\n}\n", &known_commands()).remove(0);
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(range, (6, 6));
+    }
+
+    #[test]
+    fn test_occurrence() {
+        let source = "echo $nope\necho (nope $nope)\n";
+        let occurrences: Vec<_> = check_source(source, &known_commands()).into_iter().map(|(diagnostic, _)| diagnostic.occurrence).collect();
+        assert_eq!(occurrences, vec![
+            Some((SpanKind::Variable, "nope".to_string(), 0)),
+            Some((SpanKind::Variable, "nope".to_string(), 1)),
+            Some((SpanKind::Command, "nope".to_string(), 0)),
+        ]);
+    }
+}
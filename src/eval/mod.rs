@@ -41,7 +41,7 @@ pub fn repl(shell: Arc<RwLock<Shell>>) {
                     }
                 };
                 //eprintln!("{:?}", interactive);
-                match eval(shell.clone(), &mut interactive) {
+                match crate::shell::run_in(shell.clone(), || eval(shell.clone(), &mut interactive)) {
                     Ok(EvalContext {should_return: false, value, ..}) => {
                         println!("{}", format_value(&value));
                     }
@@ -84,7 +84,15 @@ pub fn repl(shell: Arc<RwLock<Shell>>) {
     Ok(run_file(shell, &mut file))
 }*/
 
+/// Runs `file` with `shell` as the current shell, so `{...}` blocks called
+/// by builtins find its builtins too. An error stops the file and is returned
+/// as a `Value::Failure` without being printed, since this also runs the
+/// bodies of functions.
 pub fn run_file(shell: Arc<RwLock<Shell>>, file: &mut File) -> EvalContext {
+    crate::shell::run_in(shell.clone(), || run_statements(shell, file))
+}
+
+fn run_statements(shell: Arc<RwLock<Shell>>, file: &mut File) -> EvalContext {
     loop {
         match eval(shell.clone(), file) {
             Ok(EvalContext {should_return: false, ..}) => {
@@ -95,13 +103,32 @@ pub fn run_file(shell: Arc<RwLock<Shell>>, file: &mut File) -> EvalContext {
                 return ctx;
             }
             Err(msg) => {
-                println!("{}", msg);
                 return EvalContext::new(Value::Failure(msg));
             }
         }
     }
 }
 
+/// Runs every statement in `file` and returns the value of the last statement,
+/// or of the first `return`. Unlike `run_file`, errors are passed to the caller.
+pub fn eval_file(shell: Arc<RwLock<Shell>>, file: &mut File) -> Result<Value, String> {
+    crate::shell::run_in(shell.clone(), || eval_statements(shell, file))
+}
+
+fn eval_statements(shell: Arc<RwLock<Shell>>, file: &mut File) -> Result<Value, String> {
+    let mut last = Value::Null;
+    loop {
+        if file.statements.as_ref().map_or(true, |statements| statements.is_empty()) {
+            return Ok(last);
+        }
+        let ctx = eval(shell.clone(), file)?;
+        if ctx.should_return {
+            return Ok(ctx.value);
+        }
+        last = ctx.value;
+    }
+}
+
 pub struct EvalContext {
     value: Value,
     should_return: bool,
@@ -144,6 +171,9 @@ pub enum LoopState {
 }
 
 fn eval(shell: Arc<RwLock<Shell>>, input: &mut dyn Iterator<Item = Statement>) -> Result<EvalContext, String> {
+    if borrow!(shell).is_cancelled() {
+        return Err("cancelled".to_string());
+    }
     let next = input.next();
     match next {
         Some(Statement::Assignment(assignment)) => {
@@ -160,7 +190,9 @@ fn eval(shell: Arc<RwLock<Shell>>, input: &mut dyn Iterator<Item = Statement>) -
         }
         Some(Statement::FunctionDef(function)) => {
             let name = function.name.clone();
-            let function = crate::shell::function::Function::new(&function.name, function.args, function.body, shell.clone());
+            let doc = function.doc;
+            let mut function = crate::shell::function::Function::new(&function.name, function.args, function.body, shell.clone());
+            function.doc = doc;
             let mut borrowed_shell = borrow_mut!(shell);
             borrowed_shell.set_function(name, function);
         }
@@ -374,7 +406,7 @@ fn eval_pipeline(shell: Arc<RwLock<Shell>>, pipeline: &PipelinePart, arg: Option
         None => args,
     };
 
-    let borrowed_shell = borrow_mut!(shell);
+    let borrowed_shell = borrow!(shell);
     let value = if let Some(function) = borrowed_shell.get_function(name) {
         drop(borrowed_shell);
         match function.call(&args) {
@@ -401,7 +433,7 @@ fn eval_pipeline(shell: Arc<RwLock<Shell>>, pipeline: &PipelinePart, arg: Option
                         let ff = caat_rust::ForeignFunction::new(&command.name);
                         //println!("{:?}", command.arguments_as_value(shell.environment()));
                         drop(borrowed_shell);
-                        let return_value = match ff.call(&command.arguments_as_value(shell.clone())) {
+                        let return_value = match ff.call(&args) {
                             Value::Failure(msg) => {
                                 return Err(msg);
                             },
@@ -560,7 +592,8 @@ fn format_map(value: &Value) -> Vec<String> {
     }
 }
 
-fn format_value_file(value: &Value) -> String {
+/// Formats a value the way the `>` and `>>` redirects write it.
+pub fn format_value_file(value: &Value) -> String {
     match value {
         Value::Null => format!("()"),
         Value::String(string) => format!("{}", string),
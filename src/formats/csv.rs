@@ -0,0 +1,234 @@
+use caat_rust::Value;
+use std::collections::HashMap;
+
+
+/// How delimited text is read and written.
+#[derive(Clone, Copy)]
+pub struct Options {
+    pub delimiter: char,
+    /// The first record names the columns.
+    pub headers: bool,
+    /// Unquoted fields that look like numbers or booleans are read as them.
+    pub infer: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { delimiter: ',', headers: true, infer: true }
+    }
+}
+
+impl Options {
+    pub fn tsv() -> Self {
+        Options { delimiter: '\t', ..Options::default() }
+    }
+}
+
+/// A field as read, remembering whether it was quoted so that quoted fields
+/// are always kept as strings.
+struct Field {
+    text: String,
+    quoted: bool,
+}
+
+/// Splits text into records of fields. Quoted fields can hold the delimiter,
+/// newlines, and quotes written twice.
+fn parse_records(text: &str, delimiter: char) -> Result<Vec<Vec<Field>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = Field { text: String::new(), quoted: false };
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.text.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.text.push(c);
+                }
+                _ => field.text.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.text.is_empty() && !field.quoted => {
+                in_quotes = true;
+                field.quoted = true;
+            }
+            c if c == delimiter => record.push(std::mem::replace(&mut field, Field { text: String::new(), quoted: false })),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                record.push(std::mem::replace(&mut field, Field { text: String::new(), quoted: false }));
+                records.push(std::mem::take(&mut record));
+            }
+            _ => field.text.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!("csv: unterminated quoted field on line {}", line));
+    }
+    if !field.text.is_empty() || field.quoted || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Reads a field as a string if it was quoted, and guesses its type if not.
+fn infer(field: Field) -> Value {
+    if field.quoted {
+        Value::String(field.text)
+    } else {
+        super::infer(&field.text)
+    }
+}
+
+fn read_field(field: Field, options: &Options) -> Value {
+    if options.infer {
+        infer(field)
+    } else {
+        Value::String(field.text)
+    }
+}
+
+/// Parses delimited text. With headers the first record names the columns and
+/// every other record becomes a map, with `()` for missing fields. Without,
+/// every record becomes a list.
+pub fn from_str(text: &str, options: &Options) -> Result<Value, String> {
+    let mut records = parse_records(text, options.delimiter)?.into_iter();
+    if !options.headers {
+        let rows: Vec<Value> = records
+            .map(|record| Value::List(record.into_iter().map(|field| read_field(field, options)).collect()))
+            .collect();
+        return Ok(Value::List(rows.into()));
+    }
+    let header: Vec<String> = match records.next() {
+        Some(header) => header.into_iter().map(|field| field.text).collect(),
+        None => return Ok(Value::List(Vec::new().into())),
+    };
+    let mut rows = Vec::new();
+    for (i, record) in records.enumerate() {
+        if record.len() > header.len() {
+            return Err(format!("csv: record {} has {} fields but the header has {}", i + 1, record.len(), header.len()));
+        }
+        let mut map: HashMap<String, Value> = header.iter().map(|key| (key.clone(), Value::Null)).collect();
+        for (key, field) in header.iter().zip(record) {
+            map.insert(key.clone(), read_field(field, options));
+        }
+        rows.push(Value::Map(map, None));
+    }
+    Ok(Value::List(rows.into()))
+}
+
+fn write_field(value: &Value, delimiter: char) -> Result<String, String> {
+    let text = match value {
+        Value::Null => return Ok(String::new()),
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => return Ok(i.to_string()),
+        Value::Float(f) => return Ok(format!("{:?}", f)),
+        Value::Boolean(b) => return Ok(b.to_string()),
+        _ => return Err("csv: only numbers, strings, booleans and () can be written as fields".to_string()),
+    };
+    let needs_quotes = text.contains(delimiter)
+        || text.contains(['"', '\n', '\r'])
+        || text.trim() != text
+        || !matches!(super::infer(&text), Value::String(_));
+    if needs_quotes {
+        Ok(format!("\"{}\"", text.replace('"', "\"\"")))
+    } else {
+        Ok(text)
+    }
+}
+
+fn write_record(fields: &[&Value], delimiter: char) -> Result<String, String> {
+    let fields: Result<Vec<String>, String> = fields.iter().map(|field| write_field(field, delimiter)).collect();
+    Ok(fields?.join(&delimiter.to_string()) + "\n")
+}
+
+/// Writes a list of maps, with columns for every key in sorted order, or a
+/// list of lists. Strings that would read back as another type are quoted.
+/// The header is only written for maps.
+pub fn to_string(value: &Value, options: &Options) -> Result<String, String> {
+    let rows = match value {
+        Value::List(rows) => rows.to_vec(),
+        _ => return Err("csv: expected a list of maps or lists".to_string()),
+    };
+    let mut output = String::new();
+    if rows.iter().all(|row| matches!(row, Value::Map(_, _))) && !rows.is_empty() {
+        let mut header: Vec<String> = Vec::new();
+        for row in &rows {
+            if let Value::Map(map, _) = row {
+                for key in map.keys() {
+                    if !header.contains(key) {
+                        header.push(key.clone());
+                    }
+                }
+            }
+        }
+        header.sort();
+        if options.headers {
+            let keys: Vec<Value> = header.iter().map(|key| Value::String(key.clone())).collect();
+            output.push_str(&write_record(&keys.iter().collect::<Vec<_>>(), options.delimiter)?);
+        }
+        let null = Value::Null;
+        for row in &rows {
+            if let Value::Map(map, _) = row {
+                let fields: Vec<&Value> = header.iter().map(|key| map.get(key).unwrap_or(&null)).collect();
+                output.push_str(&write_record(&fields, options.delimiter)?);
+            }
+        }
+        return Ok(output);
+    }
+    for row in &rows {
+        let record = match row {
+            Value::List(fields) => write_record(&fields.iter().collect::<Vec<_>>(), options.delimiter)?,
+            Value::Map(_, _) => return Err("csv: can't mix maps and lists".to_string()),
+            field => write_record(&[field], options.delimiter)?,
+        };
+        output.push_str(&record);
+    }
+    Ok(output)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_headers() {
+        let text = "name,size,note\ncaat,3,\"a, b\"\n\"007\",2.5,\n";
+        let rows = match from_str(text, &Options::default()).unwrap() {
+            Value::List(rows) => rows.to_vec(),
+            _ => panic!("expected a list"),
+        };
+        let row = |i: usize, key: &str| match &rows[i] {
+            Value::Map(map, _) => map.get(key).cloned().unwrap(),
+            _ => panic!("expected a map"),
+        };
+        assert_eq!(row(0, "size"), Value::Integer(3));
+        assert_eq!(row(0, "note"), Value::String("a, b".to_string()));
+        assert_eq!(row(1, "name"), Value::String("007".to_string()));
+        assert_eq!(row(1, "size"), Value::Float(2.5));
+        assert_eq!(row(1, "note"), Value::Null);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = "name\tsize\n\"007\"\t3\n\"say \"\"hi\"\"\"\t\n";
+        let value = from_str(text, &Options::tsv()).unwrap();
+        assert_eq!(to_string(&value, &Options::tsv()).unwrap(), text);
+    }
+
+    #[test]
+    fn test_unterminated() {
+        assert!(from_str("a,\"b\n", &Options::default()).is_err());
+    }
+}
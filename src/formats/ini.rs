@@ -0,0 +1,129 @@
+use caat_rust::Value;
+use std::collections::{HashMap, HashSet};
+
+
+fn unquote(text: &str) -> Option<&str> {
+    let quoted = |q: char| text.len() >= 2 && text.starts_with(q) && text.ends_with(q);
+    if quoted('"') || quoted('\'') {
+        Some(&text[1..text.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Cuts a `;` or `#` comment off the end of a line. The comment character
+/// has to start the line or follow whitespace, and ones inside a quoted
+/// value are kept, so `color = "#fff"` and `url = a#b` are left alone.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in line.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if (c == '"' || c == '\'') && (previous.is_whitespace() || previous == '=' || previous == ':') => quote = Some(c),
+            None if (c == ';' || c == '#') && previous.is_whitespace() => return &line[..i],
+            None => {}
+        }
+        previous = c;
+    }
+    line
+}
+
+/// Parses an INI file into a map. Keys before the first `[section]` are at
+/// the top level and each section becomes a map of its own. Quoted values are
+/// strings and unquoted ones have their type guessed. `;` and `#` start
+/// comments, and a later key replaces an earlier one. A section can't have
+/// the name of a top-level key.
+pub fn from_str(text: &str) -> Result<Value, String> {
+    let mut root: HashMap<String, Value> = HashMap::new();
+    let mut section: Option<(String, HashMap<String, Value>)> = None;
+    let mut sections = HashSet::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            let name = match line.strip_suffix(']') {
+                Some(name) => name[1..].trim().to_string(),
+                None => return Err(format!("ini: line {}: unclosed section header", i + 1)),
+            };
+            if let Some((name, map)) = section.take() {
+                root.insert(name, Value::Map(map, None));
+            }
+            if root.contains_key(&name) && !sections.contains(&name) {
+                return Err(format!("ini: line {}: section [{}] has the same name as a key", i + 1, name));
+            }
+            sections.insert(name.clone());
+            let map = match root.remove(&name) {
+                Some(Value::Map(map, _)) => map,
+                _ => HashMap::new(),
+            };
+            section = Some((name, map));
+            continue;
+        }
+        let (key, value) = match line.find(['=', ':']) {
+            Some(split) => (line[..split].trim(), line[split + 1..].trim()),
+            None => return Err(format!("ini: line {}: expected key = value", i + 1)),
+        };
+        if key.is_empty() {
+            return Err(format!("ini: line {}: missing key", i + 1));
+        }
+        let value = match unquote(value) {
+            Some(value) => Value::String(value.to_string()),
+            None => super::infer(value),
+        };
+        match &mut section {
+            Some((_, map)) => map.insert(key.to_string(), value),
+            None => root.insert(key.to_string(), value),
+        };
+    }
+    if let Some((name, map)) = section {
+        root.insert(name, Value::Map(map, None));
+    }
+    Ok(Value::Map(root, None))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sections() {
+        let text = "; settings\nname = caat\n\n[server]\nport: 8080\nhost = \"0.0.0.0\"\n[server]\ndebug = true\n";
+        let root = match from_str(text).unwrap() {
+            Value::Map(map, _) => map,
+            _ => panic!("expected a map"),
+        };
+        assert_eq!(root.get("name"), Some(&Value::String("caat".to_string())));
+        let server = match root.get("server") {
+            Some(Value::Map(map, _)) => map,
+            _ => panic!("expected a section"),
+        };
+        assert_eq!(server.get("port"), Some(&Value::Integer(8080)));
+        assert_eq!(server.get("host"), Some(&Value::String("0.0.0.0".to_string())));
+        assert_eq!(server.get("debug"), Some(&Value::Boolean(true)));
+        assert!(from_str("[broken\n").is_err());
+    }
+
+    #[test]
+    fn test_section_named_like_a_key() {
+        assert!(from_str("server = 1\n[server]\nport = 80\n").is_err());
+    }
+
+    #[test]
+    fn test_inline_comments() {
+        let text = "port = 80 ; web\ncolor = \"#fff ; not a comment\" # hex\nurl = a#b\nowner = it's me ; who\n[main] ; first\nx = 1\n";
+        let root = match from_str(text).unwrap() {
+            Value::Map(map, _) => map,
+            _ => panic!("expected a map"),
+        };
+        assert_eq!(root.get("port"), Some(&Value::Integer(80)));
+        assert_eq!(root.get("color"), Some(&Value::String("#fff ; not a comment".to_string())));
+        assert_eq!(root.get("url"), Some(&Value::String("a#b".to_string())));
+        assert_eq!(root.get("owner"), Some(&Value::String("it's me".to_string())));
+        assert!(matches!(root.get("main"), Some(Value::Map(..))));
+    }
+}
@@ -0,0 +1,112 @@
+use caat_rust::Value;
+use serde::Serialize;
+use serde_json::{Map, Number};
+
+
+/// Converts a value to JSON.
+///
+/// `Null` becomes `null` and a map's display template is dropped. A failure
+/// becomes an object with a single `failure` key holding its message. Functions
+/// have no JSON form and are an error.
+pub fn to_json(value: &Value) -> Result<serde_json::Value, String> {
+    let json = match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Boolean(b) => serde_json::Value::Bool(*b),
+        Value::Integer(i) => serde_json::Value::Number(Number::from(*i)),
+        Value::Float(f) => match Number::from_f64(*f) {
+            Some(n) => serde_json::Value::Number(n),
+            None => serde_json::Value::Null,
+        },
+        Value::String(s) => serde_json::Value::String(s.clone()),
+        Value::List(list) => {
+            let mut output = Vec::new();
+            for value in list.iter() {
+                output.push(to_json(value)?);
+            }
+            serde_json::Value::Array(output)
+        }
+        Value::Map(map, _) => {
+            let mut output = Map::new();
+            for (key, value) in map.iter() {
+                output.insert(key.clone(), to_json(value)?);
+            }
+            serde_json::Value::Object(output)
+        }
+        Value::Failure(msg) => {
+            let mut output = Map::new();
+            output.insert(String::from("failure"), serde_json::Value::String(msg.clone()));
+            serde_json::Value::Object(output)
+        }
+        Value::CAATFunction(_) => return Err("json: functions can't be converted to JSON".to_string()),
+    };
+    Ok(json)
+}
+
+/// Converts JSON to a value. Numbers that fit in an `i64` become integers and
+/// all others become floats. Objects become maps without a display template.
+pub fn from_json(json: &serde_json::Value) -> Value {
+    match json {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Boolean(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        serde_json::Value::Array(list) => Value::List(list.iter().map(from_json).collect::<Vec<Value>>().into()),
+        serde_json::Value::Object(map) => {
+            let map = map.iter().map(|(key, value)| (key.clone(), from_json(value))).collect();
+            Value::Map(map, None)
+        }
+    }
+}
+
+pub fn to_string(value: &Value, pretty: bool) -> Result<String, String> {
+    let json = to_json(value)?;
+    let text = if pretty {
+        serde_json::to_string_pretty(&json)
+    } else {
+        serde_json::to_string(&json)
+    };
+    text.map_err(|e| format!("json: {}", e))
+}
+
+/// Writes JSON indented by `indent` spaces per level.
+pub fn to_string_indented(value: &Value, indent: usize) -> Result<String, String> {
+    let json = to_json(value)?;
+    let indent = " ".repeat(indent);
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+    let mut output = Vec::new();
+    let mut serializer = serde_json::Serializer::with_formatter(&mut output, formatter);
+    json.serialize(&mut serializer).map_err(|e| format!("json: {}", e))?;
+    String::from_utf8(output).map_err(|e| format!("json: {}", e))
+}
+
+pub fn from_str(text: &str) -> Result<Value, String> {
+    let json: serde_json::Value = serde_json::from_str(text).map_err(|e| format!("json: {}", e))?;
+    Ok(from_json(&json))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_round_trip() {
+        let mut map = HashMap::new();
+        map.insert("name".to_string(), Value::String("caat".to_string()));
+        map.insert("sizes".to_string(), Value::List(vec![Value::Integer(1), Value::Float(2.5), Value::Null].into()));
+        map.insert("ok".to_string(), Value::Boolean(true));
+        let value = Value::Map(map, None);
+        let text = to_string(&value, false).unwrap();
+        assert_eq!(from_str(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_failure() {
+        let text = to_string(&Value::Failure("bad".to_string()), false).unwrap();
+        assert_eq!(text, r#"{"failure":"bad"}"#);
+    }
+}
@@ -0,0 +1,32 @@
+//! Conversions between shell values and text formats.
+
+pub mod csv;
+pub mod ini;
+pub mod json;
+pub mod toml;
+
+use caat_rust::Value;
+
+
+/// Guesses the type of text from a file: empty is `()`, then booleans,
+/// integers and floats, and anything else is a string.
+pub fn infer(text: &str) -> Value {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return Value::Null;
+    }
+    match trimmed {
+        "true" => return Value::Boolean(true),
+        "false" => return Value::Boolean(false),
+        _ => {}
+    }
+    if let Ok(i) = trimmed.parse::<i64>() {
+        return Value::Integer(i);
+    }
+    if trimmed.chars().any(|c| c.is_ascii_digit()) && trimmed.chars().all(|c| c.is_ascii_digit() || "+-.eE".contains(c)) {
+        if let Ok(f) = trimmed.parse::<f64>() {
+            return Value::Float(f);
+        }
+    }
+    Value::String(text.to_string())
+}
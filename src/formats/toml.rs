@@ -0,0 +1,86 @@
+use caat_rust::Value;
+
+
+/// Converts TOML to a value. Tables become maps without a display template
+/// and datetimes become strings in their TOML form.
+pub fn from_toml(toml: &toml::Value) -> Value {
+    match toml {
+        toml::Value::String(s) => Value::String(s.clone()),
+        toml::Value::Integer(i) => Value::Integer(*i),
+        toml::Value::Float(f) => Value::Float(*f),
+        toml::Value::Boolean(b) => Value::Boolean(*b),
+        toml::Value::Datetime(datetime) => Value::String(datetime.to_string()),
+        toml::Value::Array(list) => Value::List(list.iter().map(from_toml).collect::<Vec<Value>>().into()),
+        toml::Value::Table(table) => {
+            let map = table.iter().map(|(key, value)| (key.clone(), from_toml(value))).collect();
+            Value::Map(map, None)
+        }
+    }
+}
+
+/// Converts a value to TOML. TOML has no null, so `()` entries of a map are
+/// left out and `()` anywhere else is an error, as are failures and functions.
+pub fn to_toml(value: &Value) -> Result<toml::Value, String> {
+    let toml = match value {
+        Value::String(s) => toml::Value::String(s.clone()),
+        Value::Integer(i) => toml::Value::Integer(*i),
+        Value::Float(f) => toml::Value::Float(*f),
+        Value::Boolean(b) => toml::Value::Boolean(*b),
+        Value::List(list) => {
+            let mut output = Vec::new();
+            for value in list.iter() {
+                output.push(to_toml(value)?);
+            }
+            toml::Value::Array(output)
+        }
+        Value::Map(map, _) => {
+            let mut output = toml::Table::new();
+            for (key, value) in map.iter() {
+                if let Value::Null = value {
+                    continue;
+                }
+                output.insert(key.clone(), to_toml(value)?);
+            }
+            toml::Value::Table(output)
+        }
+        Value::Null => return Err("toml: () has no TOML form".to_string()),
+        Value::Failure(msg) => return Err(format!("toml: can't convert a failure to TOML: {}", msg)),
+        Value::CAATFunction(_) => return Err("toml: functions can't be converted to TOML".to_string()),
+    };
+    Ok(toml)
+}
+
+/// Writes a map as a TOML document.
+pub fn to_string(value: &Value) -> Result<String, String> {
+    match to_toml(value)? {
+        toml::Value::Table(table) => toml::to_string(&table).map_err(|e| format!("toml: {}", e)),
+        _ => Err("toml: only a map can be written as a TOML document".to_string()),
+    }
+}
+
+pub fn from_str(text: &str) -> Result<Value, String> {
+    let table: toml::Table = toml::from_str(text).map_err(|e| format!("toml: {}", e))?;
+    Ok(from_toml(&toml::Value::Table(table)))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = from_str("name = \"caat\"\nsizes = [1, 2]\n\n[paths]\nhome = \"/home\"\n").unwrap();
+        let text = to_string(&value).unwrap();
+        assert_eq!(from_str(&text).unwrap(), value);
+    }
+
+    #[test]
+    fn test_datetime() {
+        let value = from_str("when = 1979-05-27T07:32:00Z\n").unwrap();
+        match value {
+            Value::Map(map, _) => assert_eq!(map.get("when"), Some(&Value::String("1979-05-27T07:32:00Z".to_string()))),
+            _ => panic!("expected a map"),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use caat_rust::{Caat, Value};
+use crate::builtins::Builtin;
+use crate::parser;
+use crate::shell::Shell;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Parse(String),
+    Eval(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Parse(msg) => write!(f, "parse error: {}", msg),
+            Error::Eval(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// A handle to a shell for running CAAT code from Rust.
+///
+/// Cloning the handle shares the same shell, so variables and functions defined
+/// by one call are visible to the next.
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    shell: Arc<RwLock<Shell>>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::with_shell(Arc::new(RwLock::new(Shell::new())))
+    }
+    pub fn with_shell(shell: Arc<RwLock<Shell>>) -> Self {
+        Interpreter {
+            shell,
+        }
+    }
+    pub fn shell(&self) -> Arc<RwLock<Shell>> {
+        self.shell.clone()
+    }
+
+    /// Parses and runs `source`, returning the value of its last statement or
+    /// of the first top level `return`.
+    pub fn eval_str(&self, source: &str) -> Result<Value, Error> {
+        let mut file = parser::parse_file(source).map_err(|e| Error::Parse(e.to_string()))?;
+        crate::eval::eval_file(self.shell.clone(), &mut file).map_err(Error::Eval)
+    }
+
+    /// Runs the script at `path` in this interpreter's shell.
+    pub fn run_file<P: AsRef<Path>>(&self, path: P) -> Result<Value, Error> {
+        let source = std::fs::read_to_string(path)?;
+        self.eval_str(&source)
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<Value> {
+        let borrowed_shell = borrow!(self.shell);
+        let value = borrowed_shell.environment().get(name).cloned();
+        value
+    }
+
+    /// Sets a global variable, visible to every script run afterwards.
+    pub fn set_var(&self, name: &str, value: Value) {
+        let mut borrowed_shell = borrow_mut!(self.shell);
+        borrowed_shell.environment_mut().set_global(name.to_string(), value);
+    }
+
+    /// Adds a builtin command, replacing any builtin with the same name.
+    pub fn register_builtin(&self, builtin: Arc<dyn Builtin>) {
+        let mut borrowed_shell = borrow_mut!(self.shell);
+        borrowed_shell.builtins_mut().register(builtin);
+    }
+
+    pub fn remove_builtin(&self, name: &str) {
+        let mut borrowed_shell = borrow_mut!(self.shell);
+        borrowed_shell.builtins_mut().remove(name);
+    }
+
+    /// Registers the executables in the plugin directory as commands.
+    pub fn load_plugins(&self) -> Result<(), Error> {
+        let mut borrowed_shell = borrow_mut!(self.shell);
+        crate::builtins::plugin::load_plugins(&mut borrowed_shell).map_err(Error::Eval)
+    }
+
+    /// Calls a `function` defined by a previously run script.
+    pub fn call_function(&self, name: &str, args: &[Value]) -> Result<Value, Error> {
+        let borrowed_shell = borrow!(self.shell);
+        let function = borrowed_shell.get_function(name);
+        drop(borrowed_shell);
+        match function {
+            Some(function) => match function.call(args) {
+                Value::Failure(msg) => Err(Error::Eval(msg)),
+                value => Ok(value),
+            },
+            None => Err(Error::Eval(format!("{} is not a function", name))),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_str() {
+        let interpreter = Interpreter::new();
+        assert_eq!(interpreter.eval_str("x = 5\necho $x").unwrap(), Value::Integer(5));
+    }
+
+    #[test]
+    fn test_variables() {
+        let interpreter = Interpreter::new();
+        interpreter.set_var("name", Value::String("caat".to_string()));
+        assert_eq!(interpreter.get_var("name"), Some(Value::String("caat".to_string())));
+        interpreter.eval_str("name = \"shell\"").unwrap();
+        assert_eq!(interpreter.get_var("name"), Some(Value::String("shell".to_string())));
+    }
+
+    #[test]
+    fn test_register_builtin() {
+        use crate::builtins::FnBuiltin;
+        let interpreter = Interpreter::new();
+        interpreter.register_builtin(Arc::new(FnBuiltin::new("answer", "answer", "Returns 42", |_, _| Ok(Value::Integer(42)))));
+        assert_eq!(interpreter.eval_str("answer").unwrap(), Value::Integer(42));
+        interpreter.register_builtin(Arc::new(FnBuiltin::new("echo", "echo value", "Always returns 0", |_, _| Ok(Value::Integer(0)))));
+        assert_eq!(interpreter.eval_str("echo 5").unwrap(), Value::Integer(0));
+        interpreter.remove_builtin("echo");
+        assert!(borrow!(interpreter.shell).builtins().get("echo").is_none());
+    }
+
+    #[test]
+    fn test_builtins_in_blocks() {
+        use crate::builtins::FnBuiltin;
+        let interpreter = Interpreter::new();
+        interpreter.register_builtin(Arc::new(FnBuiltin::new("answer", "answer", "Returns 42", |_, _| Ok(Value::Integer(42)))));
+        let answers = Value::List(vec![Value::Integer(42), Value::Integer(42)].into());
+        assert_eq!(interpreter.eval_str("map [1, 2] {answer}").unwrap(), answers);
+        assert_eq!(interpreter.eval_str("function f() {\n    return map [1, 2] {answer}\n}\nf").unwrap(), answers);
+        // jobs needs the shell, which a block only has if it was set as current.
+        let no_jobs = Value::List(vec![Value::List(Vec::new().into())].into());
+        assert_eq!(interpreter.eval_str("map [1] {jobs}").unwrap(), no_jobs);
+    }
+
+    #[test]
+    fn test_call_function() {
+        let interpreter = Interpreter::new();
+        interpreter.eval_str("function double(x) {\n    return add $x $x\n}\n").unwrap();
+        assert_eq!(interpreter.call_function("double", &[Value::Integer(4)]).unwrap(), Value::Integer(8));
+    }
+
+    #[test]
+    fn test_describe_function() {
+        let interpreter = Interpreter::new();
+        interpreter.eval_str("# Doubles a number.\nfunction double(x) {\n    return add $x $x\n}\n").unwrap();
+        let description = match interpreter.eval_str("describe \"double\"").unwrap() {
+            Value::Map(map, _) => map,
+            value => panic!("expected a map, got {:?}", value),
+        };
+        assert_eq!(description.get("type"), Some(&Value::String("function".to_string())));
+        assert_eq!(description.get("signature"), Some(&Value::String("double x".to_string())));
+        assert_eq!(description.get("help"), Some(&Value::String("Doubles a number.".to_string())));
+    }
+}
@@ -0,0 +1,27 @@
+//! CAAT Shell as a library.
+//!
+//! The binary is a thin wrapper around this crate. Other programs can embed
+//! the interpreter through [`Interpreter`]:
+//!
+//! ```no_run
+//! use caat_shell::{Interpreter, Value};
+//!
+//! let interpreter = Interpreter::new();
+//! interpreter.set_var("name", Value::String("world".to_string()));
+//! let greeting = interpreter.eval_str(r#"echo "hello " ++ $name"#).unwrap();
+//! ```
+
+pub mod parser;
+pub mod eval;
+#[macro_use]
+pub mod shell;
+pub mod builtins;
+pub mod check;
+pub mod formats;
+pub mod lsp;
+pub mod server;
+pub mod test_runner;
+mod interpreter;
+
+pub use caat_rust::Value;
+pub use interpreter::{Error, Interpreter};
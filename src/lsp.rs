@@ -0,0 +1,497 @@
+//! A language server for CAAT scripts, spoken over stdin and stdout.
+//!
+//! Documents are synced whole. Positions come from `parse_file_with_spans`,
+//! and the spans of the last version that parsed are kept, together with the
+//! text they were found in, while the user is in the middle of an edit, so
+//! completion and navigation keep working.
+
+use crate::builtins::BuiltinRegistry;
+use crate::check::{self, Severity};
+use crate::parser::{self, Span, SpanKind};
+use crate::shell::{Environment, Shell};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+
+
+/// Spans and the text they point into.
+struct Parsed {
+    text: String,
+    spans: Vec<Span>,
+}
+
+struct Document {
+    text: String,
+    parsed: Parsed,
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    builtins: BuiltinRegistry,
+    /// The names the checker accepts as commands. Plugins are only listed,
+    /// never run, so they have no signature or help here.
+    commands: HashSet<String>,
+}
+
+/// Converts a byte offset to an LSP position, which counts UTF-16 units. An
+/// offset past the end or inside a character is moved back to the nearest
+/// character boundary.
+fn position(source: &str, offset: usize) -> Value {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    let before = &source[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let character = source[line_start..offset].encode_utf16().count();
+    json!({"line": line, "character": character})
+}
+
+fn range(source: &str, start: usize, end: usize) -> Value {
+    json!({"start": position(source, start), "end": position(source, end)})
+}
+
+fn line_start(source: &str, line: usize) -> usize {
+    let mut start = 0;
+    for _ in 0..line {
+        match source[start..].find('\n') {
+            Some(i) => start += i + 1,
+            None => return source.len(),
+        }
+    }
+    start
+}
+
+/// Converts an LSP position to a byte offset.
+fn offset(source: &str, position: &Value) -> usize {
+    let line = position["line"].as_u64().unwrap_or(0) as usize;
+    let character = position["character"].as_u64().unwrap_or(0) as usize;
+    let start = line_start(source, line);
+    let mut units = 0;
+    for (i, c) in source[start..].char_indices() {
+        if units >= character || c == '\n' {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    source.len()
+}
+
+/// The name under `offset`.
+fn span_at(spans: &[Span], offset: usize) -> Option<&Span> {
+    spans.iter().find(|span| span.kind != SpanKind::Statement && span.start <= offset && offset <= span.end)
+}
+
+/// The innermost function whose definition contains `offset`.
+fn enclosing_function(spans: &[Span], offset: usize) -> Option<(usize, usize)> {
+    spans.iter()
+        .filter(|span| span.kind == SpanKind::Function && span.extent.0 <= offset && offset < span.extent.1)
+        .map(|span| span.extent)
+        .max_by_key(|extent| extent.0)
+}
+
+/// Finds where the name under `offset` is defined. A variable resolves to its
+/// first assignment or parameter in the same function, then at the top level.
+fn definition(spans: &[Span], offset: usize) -> Option<&Span> {
+    let span = span_at(spans, offset)?;
+    if matches!(span.kind, SpanKind::Command | SpanKind::Function) {
+        let function = spans.iter().find(|other| other.kind == SpanKind::Function && other.name == span.name);
+        if function.is_some() {
+            return function;
+        }
+    }
+    let scope = enclosing_function(spans, span.start);
+    let mut candidates = spans.iter()
+        .filter(|other| matches!(other.kind, SpanKind::Parameter | SpanKind::Assignment) && other.name == span.name);
+    candidates.clone().find(|other| enclosing_function(spans, other.start) == scope)
+        .or_else(|| candidates.find(|other| enclosing_function(spans, other.start).is_none()))
+}
+
+fn diagnostic(source: &str, start: usize, end: usize, severity: Severity, message: &str) -> Value {
+    let severity = match severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    json!({
+        "range": range(source, start, end),
+        "severity": severity,
+        "source": "caat_shell",
+        "message": message,
+    })
+}
+
+impl Server {
+    fn new() -> Self {
+        Server {
+            documents: HashMap::new(),
+            builtins: Shell::new().builtins().clone(),
+            commands: check::known_commands(),
+        }
+    }
+
+    /// Stores a new version of a document and returns its diagnostics.
+    fn update(&mut self, uri: &str, text: String) -> Value {
+        let mut diagnostics = Vec::new();
+        let spans = match parser::parse_file_with_spans(&text) {
+            Ok((file, spans)) => {
+                for problem in check::check_file(&file, &self.commands) {
+                    let (start, end) = problem.range(&spans).unwrap_or((0, 0));
+                    diagnostics.push(diagnostic(&text, start, end, problem.severity, &problem.to_string()));
+                }
+                Some(spans)
+            }
+            Err(e) => {
+                let start = e.location.offset.min(text.len());
+                let message = format!("parse error: expected {}", e.expected);
+                diagnostics.push(diagnostic(&text, start, start, Severity::Error, &message));
+                None
+            }
+        };
+        let parsed = match (spans, self.documents.remove(uri)) {
+            (Some(spans), _) => Parsed { text: text.clone(), spans },
+            (None, Some(old)) => old.parsed,
+            (None, None) => Parsed { text: String::new(), spans: Vec::new() },
+        };
+        self.documents.insert(uri.to_string(), Document { text, parsed });
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics},
+        })
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Option<Value> {
+        let uri = params["textDocument"]["uri"].as_str()?.to_string();
+        match method {
+            "textDocument/didOpen" => {
+                let text = params["textDocument"]["text"].as_str()?.to_string();
+                Some(self.update(&uri, text))
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"].as_array()?.last()?["text"].as_str()?.to_string();
+                Some(self.update(&uri, text))
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                Some(json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": {"uri": uri, "diagnostics": []},
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    /// Answers a request, or returns `None` if the method is not supported.
+    fn handle_request(&self, method: &str, params: &Value) -> Option<Value> {
+        match method {
+            "initialize" => return Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {"triggerCharacters": ["$"]},
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": {"name": "caat_shell"},
+            })),
+            "shutdown" => return Some(Value::Null),
+            "textDocument/definition" | "textDocument/hover" | "textDocument/completion" | "textDocument/documentSymbol" => {}
+            _ => return None,
+        }
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let document = match self.documents.get(uri) {
+            Some(document) => document,
+            None => return Some(Value::Null),
+        };
+        let offset = offset(&document.text, &params["position"]);
+        Some(match method {
+            "textDocument/definition" => self.definition(uri, document, offset),
+            "textDocument/hover" => self.hover(document, offset),
+            "textDocument/completion" => self.completion(document),
+            _ => self.symbols(document),
+        })
+    }
+
+    fn definition(&self, uri: &str, document: &Document, offset: usize) -> Value {
+        let parsed = &document.parsed;
+        match definition(&parsed.spans, offset) {
+            Some(span) => json!({
+                "uri": uri,
+                "range": range(&parsed.text, span.start, span.end),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, document: &Document, offset: usize) -> Value {
+        let parsed = &document.parsed;
+        let span = match span_at(&parsed.spans, offset) {
+            Some(span) if matches!(span.kind, SpanKind::Command | SpanKind::Function) => span,
+            _ => return Value::Null,
+        };
+        let function = parsed.spans.iter()
+            .find(|other| other.kind == SpanKind::Function && other.name == span.name);
+        let contents = if let Some(function) = function {
+            let header = parsed.text.get(function.extent.0..).and_then(|rest| rest.lines().next()).unwrap_or("");
+            format!("```caat\n{}\n```", header.trim_end().trim_end_matches('{').trim_end())
+        } else if let Some(builtin) = self.builtins.get(&span.name) {
+            format!("```caat\n{}\n```\n{}", builtin.signature(), builtin.help())
+        } else {
+            return Value::Null;
+        };
+        json!({"contents": {"kind": "markdown", "value": contents}})
+    }
+
+    fn completion(&self, document: &Document) -> Value {
+        let mut items = Vec::new();
+        for builtin in self.builtins.list() {
+            items.push(json!({
+                "label": builtin.name(),
+                "kind": 3,
+                "detail": builtin.signature(),
+                "documentation": builtin.help(),
+            }));
+        }
+        let mut plugins: Vec<&String> = self.commands.iter().filter(|name| self.builtins.get(name).is_none()).collect();
+        plugins.sort();
+        for name in plugins {
+            items.push(json!({"label": name, "kind": 3}));
+        }
+        let mut seen = HashSet::new();
+        for span in &document.parsed.spans {
+            let kind = match span.kind {
+                SpanKind::Function => 3,
+                SpanKind::Parameter | SpanKind::Assignment => 6,
+                _ => continue,
+            };
+            if seen.insert(span.name.clone()) {
+                items.push(json!({"label": span.name, "kind": kind}));
+            }
+        }
+        let mut globals: Vec<String> = Environment::create_global().into_keys().collect();
+        globals.sort();
+        for name in globals {
+            if seen.insert(name.clone()) {
+                items.push(json!({"label": name, "kind": 6}));
+            }
+        }
+        Value::Array(items)
+    }
+
+    /// Functions, and the variables assigned at the top level.
+    fn symbols(&self, document: &Document) -> Value {
+        let Parsed { text, spans } = &document.parsed;
+        let mut seen = HashSet::new();
+        let mut symbols = Vec::new();
+        for span in spans {
+            let kind = match span.kind {
+                SpanKind::Function => 12,
+                SpanKind::Assignment if enclosing_function(spans, span.start).is_none() => 13,
+                _ => continue,
+            };
+            if seen.insert((span.name.clone(), kind)) {
+                symbols.push(json!({
+                    "name": span.name,
+                    "kind": kind,
+                    "range": range(text, span.extent.0, span.extent.1),
+                    "selectionRange": range(text, span.start, span.end),
+                }));
+            }
+        }
+        Value::Array(symbols)
+    }
+}
+
+/// Reads the body of the next message, or `None` at the end of the input.
+fn read_body<R: BufRead>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "lsp: missing Content-Length"))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(body))
+}
+
+fn write_message<W: Write>(writer: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    writer.flush()
+}
+
+/// Answers messages from `reader` until the client sends `exit` or closes it.
+pub fn serve<R: BufRead, W: Write>(mut reader: R, mut writer: W) -> io::Result<()> {
+    let mut server = Server::new();
+    while let Some(body) = read_body(&mut reader)? {
+        let message: Value = match serde_json::from_slice(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32700, "message": format!("parse error: {}", e)},
+                });
+                write_message(&mut writer, &response)?;
+                continue;
+            }
+        };
+        let method = match message["method"].as_str() {
+            Some(method) => method,
+            None => continue,
+        };
+        let params = &message["params"];
+        match message.get("id") {
+            Some(id) => {
+                let response = match server.handle_request(method, params) {
+                    Some(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                    None => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": {"code": -32601, "message": format!("unknown method {}", method)},
+                    }),
+                };
+                write_message(&mut writer, &response)?;
+            }
+            None if method == "exit" => return Ok(()),
+            None => {
+                if let Some(notification) = server.handle_notification(method, params) {
+                    write_message(&mut writer, &notification)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn serve_stdio() -> io::Result<()> {
+    let stdin = io::stdin();
+    serve(stdin.lock(), io::stdout())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_message<R: BufRead>(reader: &mut R) -> io::Result<Option<Value>> {
+        Ok(read_body(reader)?.map(|body| serde_json::from_slice(&body).unwrap()))
+    }
+
+    const SOURCE: &str = "x = 1\nfunction f(a) {\n    return add $a $x\n}\ny = f 2\n";
+
+    fn spans() -> Vec<Span> {
+        parser::parse_file_with_spans(SOURCE).unwrap().1
+    }
+
+    #[test]
+    fn test_definition() {
+        let spans = spans();
+        let call = SOURCE.rfind("f 2").unwrap();
+        assert_eq!(definition(&spans, call).map(|span| span.start), Some(SOURCE.find("f(a)").unwrap()));
+        let parameter = SOURCE.find("$a").unwrap() + 1;
+        assert_eq!(definition(&spans, parameter).map(|span| span.kind), Some(SpanKind::Parameter));
+        let global = SOURCE.find("$x").unwrap() + 1;
+        assert_eq!(definition(&spans, global).map(|span| span.start), Some(0));
+    }
+
+    #[test]
+    fn test_positions() {
+        let source = "a\nbé c\n";
+        let offset_of_c = source.find('c').unwrap();
+        assert_eq!(position(source, offset_of_c), json!({"line": 1, "character": 3}));
+        assert_eq!(offset(source, &json!({"line": 1, "character": 3})), offset_of_c);
+    }
+
+    #[test]
+    fn test_session() {
+        let mut input = Vec::new();
+        let messages = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "textDocument/didOpen", "params": {"textDocument": {"uri": "file:///t.caat", "text": "echo $nope\n"}}}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"}),
+            json!({"jsonrpc": "2.0", "method": "exit"}),
+        ];
+        for message in &messages {
+            write_message(&mut input, message).unwrap();
+        }
+        let mut output = Vec::new();
+        serve(io::Cursor::new(input), &mut output).unwrap();
+        let mut reader = io::Cursor::new(output);
+        let initialized = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(initialized["result"]["capabilities"]["hoverProvider"], json!(true));
+        let diagnostics = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(diagnostics["params"]["diagnostics"][0]["message"], json!("warning: undefined variable $nope"));
+        assert_eq!(diagnostics["params"]["diagnostics"][0]["range"]["start"], json!({"line": 0, "character": 5}));
+        let shutdown = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(shutdown["id"], json!(2));
+    }
+
+    #[test]
+    fn test_malformed_message() {
+        let mut input = Vec::new();
+        write!(input, "Content-Length: 8\r\n\r\n{{\"id\": 1").unwrap();
+        write_message(&mut input, &json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"})).unwrap();
+        let mut output = Vec::new();
+        serve(io::Cursor::new(input), &mut output).unwrap();
+        let mut reader = io::Cursor::new(output);
+        let error = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(error["error"]["code"], json!(-32700));
+        assert_eq!(error["id"], Value::Null);
+        let shutdown = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(shutdown["id"], json!(2));
+    }
+
+    #[test]
+    fn test_diagnostics_use_spans() {
+        let mut server = Server::new();
+        let text = "x = \"$nope\"\necho $nope\n";
+        let diagnostics = server.update("file:///t.caat", text.to_string());
+        assert_eq!(diagnostics["params"]["diagnostics"][0]["range"], json!({
+            "start": {"line": 1, "character": 5},
+            "end": {"line": 1, "character": 10},
+        }));
+    }
+
+    #[test]
+    fn test_plugin_commands() {
+        let mut server = Server {
+            documents: HashMap::new(),
+            builtins: Shell::new().builtins().clone(),
+            commands: HashSet::from([String::from("greet")]),
+        };
+        let uri = "file:///t.caat";
+        let diagnostics = server.update(uri, "greet world\n".to_string());
+        assert_eq!(diagnostics["params"]["diagnostics"], json!([]));
+        let completion = server.completion(&server.documents[uri]);
+        assert!(completion.as_array().unwrap().iter().any(|item| item["label"] == json!("greet")));
+    }
+
+    #[test]
+    fn test_stale_spans() {
+        let mut server = Server::new();
+        let uri = "file:///t.caat";
+        server.update(uri, "function long_name() {\n    return 1\n}\nlong_name\n".to_string());
+        server.update(uri, "function é(".to_string());
+        let document = &server.documents[uri];
+        assert_eq!(document.text, "function é(");
+        let call = document.parsed.text.rfind("long_name").unwrap();
+        let hover = server.hover(document, call);
+        assert_eq!(hover["contents"]["value"], json!("```caat\nfunction long_name()\n```"));
+        assert_eq!(position("é", 1), json!({"line": 0, "character": 0}));
+        assert_eq!(position("a", 5), json!({"line": 0, "character": 1}));
+    }
+}
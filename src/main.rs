@@ -1,27 +1,114 @@
-use parser::{parse_shebang, File};
-use shell::Shell;
+use caat_shell::parser::{self, parse_shebang, File};
+use caat_shell::shell::Shell;
+use caat_shell::{check, eval, lsp, server, test_runner, Interpreter};
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
-pub mod parser;
-pub mod eval;
-#[macro_use]
-pub mod shell;
-pub mod builtins;
+/// A shell with the plugins loaded. Loading runs every plugin with `--help`,
+/// so only the commands that run scripts do it.
+fn shell_with_plugins() -> Arc<RwLock<Shell>> {
+    let mut shell = Shell::new();
+    if let Err(msg) = caat_shell::builtins::plugin::load_plugins(&mut shell) {
+        eprintln!("{}", msg);
+    }
+    Arc::new(RwLock::new(shell))
+}
+
+/// Runs a script, reporting the error that stopped it on stderr.
+fn run_script(file: &mut File) {
+    if let caat_rust::Value::Failure(msg) = eval::run_file(shell_with_plugins(), file).get_value() {
+        eprintln!("{}", msg);
+    }
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>>  {
-    let shell = Arc::new(RwLock::new(Shell::new()));
     let args: Vec<String> = std::env::args().collect();
     //eprintln!("args: {:?}", args);
     //eprintln!("args.len(): {}", args.len());
-    if args.len() > 1 {
+    // A file named like a subcommand is run as a script, and `--` makes the
+    // argument after it a script path whether or not it exists.
+    let script = match args.get(1).map(|arg| arg.as_str()) {
+        Some("--") => args.get(2).cloned(),
+        Some(arg) if Path::new(arg).is_file() => Some(arg.to_string()),
+        _ => None,
+    };
+    if let Some(script) = script {
+        let mut file = parse_file(&script)?;
+        run_script(&mut file);
+    } else if args.len() > 1 && args[1] == "test" {
+        let path = args.get(2).map_or(".", |path| path.as_str());
+        if !test_runner::run_tests(Path::new(path))? {
+            std::process::exit(1);
+        }
+    } else if args.len() > 2 && args[1] == "check" {
+        let mut clean = true;
+        let commands = check::known_commands();
+        for path in &args[2..] {
+            let source = std::fs::read_to_string(path)?;
+            for (diagnostic, (start, _)) in check::check_source(&source, &commands) {
+                let before = &source[..start];
+                let line = before.matches('\n').count();
+                let column = before[before.rfind('\n').map_or(0, |i| i + 1)..].chars().count();
+                println!("{}:{}:{}: {}", path, line + 1, column + 1, diagnostic);
+                if diagnostic.severity == check::Severity::Error {
+                    clean = false;
+                }
+            }
+        }
+        if !clean {
+            std::process::exit(1);
+        }
+    } else if args.len() > 1 && args[1] == "lsp" {
+        lsp::serve_stdio()?;
+    } else if args.len() > 1 && args[1] == "fmt" {
+        if !format_files(&args[2..])? {
+            std::process::exit(1);
+        }
+    } else if args.len() > 2 && args[1] == "--serve" {
+        let interpreter = Interpreter::with_shell(shell_with_plugins());
+        interpreter.run_file(&args[2])?;
+        match (args.get(3), args.get(4)) {
+            (Some(flag), Some(path)) if flag == "--socket" => server::serve_socket(&interpreter, Path::new(path))?,
+            _ => server::serve_call(&interpreter, 3),
+        }
+    } else if args.len() > 1 {
         let mut file = parse_file(&args[1])?;
-        eval::run_file(shell, &mut file);
+        run_script(&mut file);
     } else {
-        eval::repl(shell);
+        eval::repl(shell_with_plugins());
     }
     Ok(())
 }
 
+/// `fmt [--check] [files...]` rewrites each file in canonical form, or with
+/// `--check` only lists the ones that are not. Without files it formats stdin
+/// to stdout. Returns false if a file needed formatting under `--check`.
+fn format_files(args: &[String]) -> Result<bool, Box<dyn std::error::Error>> {
+    let check = args.first().map_or(false, |arg| arg == "--check");
+    let paths = if check { &args[1..] } else { args };
+    if paths.is_empty() {
+        let mut source = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut source)?;
+        print!("{}", parser::format::format_file(&parser::parse_file(&source)?));
+        return Ok(true);
+    }
+    let mut clean = true;
+    for path in paths {
+        let source = std::fs::read_to_string(path)?;
+        let formatted = parser::format::format_file(&parser::parse_file(&source)?);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            clean = false;
+        } else {
+            std::fs::write(path, formatted)?;
+        }
+    }
+    Ok(clean)
+}
+
 fn parse_file(file_path: &str) -> Result<File, Box<dyn std::error::Error>> {
     let file = std::fs::read_to_string(file_path)?;
     match parse_shebang(&file) {
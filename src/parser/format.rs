@@ -0,0 +1,372 @@
+//! Prints the syntax tree back out as source that parses to the same tree.
+//!
+//! Blocks are indented by four spaces and every `function` at the top level is
+//! surrounded by a blank line. Comments are kept, but other blank lines are
+//! lost since the parser drops them.
+
+use super::{Assignment, Command, Expression, File, FunctionDef, Literal, MatchArm, Operator, PipelinePart, Pipeline, Redirect, Statement};
+
+const INDENT: &str = "    ";
+
+
+fn indent(level: usize) -> String {
+    INDENT.repeat(level)
+}
+
+/// Which statements get a blank line before them. Top level functions get one
+/// on each side, with their doc comment kept against them. A comment that is
+/// not a function's doc is kept apart from it, or it would become the doc when
+/// the output is parsed again.
+fn blank_lines(statements: &[Statement], top_level: bool) -> Vec<bool> {
+    let mut blank = vec![false; statements.len()];
+    for (i, statement) in statements.iter().enumerate() {
+        if let Statement::FunctionDef(function) = statement {
+            let doc_lines = function.doc.as_ref().map_or(0, |doc| doc.split('\n').count());
+            let first = i.saturating_sub(doc_lines);
+            if first > 0 && (top_level || matches!(statements[first - 1], Statement::Comment(_))) {
+                blank[first] = true;
+            }
+            if top_level && i + 1 < statements.len() {
+                blank[i + 1] = true;
+            }
+        }
+    }
+    blank
+}
+
+/// Formats a whole script, ending with a newline.
+pub fn format_file(file: &File) -> String {
+    let statements = file.statements.as_deref().unwrap_or(&[]);
+    let mut output = String::new();
+    for (statement, blank) in statements.iter().zip(blank_lines(statements, true)) {
+        if blank {
+            output.push('\n');
+        }
+        output.push_str(&format_statement(statement, 0));
+        output.push('\n');
+    }
+    output
+}
+
+/// Formats the statements of a block between braces. The closing brace is
+/// indented to `level`, the statements one level deeper.
+pub fn format_block(file: &File, level: usize) -> String {
+    let statements = file.statements.as_deref().unwrap_or(&[]);
+    let mut output = String::from("{\n");
+    for (statement, blank) in statements.iter().zip(blank_lines(statements, false)) {
+        if blank {
+            output.push('\n');
+        }
+        output.push_str(&indent(level + 1));
+        output.push_str(&format_statement(statement, level + 1));
+        output.push('\n');
+    }
+    output.push_str(&indent(level));
+    output.push('}');
+    output
+}
+
+pub fn format_statement(statement: &Statement, level: usize) -> String {
+    match statement {
+        Statement::Assignment(assignment) => format_assignment(assignment, level),
+        Statement::Expression(expression) => format_expression(expression, level),
+        Statement::FunctionDef(function) => format_function(function, level),
+        Statement::Return(expression) => format!("return {}", format_expression(expression, level)),
+        Statement::Comment(comment) => format!("#{}", comment),
+        Statement::Blank => String::new(),
+        Statement::Break => String::from("break"),
+        Statement::Continue => String::from("continue"),
+        Statement::Loop(body) => format!("loop {}", format_block(body, level)),
+    }
+}
+
+pub fn format_assignment(assignment: &Assignment, level: usize) -> String {
+    format!("{} = {}", assignment.target, format_expression(&assignment.value, level))
+}
+
+pub fn format_function(function: &FunctionDef, level: usize) -> String {
+    format!("function {}({}) {}", function.name, function.args.join(", "), format_block(&function.body, level))
+}
+
+pub fn format_expression(expression: &Expression, level: usize) -> String {
+    match expression {
+        Expression::Literal(literal) => format_literal(literal),
+        Expression::Pipeline(pipeline) => format_pipeline(pipeline, level),
+        Expression::Variable(name) => format!("${}", name),
+        Expression::Parenthesized(expression) => format!("({})", format_expression(expression, level)),
+        Expression::HigherOrder(pipeline) => format!("{{{}}}", format_pipeline(pipeline, level)),
+        Expression::If(cond, then, else_) => format!(
+            "if {} then {} else {}",
+            format_expression(cond, level),
+            format_expression(then, level),
+            format_expression(else_, level),
+        ),
+        Expression::Access(thing, index) => format!("{}[{}]", format_expression(thing, level), format_expression(index, level)),
+        Expression::Concat(a, b) => format!("{} ++ {}", format_expression(a, level), format_expression(b, level)),
+        Expression::Lambda(args, body) => format!("fn({}) {}", args.join(", "), format_block(body, level)),
+        Expression::Match(expr, arms) => {
+            let mut output = format!("match {} with", format_expression(expr, level));
+            for arm in arms {
+                output.push('\n');
+                output.push_str(&indent(level + 1));
+                output.push_str(&format_match_arm(arm, level + 1));
+            }
+            output
+        }
+    }
+}
+
+pub fn format_match_arm(arm: &MatchArm, level: usize) -> String {
+    match arm {
+        MatchArm::Expression(pattern, body) => format!("{} => {}", format_expression(pattern, level), format_expression(body, level)),
+        MatchArm::WildcardBind(name, body) => format!("{} => {}", name, format_expression(body, level)),
+        MatchArm::WildcardDiscard(body) => format!("_ => {}", format_expression(body, level)),
+    }
+}
+
+/// Strings and map keys are double quoted unless they contain a double quote.
+/// The grammar has no escapes, so a string with both kinds of quote can't be
+/// written.
+pub fn format_string(string: &str) -> String {
+    if string.contains('"') {
+        format!("'{}'", string)
+    } else {
+        format!("\"{}\"", string)
+    }
+}
+
+/// Floats are written without an exponent, which the grammar doesn't have,
+/// and always with a fraction, so they don't read back as integers.
+fn format_float(f: f64) -> String {
+    let text = format!("{}", f);
+    if text.contains('.') {
+        text
+    } else {
+        format!("{}.0", text)
+    }
+}
+
+pub fn format_literal(literal: &Literal) -> String {
+    match literal {
+        Literal::Integer(i) => format!("{}", i),
+        Literal::Float(f) => format_float(*f),
+        Literal::String(s) => format_string(s),
+        Literal::Boolean(b) => format!("{}", b),
+        Literal::List(list) => {
+            let items: Vec<String> = list.iter().map(format_literal).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Literal::Map(map) => {
+            let items: Vec<String> = map.iter().map(|(key, value)| format!("{}: {}", format_string(key), format_literal(value))).collect();
+            format!("{{{}}}", items.join(", "))
+        }
+        Literal::Null => String::from("()"),
+    }
+}
+
+pub fn format_pipeline(pipeline: &Pipeline, level: usize) -> String {
+    let mut output = format_pipeline_part(&pipeline.pipeline, level);
+    if let Some(redirect) = &pipeline.redirect {
+        output.push(' ');
+        output.push_str(&format_redirect(redirect, level));
+    }
+    output
+}
+
+pub fn format_redirect(redirect: &Redirect, level: usize) -> String {
+    match redirect {
+        Redirect::Input(expression) => format!("< {}", format_expression(expression, level)),
+        Redirect::Output(expression) => format!("> {}", format_expression(expression, level)),
+        Redirect::Append(expression) => format!(">> {}", format_expression(expression, level)),
+    }
+}
+
+pub fn format_operator(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::Pipe => "|",
+        Operator::And => "&&",
+        Operator::Or => "||",
+        Operator::Then => ";",
+    }
+}
+
+pub fn format_command(command: &Command, level: usize) -> String {
+    let mut output = command.name.clone();
+    for argument in &command.arguments {
+        output.push(' ');
+        output.push_str(&format_expression(argument, level));
+    }
+    output
+}
+
+pub fn format_pipeline_part(part: &PipelinePart, level: usize) -> String {
+    let mut output = format_command(&part.command, level);
+    if let Some(next) = &part.next {
+        let operator = part.operator.as_ref().unwrap_or(&Operator::Pipe);
+        output.push(' ');
+        output.push_str(format_operator(operator));
+        output.push(' ');
+        output.push_str(&format_pipeline_part(next, level));
+    }
+    output
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_file;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const SAMPLES: &[&str] = &[
+        "x = 5\n",
+        "name = \"caat\" ++ 'say \"hi\"'\n",
+        "list = [1, 2.0, \"three\", true]\nmap = {\"a\": 1, \"b\": [2]}\n",
+        "map = {'say \"hi\"': 1, \"it's\": 'x'}\n",
+        "echo $list[0]\n",
+        "ls | filter fn(x) {\n    return true\n} && echo \"done\" ; echo \"after\"\n",
+        "# a comment\nfunction greet(name, greeting) {\n    # inside\n    return $greeting ++ \" \" ++ $name\n}\n\ngreet \"world\" \"hello\"\n",
+        "loop {\n    x = add $x 1\n    if contains $x 1 then echo 1 else echo 2\n    break\n}\n",
+        "result = match $x with\n    1 => \"one\"\n    other => $other\n    _ => \"none\"\n",
+        "job = background {sleep 1}\necho $HOME > \"out.txt\"\necho $HOME >> \"out.txt\"\n",
+        "# not a doc\n\n# first\n# second\nfunction f() {\n    # loose\n\n    function g() {\n        return 1\n    }\n    return g\n}\n",
+        include_str!("../../scripts/wallpaper.sh"),
+        include_str!("../../scripts/lockscreen.sh"),
+    ];
+
+    #[test]
+    fn test_round_trip() {
+        for sample in SAMPLES {
+            let parsed = parse_file(sample).unwrap();
+            let formatted = format_file(&parsed);
+            assert_eq!(parse_file(&formatted).unwrap(), parsed, "formatted source:\n{}", formatted);
+        }
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!(format_literal(&Literal::Float(1e-6)), "0.000001");
+        assert_eq!(format_literal(&Literal::Float(1e16)), "10000000000000000.0");
+        assert_eq!(format_literal(&Literal::Float(2.0)), "2.0");
+        for f in [1e-6, 1.5e-300, 1e16, 1.7976931348623157e308, 0.1] {
+            let source = format!("x = {}\n", format_literal(&Literal::Float(f)));
+            match parse_file(&source).unwrap().statements.unwrap().pop() {
+                Some(Statement::Assignment(assignment)) => assert_eq!(assignment.value, Expression::Literal(Literal::Float(f))),
+                statement => panic!("expected an assignment, got {:?}", statement),
+            }
+        }
+    }
+
+    #[test]
+    fn test_idempotent() {
+        for sample in SAMPLES {
+            let formatted = format_file(&parse_file(sample).unwrap());
+            assert_eq!(format_file(&parse_file(&formatted).unwrap()), formatted);
+        }
+    }
+
+    /// Writes random source from the parts of the grammar the formatter has to
+    /// quote, space or break, so parsing it gives a random AST.
+    struct Generator(StdRng);
+
+    impl Generator {
+        fn pick<'a>(&mut self, options: &[&'a str]) -> &'a str {
+            options[self.0.gen_range(0..options.len())]
+        }
+
+        fn string(&mut self) -> String {
+            let quote = self.pick(&["\"", "'"]);
+            let other = if quote == "\"" { "'" } else { "\"" };
+            let length = self.0.gen_range(1..8);
+            let text: String = (0..length).map(|_| self.pick(&["a", "Z", " ", "-", ".", "#", ":", "\n", other])).collect();
+            format!("{}{}{}", quote, text, quote)
+        }
+
+        /// Maps are only written where a statement starts with an assignment,
+        /// since a brace after a command starts a block.
+        fn literal(&mut self, depth: usize, maps: bool) -> String {
+            let kinds = match (depth, maps) {
+                (0, _) => 4,
+                (_, false) => 5,
+                (_, true) => 6,
+            };
+            match self.0.gen_range(0..kinds) {
+                0 => self.0.gen_range(-1000..1000).to_string(),
+                1 => match self.0.gen_range(0..3) {
+                    0 => format!("{:?}", self.0.gen_range(0..1000) as f64 + self.0.gen_range(0..8) as f64 / 8.0),
+                    1 => format!("0.{}{}", "0".repeat(self.0.gen_range(4..20)), self.0.gen_range(1..100000)),
+                    _ => format!("{}{}.{}", self.0.gen_range(1..10), "0".repeat(self.0.gen_range(15..25)), self.0.gen_range(0..100)),
+                },
+                2 => self.string(),
+                3 => self.pick(&["true", "false"]).to_string(),
+                4 => {
+                    let items: Vec<String> = (0..self.0.gen_range(0..4)).map(|_| self.literal(depth - 1, maps)).collect();
+                    format!("[{}]", items.join(", "))
+                }
+                _ => {
+                    let pairs: Vec<String> = (0..self.0.gen_range(1..4)).map(|_| format!("{}: {}", self.string(), self.literal(depth - 1, maps))).collect();
+                    format!("{{{}}}", pairs.join(", "))
+                }
+            }
+        }
+
+        fn argument(&mut self, maps: bool) -> String {
+            match self.0.gen_range(0..3) {
+                0 => self.literal(2, maps),
+                1 => format!("${}", self.pick(&["x", "name", "cfg"])),
+                _ => format!("${}[{}]", self.pick(&["list", "cfg"]), self.literal(0, false)),
+            }
+        }
+
+        fn pipeline(&mut self) -> String {
+            let mut output = String::new();
+            for i in 0..self.0.gen_range(1..4) {
+                if i > 0 {
+                    output.push_str(self.pick(&[" | ", " && ", " || ", " ; "]));
+                }
+                output.push_str(self.pick(&["echo", "ls", "head", "to_json"]));
+                for _ in 0..self.0.gen_range(0..4) {
+                    output.push(' ');
+                    output.push_str(&self.argument(false));
+                }
+            }
+            output
+        }
+
+        fn file(&mut self) -> String {
+            let mut output = String::new();
+            for _ in 0..self.0.gen_range(1..6) {
+                let statement = match self.0.gen_range(0..4) {
+                    0 => format!("{} = {}", self.pick(&["x", "name", "cfg"]), self.argument(true)),
+                    1 => format!("x = {} ++ {}", self.argument(false), self.argument(false)),
+                    2 => format!("list = {}", self.pipeline()),
+                    _ => self.pipeline(),
+                };
+                output.push_str(&statement);
+                output.push('\n');
+            }
+            output
+        }
+    }
+
+    #[test]
+    fn test_generated_round_trip() {
+        let mut generator = Generator(StdRng::seed_from_u64(38));
+        for _ in 0..500 {
+            let source = generator.file();
+            let parsed = match parse_file(&source) {
+                Ok(parsed) => parsed,
+                Err(e) => panic!("generated source doesn't parse: {}\n{}", e, source),
+            };
+            let formatted = format_file(&parsed);
+            assert_eq!(parse_file(&formatted).ok(), Some(parsed), "source:\n{}\nformatted:\n{}", source, formatted);
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let source = "function f(a) {\nreturn $a\n}\nf 1";
+        assert_eq!(format_file(&parse_file(source).unwrap()), "function f(a) {\n    return $a\n}\n\nf 1\n");
+    }
+}
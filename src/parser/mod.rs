@@ -2,15 +2,41 @@
 //mod parser;
 
 mod peg_parser;
+pub mod format;
 
 use caat_rust::{Caat, Value};
-pub use peg_parser::{parse_file, parse_interactive, parse_shebang};
+pub use peg_parser::{parse_file, parse_file_with_spans, parse_interactive, parse_shebang};
 use std::fmt;
 use std::sync::{Arc, RwLock};
 use crate::shell::function::Function;
 use crate::{borrow, borrow_mut};
 use crate::shell::Shell;
 
+/// What a `Span` marks in the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SpanKind {
+    Function,
+    Parameter,
+    Assignment,
+    Variable,
+    Command,
+    /// A whole statement, without its indentation. Its name is empty.
+    Statement,
+}
+
+/// Where a name was defined or used, found by `parse_file_with_spans`.
+/// Offsets are in bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub kind: SpanKind,
+    pub name: String,
+    pub start: usize,
+    pub end: usize,
+    /// The whole construct, which for a function is its definition and body.
+    /// Otherwise it is the same as the name.
+    pub extent: (usize, usize),
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct File {
     pub statements: Option<Vec<Statement>>,
@@ -37,13 +63,7 @@ impl Iterator for File {
 
 impl fmt::Display for File {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let mut result = Ok(());
-        if let Some(statements) = &self.statements {
-            for statement in statements {
-                result = write!(f, "{}\n", statement);
-            }
-        }
-        result
+        write!(f, "{}", format::format_file(self))
     }
 }
 
@@ -74,18 +94,7 @@ pub enum Statement {
 
 impl fmt::Display for Statement {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Statement::Assignment(a) => write!(f, "{}", a),
-            Statement::Expression(e) => write!(f, "{}", e),
-            Statement::FunctionDef(fd) => write!(f, "{}", fd),
-            Statement::Return(e) => write!(f, "return {}", e),
-            Statement::Comment(c) => write!(f, "# {}", c),
-            Statement::Blank => write!(f, ""),
-            Statement::Break => write!(f, "break"),
-            Statement::Continue => write!(f, "continue"),
-            Statement::Loop(body) => write!(f, "loop {{{}}}", body),
-                    
-        }
+        write!(f, "{}", format::format_statement(self, 0))
     }
 }
 
@@ -98,7 +107,7 @@ pub struct Assignment {
 
 impl fmt::Display for Assignment {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{} = {}", self.target, self.value)
+        write!(f, "{}", format::format_assignment(self, 0))
     }
 }
 
@@ -107,11 +116,13 @@ pub struct FunctionDef {
     pub name: String,
     pub args: Vec<String>,
     pub body: File,
+    /// The `#` comments directly above the definition, without the `#`.
+    pub doc: Option<String>,
 }
 
 impl fmt::Display for FunctionDef {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "fn {}({}) {}", self.name, self.args.join(", "), self.body)
+        write!(f, "{}", format::format_function(self, 0))
     }
 }
 
@@ -131,24 +142,24 @@ pub enum Expression {
 
 impl Expression {
     pub fn as_value(&self, shell: Arc<RwLock<Shell>>) -> Value {
-        let borrowed_shell = borrow!(shell);
-        let env = borrowed_shell.environment();
         match self {
             Expression::Literal(literal) => literal.as_value(),
-            Expression::Variable(string) => env.get(&string).map_or(Value::Failure(format!("{} not found in environment", string)), |v| v.clone()),
+            Expression::Variable(string) => {
+                let borrowed_shell = borrow!(shell);
+                let env = borrowed_shell.environment();
+                let value = env.get(&string).map_or(Value::Failure(format!("{} not found in environment", string)), |v| v.clone());
+                value
+            },
             Expression::Pipeline(pipeline) => pipeline.pipeline.call(&[]),
             Expression::Parenthesized(expression) => {
-                drop(borrowed_shell);
                 expression.as_value(shell)
             },
             Expression::HigherOrder(ho) => {
-                drop(borrowed_shell);
                 let mut ho = ho.clone();
                 ho.resolve_args(shell.clone());
                 Value::CAATFunction(Arc::new(ho.pipeline))
             },
             Expression::If(cond, then, else_) => {
-                drop(borrowed_shell);
                 let cond = cond.as_value(shell.clone());
                 if let Value::Boolean(b) = cond {
                     if b {
@@ -161,7 +172,6 @@ impl Expression {
                 }
             },
             Expression::Access(thing, index) => {
-                drop(borrowed_shell);
                 let thing = thing.as_value(shell.clone());
                 let index = index.as_value(shell.clone());
                 match thing {
@@ -190,7 +200,6 @@ impl Expression {
                 }
             },
             Expression::Concat(a, b) => {
-                drop(borrowed_shell);
                 let a = a.as_value(shell.clone());
                 let b = b.as_value(shell.clone());
                 match (a, b) {
@@ -208,12 +217,12 @@ impl Expression {
             }
             Expression::Lambda(args, body) => {
                 let mut lambda = Function::new("lambda", args.to_vec(), body.clone(), shell.clone());
-                let env = env.get_current();
+                let borrowed_shell = borrow!(shell);
+                let env = borrowed_shell.environment().get_current();
                 lambda.bind_environment(env);
                 return Value::CAATFunction(Arc::new(lambda));
             }
             Expression::Match(expr, arms) => {
-                drop(borrowed_shell);
                 let expr = expr.as_value(shell.clone());
                 for arm in arms {
                     match arm {
@@ -249,28 +258,7 @@ impl Expression {
 
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Expression::Literal(l) => write!(f, "{}", l),
-            Expression::Pipeline(p) => write!(f, "{}", p),
-            Expression::Variable(v) => write!(f, "{}", v),
-            Expression::Parenthesized(e) => write!(f, "({})", e),
-            Expression::HigherOrder(h) => write!(f, "{}", h),
-            Expression::If(cond, then, else_) => write!(f, "if {} then {} else {}", cond, then, else_),
-            Expression::Access(thing, index) => write!(f, "{}[{}]", thing, index),
-            Expression::Concat(a, b) => write!(f, "{} ++ {}", a, b),
-            Expression::Lambda(args, body) => {
-                write!(f, "fn ({})", args.join(", "))?;
-                write!(f, "{{ {} }}", body)
-            }
-            Expression::Match(expr, arms) => {
-                let out = write!(f, "match {} with", expr);
-                for arm in arms {
-                    write!(f, "{}", arm)?;
-                }
-                out
-            }
-                    
-        }
+        write!(f, "{}", format::format_expression(self, 0))
     }
 }
 
@@ -283,11 +271,7 @@ pub enum MatchArm {
 
 impl fmt::Display for MatchArm {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            MatchArm::Expression(e, b) => write!(f, "{} => {}", e, b),
-            MatchArm::WildcardBind(s, e) => write!(f, "{} => {}", s, e),
-            MatchArm::WildcardDiscard(e) => write!(f, "_ => {}", e),
-        }
+        write!(f, "{}", format::format_match_arm(self, 0))
     }
 }
 
@@ -321,33 +305,7 @@ impl Literal {
 
 impl fmt::Display for Literal {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Literal::Integer(i) => write!(f, "{}", i),
-            Literal::Float(fl) => write!(f, "{}", fl),
-            Literal::String(s) => write!(f, "{}", s),
-            Literal::Boolean(b) => write!(f, "{}", b),
-            Literal::List(l) => {
-                write!(f, "[")?;
-                for (i, item) in l.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}", item)?;
-                }
-                write!(f, "]")
-            }
-            Literal::Map(m) => {
-                write!(f, "{{")?;
-                for (i, (k, v)) in m.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
-                    }
-                    write!(f, "{}: {}", k, v)?;
-                }
-                write!(f, "}}")
-            }
-            Literal::Null => write!(f, "()"),
-        }
+        write!(f, "{}", format::format_literal(self))
     }
 }
 
@@ -365,11 +323,7 @@ impl Pipeline {
 
 impl fmt::Display for Pipeline {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let result = write!(f, "{}", self.pipeline);
-        if let Some(redirect) = &self.redirect {
-            write!(f, " {}", redirect)?;
-        }
-        result
+        write!(f, "{}", format::format_pipeline(self, 0))
     }
 }
 
@@ -382,11 +336,7 @@ pub enum Redirect {
 
 impl fmt::Display for Redirect {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Redirect::Input(s) => write!(f, "< {}", s),
-            Redirect::Output(s) => write!(f, "> {}", s),
-            Redirect::Append(s) => write!(f, ">> {}", s),
-        }
+        write!(f, "{}", format::format_redirect(self, 0))
     }
 }
 
@@ -464,14 +414,7 @@ impl Caat for PipelinePart {
 
 impl fmt::Display for PipelinePart {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.command.name)?;
-        for arg in &self.command.arguments {
-            write!(f, " {}", arg)?;
-        }
-        if let Some(next) = &self.next {
-            write!(f, " | {}", next)?;
-        }
-        Ok(())
+        write!(f, "{}", format::format_pipeline_part(self, 0))
     }
 }
 
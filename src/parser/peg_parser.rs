@@ -1,5 +1,138 @@
 
-use crate::parser::{Literal, Expression, Command, Pipeline, Operator, Statement, Assignment, Interactive, File, FunctionDef, Redirect, PipelinePart, MatchArm};
+use crate::parser::{Span, SpanKind, Literal, Expression, Command, Pipeline, Operator, Statement, Assignment, Interactive, File, FunctionDef, Redirect, PipelinePart, MatchArm};
+
+use std::cell::RefCell;
+
+thread_local! {
+    /// Spans found by the running parse, when it was started by
+    /// `parse_file_with_spans`.
+    static SPANS: RefCell<Option<Vec<Span>>> = RefCell::new(None);
+}
+
+fn record(kind: SpanKind, name: &str, start: usize, end: usize, extent: (usize, usize)) {
+    SPANS.with(|spans| {
+        if let Some(spans) = spans.borrow_mut().as_mut() {
+            spans.push(Span { kind, name: name.to_string(), start, end, extent });
+        }
+    });
+}
+
+/// The names a parsed file defines and uses, in source order, as the spans
+/// for them would be recorded.
+fn parsed_names(file: &File, names: &mut Vec<(SpanKind, String)>) {
+    for statement in file.statements.iter().flatten() {
+        names.push((SpanKind::Statement, String::new()));
+        match statement {
+            Statement::Assignment(assignment) => {
+                names.push((SpanKind::Assignment, assignment.target.clone()));
+                expression_names(&assignment.value, names);
+            }
+            Statement::Expression(expression) | Statement::Return(expression) => expression_names(expression, names),
+            Statement::FunctionDef(function) => {
+                names.push((SpanKind::Function, function.name.clone()));
+                names.extend(function.args.iter().map(|arg| (SpanKind::Parameter, arg.clone())));
+                parsed_names(&function.body, names);
+            }
+            Statement::Loop(body) => parsed_names(body, names),
+            Statement::Comment(_) | Statement::Blank | Statement::Break | Statement::Continue => {}
+        }
+    }
+}
+
+fn expression_names(expression: &Expression, names: &mut Vec<(SpanKind, String)>) {
+    match expression {
+        Expression::Literal(_) => {}
+        Expression::Variable(name) => names.push((SpanKind::Variable, name.clone())),
+        Expression::Pipeline(pipeline) | Expression::HigherOrder(pipeline) => {
+            let mut part = Some(&pipeline.pipeline);
+            while let Some(current) = part {
+                names.push((SpanKind::Command, current.command.name.clone()));
+                for argument in &current.command.arguments {
+                    expression_names(argument, names);
+                }
+                part = current.next.as_deref();
+            }
+            match &pipeline.redirect {
+                Some(Redirect::Input(e)) | Some(Redirect::Output(e)) | Some(Redirect::Append(e)) => expression_names(e, names),
+                None => {}
+            }
+        }
+        Expression::Parenthesized(e) => expression_names(e, names),
+        Expression::If(cond, then, else_) => {
+            expression_names(cond, names);
+            expression_names(then, names);
+            expression_names(else_, names);
+        }
+        Expression::Access(a, b) | Expression::Concat(a, b) => {
+            expression_names(a, names);
+            expression_names(b, names);
+        }
+        Expression::Lambda(args, body) => {
+            names.extend(args.iter().map(|arg| (SpanKind::Parameter, arg.clone())));
+            parsed_names(body, names);
+        }
+        Expression::Match(e, arms) => {
+            expression_names(e, names);
+            for arm in arms {
+                match arm {
+                    MatchArm::Expression(pattern, body) => {
+                        expression_names(pattern, names);
+                        expression_names(body, names);
+                    }
+                    MatchArm::WildcardBind(_, body) | MatchArm::WildcardDiscard(body) => expression_names(body, names),
+                }
+            }
+        }
+    }
+}
+
+/// Keeps the spans that belong to the parse that succeeded. Actions run as
+/// soon as a rule matches, so an alternative that fails later still leaves
+/// spans behind; those are the ones that don't line up with the names in
+/// the parsed file.
+fn keep_parsed(file: &File, spans: Vec<Span>) -> Vec<Span> {
+    let mut names = Vec::new();
+    parsed_names(file, &mut names);
+    let mut names = names.into_iter().peekable();
+    spans.into_iter()
+        .filter(|span| match names.peek() {
+            Some((kind, name)) if *kind == span.kind && *name == span.name => {
+                names.next();
+                true
+            }
+            _ => false,
+        })
+        .collect()
+}
+
+/// Drops blank lines and gives each function the comments directly above it
+/// as its doc. Statements come with their start and end offsets, since the
+/// separator between statements swallows blank lines.
+fn attach_docs(statements: Vec<(usize, Statement, usize)>) -> Vec<Statement> {
+    let mut output = Vec::new();
+    let mut doc: Vec<String> = Vec::new();
+    let mut previous_end = None;
+    for (start, mut statement, end) in statements {
+        if previous_end.map_or(false, |previous_end| start > previous_end + 1) {
+            doc.clear();
+        }
+        previous_end = Some(end);
+        match &mut statement {
+            Statement::Comment(comment) => doc.push(comment.trim().to_string()),
+            Statement::FunctionDef(function) => {
+                if !doc.is_empty() {
+                    function.doc = Some(doc.join("\n"));
+                }
+                doc.clear();
+            }
+            _ => doc.clear(),
+        }
+        if !matches!(statement, Statement::Blank) {
+            output.push(statement);
+        }
+    }
+    output
+}
 
 #[derive(Debug, PartialEq)]
 pub enum Token {
@@ -86,6 +219,13 @@ peg::parser!{
                     //_ => Ok(Token::Identifier(match_str.to_string())),
                 }
             }
+        rule named(kind: SpanKind) -> Token
+            = start:position!() id:identifier() end:position!() {
+                if let Token::Identifier(name) = &id {
+                    record(kind, name, start, end, (start, end));
+                }
+                id
+            }
         pub rule bool() -> Token
             = match_str:$("true" / "false") {Token::Bool(match_str.parse().unwrap())}
         pub rule float() -> Token
@@ -159,7 +299,10 @@ peg::parser!{
         rule pair() -> Vec<(String, Literal)>
             = p:pair_item() ** (comma() [' '|'\t']*) {p}
         rule pair_item() -> (String, Literal)
-            = ['"'] k:$([^ '"']+) ['"'] ([' '|'\t']* colon() [' '|'\t']*) v:literal() { (k.to_string(), v) }
+            = k:map_key() ([' '|'\t']* colon() [' '|'\t']*) v:literal() { (k, v) }
+        rule map_key() -> String
+            = ['"'] k:$([^ '"']+) ['"'] { k.to_string() }
+            / ['\''] k:$([^ '\'']+) ['\''] { k.to_string() }
         rule map() -> Literal
             = brace_open() [' '|'\t']* m:pair() brace_close() {Literal::Map(m)}
         rule base_literal() -> Literal 
@@ -174,7 +317,7 @@ peg::parser!{
         rule literal() -> Literal
             = l:(base_literal() / list() / map()) {l}
         rule variable_expression() -> Expression
-            = dollar() id:identifier() {
+            = dollar() id:named(SpanKind::Variable) {
                 match id {
                     Token::Identifier(s) => Expression::Variable(s),
                     _ => unimplemented!(),
@@ -212,7 +355,7 @@ peg::parser!{
                     
         #[cache_left_rec]
         rule access_expression() -> Expression 
-            = thing:(expression_nonterminals_right() / expression_terminals() / concat_expression() /access_expression()) [' '|'\t']* bracket_open() [' '|'\t']* index:expression() [' '|'\t']* bracket_close() {
+            = thing:(access_expression() / expression_nonterminals_right() / expression_terminals() / concat_expression()) [' '|'\t']* bracket_open() [' '|'\t']* index:expression() [' '|'\t']* bracket_close() {
                 Expression::Access(Box::new(thing), Box::new(index))
             }
         rule concat_expression() -> Expression
@@ -220,7 +363,7 @@ peg::parser!{
             Expression::Concat(Box::new(e1), Box::new(e2))
             }
         rule lambda() -> Expression
-            = "fn" [' '|'\t']* ['('] args:identifier() ** (comma() [' '|'\t']*) [')'] [' '|'\t']* ['{'] [' '|'\t'|'\r'|'\n']* body:file() [' '|'\t']* ['}'] {
+            = "fn" [' '|'\t']* ['('] args:named(SpanKind::Parameter) ** (comma() [' '|'\t']*) [')'] [' '|'\t']* ['{'] [' '|'\t'|'\r'|'\n']* body:file() [' '|'\t']* ['}'] {
                 let args = args.into_iter().map(|t| if let Token::Identifier(s) = t {s} else {unreachable!()}).collect();
                 Expression::Lambda(args, body)
             }
@@ -233,13 +376,13 @@ peg::parser!{
         pub rule expression() -> Expression
             = e:(expression_nonterminals() / expression_terminals()) {e}
         pub rule command() -> Command
-            = name:identifier() [' '|'\t']+ args:expression() ** ([' '|'\t']+) {
+            = name:named(SpanKind::Command) [' '|'\t']+ args:expression() ** ([' '|'\t']+) {
                 if let Token::Identifier(name) = name {
                     Command::new(name, args)
                 } else {
                     unimplemented!()
                 }
-            } / name:identifier() {
+            } / name:named(SpanKind::Command) {
                 if let Token::Identifier(name) = name {
                     Command::new(name, vec![])
                 } else {
@@ -270,8 +413,9 @@ peg::parser!{
         rule expression_statement() -> Statement
             = e:expression() {Statement::Expression(e)} 
         rule assignment() -> Assignment
-            = id:identifier() [' '|'\t']* ['='] [' '|'\t']* e:expression() {
+            = start:position!() id:identifier() end:position!() [' '|'\t']* ['='] [' '|'\t']* e:expression() {
                 if let Token::Identifier(s) = id {
+                    record(SpanKind::Assignment, &s, start, end, (start, end));
                     Assignment{target: s, value: e}
                 } else {
                     unreachable!()
@@ -280,10 +424,11 @@ peg::parser!{
         rule assignment_statement() -> Statement
             = a:assignment() {Statement::Assignment(a)}
         rule function_def() -> FunctionDef
-            = "function" [' '|'\t']* id:identifier() [' '|'\t']* ['('] [' '|'\t']* args:identifier() ** (comma() [' '|'\t']*) [' '|'\t']* [')'] [' '|'\t']* ['{'] [' '|'\t'|'\r'|'\n']* body:file() [' '|'\t']* ['}'] {
+            = extent_start:position!() "function" [' '|'\t']* start:position!() id:identifier() end:position!() [' '|'\t']* ['('] [' '|'\t']* args:named(SpanKind::Parameter) ** (comma() [' '|'\t']*) [' '|'\t']* [')'] [' '|'\t']* ['{'] [' '|'\t'|'\r'|'\n']* body:file() [' '|'\t']* ['}'] extent_end:position!() {
                 if let Token::Identifier(name) = id {
+                    record(SpanKind::Function, &name, start, end, (extent_start, extent_end));
                     let args = args.into_iter().map(|t| if let Token::Identifier(s) = t {s} else {unreachable!()}).collect();
-                    FunctionDef { name: name, args: args, body: body }
+                    FunctionDef { name: name, args: args, body: body, doc: None }
                 } else {
                     unreachable!()
                 }
@@ -303,13 +448,17 @@ peg::parser!{
         rule loop_statement() -> Statement
             = "loop" [' '|'\t']* ['{'] [' '|'\t'|'\r'|'\n']* body:file() [' '|'\t']* ['}'] {Statement::Loop(body)}
         rule statement() -> Statement
-            = [' '|'\t']* s:(assignment_statement() / expression_statement() / function_def_statement() / return_statement() / comment() / blank() / break_statement() / continue_statement() / loop_statement()) {s}
+            = [' '|'\t']* start:position!() s:(assignment_statement() / expression_statement() / function_def_statement() / return_statement() / comment() / blank() / break_statement() / continue_statement() / loop_statement()) end:position!() {
+                if !matches!(s, Statement::Blank) {
+                    record(SpanKind::Statement, "", start, end, (start, end));
+                }
+                s
+            }
         pub rule interactive() -> Interactive
             = s:statement() ![_]{Interactive { statement: Some(s) }}
         pub rule file() -> File
-            = s:statement() ** (['\r']?['\n']+) ['\r']?['\n']* {
-                let mut statements = s.into_iter().filter(|s| match s {Statement::Blank => false, _ => true}).collect();
-                File::new(statements)
+            = s:(start:position!() s:statement() ['\r']? end:position!() {(start, s, end)}) ** (['\r']?['\n']+) ['\r']?['\n']* {
+                File::new(attach_docs(s))
             }
         pub rule shebang() -> String
             = "#!" s:$([^ '\n']+)  ['\r']?['\n']* [_]* ![_] {s.to_string()}
@@ -318,6 +467,22 @@ peg::parser!{
 }
 
 pub use parser::file as parse_file;
+
+/// Parses like `parse_file`, also returning where every function, parameter,
+/// assignment, variable and command name is, sorted by position.
+pub fn parse_file_with_spans(input: &str) -> Result<(File, Vec<Span>), peg::error::ParseError<peg::str::LineCol>> {
+    SPANS.with(|spans| *spans.borrow_mut() = Some(Vec::new()));
+    let result = parser::file(input);
+    let mut spans = SPANS.with(|spans| spans.borrow_mut().take()).unwrap_or_default();
+    // Backtracking can match the same name more than once. A statement comes
+    // before the names that start where it does.
+    spans.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end), span.kind, span.extent));
+    spans.dedup();
+    result.map(|file| {
+        let spans = keep_parsed(&file, spans);
+        (file, spans)
+    })
+}
 pub use parser::interactive as parse_interactive;
 pub use parser::shebang as parse_shebang;
 
@@ -360,6 +525,13 @@ mod tests {
         assert_eq!(parser::expression(r#"{"foo": "bar"}"#), Ok(Expression::Literal(Literal::Map(vec![("foo".to_string(), Literal::String("bar".to_string()))]))));
         assert_eq!(parser::expression(r#"(42)"#), Ok(Expression::Parenthesized(Box::new(Expression::Literal(Literal::Integer(42))))));
     }
+
+    #[test]
+    fn test_nested_access() {
+        let string = |s: &str| Box::new(Expression::Literal(Literal::String(s.to_string())));
+        let section = Expression::Access(Box::new(Expression::Variable("cfg".to_string())), string("server"));
+        assert_eq!(parser::expression(r#"$cfg["server"]["port"]"#), Ok(Expression::Access(Box::new(section), string("port"))));
+    }
     
     #[test]
     fn test_command() {
@@ -375,4 +547,53 @@ mod tests {
     fn test_pipeline_redirect() {
         assert_eq!(parser::pipeline("foo 42 > \"bar\""), Ok(Pipeline {pipeline: PipelinePart {command: Command::new("foo".to_string(), vec![Expression::Literal(Literal::Integer(42))]), operator: None, next: None}, redirect: Some(Redirect::Output(Box::new(Expression::Literal(Literal::String("bar".to_string())))))}));
     }
+
+    #[test]
+    fn test_spans() {
+        let source = "function f(a) {\n    return $a\n}\nx = f 1\n";
+        let (_, spans) = parse_file_with_spans(source).unwrap();
+        let found: Vec<(SpanKind, &str, usize)> = spans.iter().map(|span| (span.kind, span.name.as_str(), span.start)).collect();
+        assert_eq!(found, vec![
+            (SpanKind::Statement, "", 0),
+            (SpanKind::Function, "f", 9),
+            (SpanKind::Parameter, "a", 11),
+            (SpanKind::Statement, "", 20),
+            (SpanKind::Variable, "a", 28),
+            (SpanKind::Statement, "", 32),
+            (SpanKind::Assignment, "x", 32),
+            (SpanKind::Command, "f", 36),
+        ]);
+        assert_eq!(spans[1].extent, (0, 31));
+        assert_eq!((spans[3].start, spans[3].end), (20, 29));
+    }
+
+    #[test]
+    fn test_spans_from_failed_alternatives_are_dropped() {
+        let source = "x = f 1\ny = $x\n";
+        let (file, spans) = parse_file_with_spans(source).unwrap();
+        let phantom = |kind, name: &str, start: usize| Span { kind, name: name.to_string(), start, end: start + name.len(), extent: (start, start + name.len()) };
+        let mut recorded = spans.clone();
+        recorded.push(phantom(SpanKind::Command, "x", 0));
+        recorded.push(phantom(SpanKind::Command, "y", 8));
+        recorded.push(phantom(SpanKind::Command, "x", 13));
+        recorded.push(phantom(SpanKind::Statement, "", 4));
+        recorded.sort_by_key(|span| (span.start, std::cmp::Reverse(span.end), span.kind, span.extent));
+        assert_eq!(keep_parsed(&file, recorded), spans);
+        let kinds: Vec<SpanKind> = spans.iter().map(|span| span.kind).collect();
+        assert_eq!(kinds, vec![
+            SpanKind::Statement, SpanKind::Assignment, SpanKind::Command,
+            SpanKind::Statement, SpanKind::Assignment, SpanKind::Variable,
+        ]);
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let doc = |source: &str| match parse_file(source).unwrap().statements.unwrap().pop() {
+            Some(Statement::FunctionDef(function)) => function.doc,
+            statement => panic!("expected a function, got {:?}", statement),
+        };
+        assert_eq!(doc("# first\n# second\nfunction f() {\n    return 1\n}"), Some("first\nsecond".to_string()));
+        assert_eq!(doc("# apart\n\nfunction f() {\n    return 1\n}"), None);
+        assert_eq!(doc("x = 1\nfunction f() {\n    return 1\n}"), None);
+    }
 }
@@ -0,0 +1,197 @@
+//! Serving shell functions to other programs.
+//!
+//! `caat_shell --serve script.caat function args...` is an ordinary CAAT
+//! foreign command. The arguments arrive through `caat_rust::args()` and the
+//! function's value goes back through `caat_rust::return_caat`, so callers
+//! written with caat_rust or the Python module call a script's functions the
+//! same way they call any other command:
+//!
+//! ```text
+//! ForeignFunction::new("caat_shell").call(&["--serve".into(), "lib.caat".into(), "double".into(), 4.into()])
+//! ```
+//!
+//! A failure from the function is returned as a CAAT failure. Without a
+//! function name the sorted names of the script's functions are returned.
+//!
+//! Clients that make many calls can keep a script loaded with `--socket path`
+//! instead. Each connection sends one JSON object per line and gets one back:
+//! `{"function": "name", "args": [...]}` answers `{"value": ...}` or
+//! `{"failure": "message"}`, and `{"list": true}` answers with the function
+//! names. Values are converted as described in `formats::json`.
+
+use caat_rust::Value;
+use crate::formats::json;
+use crate::Interpreter;
+use serde_json::json;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+
+fn function_names(interpreter: &Interpreter) -> Vec<String> {
+    let shell = interpreter.shell();
+    let borrowed_shell = borrow!(shell);
+    let mut names = borrowed_shell.function_names();
+    names.sort();
+    names
+}
+
+/// Calls `name` with `args`, or lists the functions when there is no name.
+fn call(interpreter: &Interpreter, name: Option<&str>, args: &[Value]) -> Value {
+    let name = match name {
+        Some(name) => name,
+        None => return Value::List(function_names(interpreter).into_iter().map(Value::String).collect()),
+    };
+    match interpreter.call_function(name, args) {
+        Ok(value) => value,
+        Err(e) => Value::Failure(e.to_string()),
+    }
+}
+
+/// Answers the CAAT call this process was started for. `skip` is the number of
+/// leading arguments that name this program, the flag and the script; the one
+/// after them is the function and the rest are its arguments.
+pub fn serve_call(interpreter: &Interpreter, skip: usize) {
+    let mut args = caat_rust::args().skip(skip);
+    let name = match args.next() {
+        Some(Value::String(name)) => Some(name),
+        Some(_) => {
+            caat_rust::return_caat(Value::Failure("serve: expected a function name".to_string()));
+            return;
+        }
+        None => None,
+    };
+    let args: Vec<Value> = args.collect();
+    caat_rust::return_caat(call(interpreter, name.as_deref(), &args));
+}
+
+
+fn handle_request(interpreter: &Interpreter, line: &str) -> serde_json::Value {
+    let request: serde_json::Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return json!({"failure": format!("bad request: {}", e)}),
+    };
+    if request.get("list").and_then(|list| list.as_bool()) == Some(true) {
+        return json!({"value": function_names(interpreter)});
+    }
+    let name = match request.get("function").and_then(|name| name.as_str()) {
+        Some(name) => name,
+        None => return json!({"failure": "bad request: expected a function name"}),
+    };
+    let args: Vec<Value> = match request.get("args") {
+        Some(serde_json::Value::Array(args)) => args.iter().map(json::from_json).collect(),
+        Some(_) => return json!({"failure": "bad request: args must be a list"}),
+        None => Vec::new(),
+    };
+    match call(interpreter, Some(name), &args) {
+        Value::Failure(msg) => json!({"failure": msg}),
+        value => match json::to_json(&value) {
+            Ok(value) => json!({"value": value}),
+            Err(msg) => json!({"failure": msg}),
+        },
+    }
+}
+
+/// Answers requests read from `reader` until it is closed.
+pub fn serve<R: BufRead, W: Write>(interpreter: &Interpreter, reader: R, mut writer: W) -> io::Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_request(interpreter, &line);
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Removes the socket file when the server stops.
+struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Binds `path`, replacing a socket left behind by a server that is no longer
+/// running. A live server or a file that isn't a socket is an error.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.file_type().is_socket() => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display())));
+        }
+        Ok(_) => match UnixStream::connect(path) {
+            Ok(_) => return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("a server is already listening on {}", path.display()))),
+            Err(_) => std::fs::remove_file(path)?,
+        },
+        Err(_) => {}
+    }
+    UnixListener::bind(path)
+}
+
+/// Listens on a Unix socket at `path`. Each connection is served on its own
+/// thread in a fork of the interpreter's shell, so clients can't see each
+/// other's variables. The socket file is removed when the server stops,
+/// including on SIGINT and SIGTERM.
+pub fn serve_socket(interpreter: &Interpreter, path: &Path) -> io::Result<()> {
+    let listener = bind(path)?;
+    let socket = SocketFile(path.to_path_buf());
+    let cleanup = path.to_path_buf();
+    ctrlc::set_handler(move || {
+        let _ = std::fs::remove_file(&cleanup);
+        std::process::exit(130);
+    }).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let shell = interpreter.shell();
+        let borrowed_shell = borrow!(shell);
+        let fork = Interpreter::with_shell(Arc::new(RwLock::new(borrowed_shell.fork(shell.clone()))));
+        drop(borrowed_shell);
+        std::thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Ok(reader) => BufReader::new(reader),
+                Err(e) => return eprintln!("serve: {}", e),
+            };
+            if let Err(e) = serve(&fork, reader, stream) {
+                eprintln!("serve: {}", e);
+            }
+        });
+    }
+    drop(socket);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_replaces_stale_socket() {
+        let path = std::env::temp_dir().join(format!("caat_serve_{}.sock", std::process::id()));
+        drop(UnixListener::bind(&path).unwrap());
+        assert!(path.exists());
+        let listener = bind(&path).unwrap();
+        assert!(bind(&path).is_err());
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_call() {
+        let interpreter = Interpreter::new();
+        interpreter.eval_str("function double(x) {\n    return add $x $x\n}\n").unwrap();
+        assert_eq!(call(&interpreter, Some("double"), &[Value::Integer(4)]), Value::Integer(8));
+        assert!(matches!(call(&interpreter, Some("missing"), &[]), Value::Failure(_)));
+        assert_eq!(call(&interpreter, None, &[]), Value::List(vec![Value::String("double".to_string())].into()));
+        let response = handle_request(&interpreter, r#"{"function": "double", "args": [2]}"#);
+        assert_eq!(response, json!({"value": 4}));
+        assert_eq!(handle_request(&interpreter, r#"{"list": true}"#), json!({"value": ["double"]}));
+        let response = handle_request(&interpreter, r#"{"list": false, "function": "double", "args": [1]}"#);
+        assert_eq!(response, json!({"value": 2}));
+    }
+}
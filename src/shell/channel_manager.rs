@@ -0,0 +1,137 @@
+use caat_rust::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar};
+
+
+#[derive(Debug, Default)]
+struct Channel {
+    values: VecDeque<Value>,
+    closed: bool,
+}
+
+/// What a receiver finds when it looks at its channel.
+pub enum Received {
+    Value(Value),
+    Empty,
+    Closed,
+}
+
+/// Owns every channel created by a shell and its forks.
+///
+/// Channel ends are handed to scripts as maps with a `type` of
+/// `channel_sender` or `channel_receiver` and the channel's `id`. Blocked
+/// receivers wait on one condvar paired with the manager's mutex, which is
+/// notified whenever a value is sent or a channel is closed. A channel is
+/// freed once it is closed and drained; ends are plain values, so a channel
+/// that is never closed lives as long as the shell.
+#[derive(Debug)]
+pub struct ChannelManager {
+    channels: HashMap<i64, Channel>,
+    next_id: i64,
+    signal: Arc<Condvar>,
+}
+
+
+impl ChannelManager {
+    pub fn new() -> Self {
+        ChannelManager {
+            channels: HashMap::new(),
+            next_id: 0,
+            signal: Arc::new(Condvar::new()),
+        }
+    }
+
+    fn end(id: i64, kind: &str) -> Value {
+        let mut output = HashMap::new();
+        output.insert(String::from("type"), Value::String(kind.to_string()));
+        output.insert(String::from("id"), Value::Integer(id));
+        Value::Map(output, Some(String::from("{type} {id}")))
+    }
+
+    fn get_id(value: &Value, kind: &str, command: &str) -> Result<i64, String> {
+        match value {
+            Value::Map(members, _) => {
+                match members.get("type") {
+                    Some(Value::String(s)) if s == kind => {},
+                    _ => return Err(format!("{}: expected a {}", command, kind)),
+                }
+                match members.get("id") {
+                    Some(Value::Integer(i)) => Ok(*i),
+                    _ => Err(format!("{}: {} has no id", command, kind)),
+                }
+            }
+            _ => Err(format!("{}: expected a {}", command, kind)),
+        }
+    }
+
+    /// Notified whenever a value is sent or a channel is closed. Wait on it
+    /// with the guard of the mutex holding this manager.
+    pub fn signal(&self) -> Arc<Condvar> {
+        self.signal.clone()
+    }
+
+    /// Creates a channel and returns its `[sender, receiver]` pair.
+    pub fn create(&mut self) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.channels.insert(id, Channel::default());
+        Value::List(vec![Self::end(id, "channel_sender"), Self::end(id, "channel_receiver")].into())
+    }
+
+    fn unknown(&self, id: i64, command: &str) -> String {
+        if id >= 0 && id < self.next_id {
+            format!("{}: channel {} is closed", command, id)
+        } else {
+            format!("{}: no channel with id {}", command, id)
+        }
+    }
+
+    pub fn send(&mut self, sender: &Value, value: Value, command: &str) -> Result<(), String> {
+        let id = Self::get_id(sender, "channel_sender", command)?;
+        match self.channels.get_mut(&id) {
+            Some(channel) if !channel.closed => channel.values.push_back(value),
+            _ => return Err(self.unknown(id, command)),
+        }
+        self.signal.notify_all();
+        Ok(())
+    }
+
+    /// Takes the next value without waiting. A closed channel is freed once
+    /// its last value has been taken.
+    pub fn try_recv(&mut self, receiver: &Value, command: &str) -> Result<Received, String> {
+        let id = Self::get_id(receiver, "channel_receiver", command)?;
+        let channel = match self.channels.get_mut(&id) {
+            Some(channel) => channel,
+            None if id >= 0 && id < self.next_id => return Ok(Received::Closed),
+            None => return Err(self.unknown(id, command)),
+        };
+        match channel.values.pop_front() {
+            Some(value) => Ok(Received::Value(value)),
+            None if channel.closed => {
+                self.channels.remove(&id);
+                Ok(Received::Closed)
+            }
+            None => Ok(Received::Empty),
+        }
+    }
+
+    /// Closes the sending side. Receivers still get the values already sent,
+    /// then see the channel as closed.
+    pub fn close(&mut self, sender: &Value) -> Result<(), String> {
+        let id = Self::get_id(sender, "channel_sender", "close")?;
+        match self.channels.get_mut(&id) {
+            Some(channel) if channel.values.is_empty() => {
+                self.channels.remove(&id);
+            }
+            Some(channel) => channel.closed = true,
+            None => return Err(self.unknown(id, "close")),
+        }
+        self.signal.notify_all();
+        Ok(())
+    }
+
+    /// The number of channels that haven't been freed.
+    pub fn channel_count(&self) -> usize {
+        self.channels.len()
+    }
+}
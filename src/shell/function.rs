@@ -17,6 +17,7 @@ pub struct Function {
     pub body: File,
     pub shell: Arc<RwLock<Shell>>,
     pub environment: Option<HashMap<String, Value>>,
+    pub doc: Option<String>,
 }
 
 impl Function {
@@ -27,6 +28,7 @@ impl Function {
             body,
             shell,
             environment: None,
+            doc: None,
         }
     }
     pub fn bind_environment(&mut self, environment: HashMap<String, Value>) {
@@ -45,7 +47,8 @@ impl std::fmt::Display for Function {
 
 impl Caat for Function {
     fn call(&self, args: &[Value]) -> Value {
-        let mut borrowed_shell = borrow_mut!(self.shell);
+        let shell = super::current_shell().unwrap_or_else(|| self.shell.clone());
+        let mut borrowed_shell = borrow_mut!(shell);
         let environment = borrowed_shell.environment_mut();
         environment.push_scope();
         match &self.environment {
@@ -60,9 +63,9 @@ impl Caat for Function {
             }
         }
         drop(borrowed_shell);
-        let value = crate::eval::run_file(self.shell.clone(), &mut self.body.clone());
+        let value = crate::eval::run_file(shell.clone(), &mut self.body.clone());
          
-        let mut borrowed_shell = borrow_mut!(self.shell);
+        let mut borrowed_shell = borrow_mut!(shell);
         let environment = borrowed_shell.environment_mut();
         environment.pop_scope();
         value.get_value()
@@ -1,7 +1,45 @@
 use caat_rust::Value;
 use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::time::Duration;
+use super::Shell;
+use crate::borrow;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
 
+/// How long a timed out call gets to return once its processes are killed.
+const TIMEOUT_GRACE: Duration = Duration::from_secs(1);
+
+/// The processes started by a thread or process, read from
+/// `/proc/<pid>/task/<tid>/children`.
+fn children_of(pid: Pid, tid: Pid) -> Vec<Pid> {
+    let path = format!("/proc/{}/task/{}/children", pid, tid);
+    std::fs::read_to_string(path).unwrap_or_default()
+        .split_whitespace()
+        .filter_map(|child| child.parse().ok().map(Pid::from_raw))
+        .collect()
+}
+
+/// Kills the processes a thread of this process started, and theirs in turn.
+/// Each one is stopped before its children are read, so the tree can't grow
+/// or be reparented while it is being collected.
+fn kill_children(tid: Pid) {
+    let mut pending = children_of(nix::unistd::getpid(), tid);
+    let mut found = Vec::new();
+    while let Some(child) = pending.pop() {
+        let _ = kill(child, Signal::SIGSTOP);
+        let tasks = std::fs::read_dir(format!("/proc/{}/task", child)).into_iter().flatten().flatten();
+        for task in tasks {
+            if let Some(task) = task.file_name().to_str().and_then(|task| task.parse().ok()) {
+                pending.extend(children_of(child, Pid::from_raw(task)));
+            }
+        }
+        found.push(child);
+    }
+    for child in found {
+        let _ = kill(child, Signal::SIGKILL);
+    }
+}
 
 #[derive(Debug)]
 pub struct Job {
@@ -20,6 +58,20 @@ impl Clone for Job {
     }
 }
 
+impl Job {
+    /// Blocks until the job's thread finishes and returns its value.
+    pub fn wait(self) -> Result<Value, String> {
+        let handle = match self.handle.lock() {
+            Ok(mut handle) => handle.take(),
+            Err(_) => return Err("join: thread panicked".to_string()),
+        };
+        match handle {
+            Some(handle) => handle.join().map_err(|_| "join: thread panicked".to_string()),
+            None => Err("join: job was already joined".to_string()),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct JobManager {
     jobs: Vec<Option<Job>>,
@@ -49,7 +101,9 @@ impl JobManager {
         }
     }
 
-    pub fn spawn_command(&mut self, command: Value, args: &Vec<Value>) -> Result<Value, String> {
+    /// Runs `command` on a new thread inside `shell`, which should be a fork
+    /// of the spawning shell so the job gets its own scope stack.
+    pub fn spawn_command(&mut self, shell: Arc<RwLock<Shell>>, command: Value, args: &Vec<Value>) -> Result<Value, String> {
         self.get_next_id();
         let id = self.next_id.expect("No next id Somehow");
         let cmd = match command {
@@ -59,7 +113,7 @@ impl JobManager {
         let command = format!("{}", cmd);
         let args = args.clone();
         let handle = std::thread::spawn(move || {
-            cmd.call(&args)
+            super::run_in(shell, || cmd.call(&args))
         });
         if id as usize >= self.jobs.len() {
             self.jobs.push(None);
@@ -75,7 +129,50 @@ impl JobManager {
         Ok(Value::Map(output, Some(String::from("{id} {Command}"))))
     }
 
-    pub fn join(&mut self, job: Value) -> Result<Value, String> {
+    /// Runs `command` on a new thread inside `shell` and waits at most `timeout`
+    /// for it. The thread is not tracked as a job.
+    ///
+    /// When the limit is reached `shell` is cancelled, so shell code stops at its
+    /// next statement, every process the thread started is killed along with
+    /// its descendants, and `Failure("timed out")` is returned. The thread is
+    /// then given `TIMEOUT_GRACE` to wind down and joined; only a builtin that
+    /// never returns can outlive that.
+    pub fn call_with_timeout(shell: Arc<RwLock<Shell>>, command: Value, args: &Vec<Value>, timeout: Duration) -> Result<Value, String> {
+        let cmd = match command {
+            Value::CAATFunction(f) => f,
+            _ => return Err("timeout: no command was given".to_string()),
+        };
+        let args = args.clone();
+        let job_shell = shell.clone();
+        let (sender, receiver) = mpsc::channel();
+        let (tid_sender, tid_receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let _ = tid_sender.send(nix::unistd::gettid());
+            let value = super::run_in(job_shell, || cmd.call(&args));
+            let _ = sender.send(value);
+        });
+        let tid = tid_receiver.recv().map_err(|_| "timeout: thread panicked".to_string())?;
+        match receiver.recv_timeout(timeout) {
+            Ok(value) => {
+                let _ = handle.join();
+                Ok(value)
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let borrowed_shell = borrow!(shell);
+                borrowed_shell.cancel();
+                drop(borrowed_shell);
+                kill_children(tid);
+                if receiver.recv_timeout(TIMEOUT_GRACE).is_ok() {
+                    let _ = handle.join();
+                }
+                Ok(Value::Failure("timed out".to_string()))
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err("timeout: thread panicked".to_string()),
+        }
+    }
+
+    /// Removes a job from the table so it can be waited on without the shell lock.
+    pub fn take_job(&mut self, job: Value) -> Result<Job, String> {
         let id = match job {
             Value::Map(members, _) => {
                 let mut id = None;
@@ -97,39 +194,23 @@ impl JobManager {
             Value::Integer(i) => i,
             _ => return Err("join: no job map or job id was given".to_string()),
         };
+        if id < 0 {
+            return Err("join: no job with that id".to_string());
+        }
         let job = match self.jobs.get_mut(id as usize) {
             Some(job) => job,
             None => return Err("join: no job with that id".to_string()),
         };
-        if let Some(job) = job.take() {
-            self.next_id = None;
-            let handle = job.handle.clone();
-            drop(job);
-            loop {
-                match handle.try_lock() {
-                    Ok(mut handle) => {
-                        let handle = handle.take().unwrap();
-                        let value = handle.join().map_err(|_| "join: thread panicked".to_string())?;
-                        self.next_id = Some(id);
-
-                        return Ok(value);
-                    },
-                    Err(std::sync::TryLockError::WouldBlock) => {
-                        std::thread::sleep(std::time::Duration::from_millis(100));
-                    },
-                    Err(_) => {
-                        return Err("join: thread panicked".to_string());
-                    },
-                                    
-                }
+        match job.take() {
+            Some(job) => {
+                self.next_id = Some(id);
+                Ok(job)
             }
-            //Ok(job.handle.join().map_err(|_| "join: thread panicked".to_string())?)
-        } else {
-            return Err("join: no job with that id".to_string());
+            None => Err("join: no job with that id".to_string()),
         }
     }
     
-    pub fn jobs(&self, _args: &Vec<Value>) -> Value {
+    pub fn jobs(&self, _args: &[Value]) -> Value {
         let mut output: Vec<Value> = Vec::new();
         for job in self.jobs.iter() {
             if let Some(job) = job {
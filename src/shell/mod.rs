@@ -1,40 +1,82 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use caat_rust::Value;
+use crate::builtins::BuiltinRegistry;
 use job_manager::JobManager;
+use channel_manager::ChannelManager;
 pub mod job_manager;
+pub mod channel_manager;
 pub mod function;
 
+/// Locks a shared `Shell` for writing, blocking until it is available.
+///
+/// The shell lock is not re-entrant. Drop the guard before calling a `Caat`
+/// value or waiting on a job, since either may need the shell again.
 #[macro_export]
 macro_rules! borrow_mut {
     ($e:expr) => {
-        loop {
-            match $e.try_write() {
-                Ok(e) => break e,
-                Err(std::sync::TryLockError::WouldBlock) => continue,
-                Err(e) => panic!("error: {:?}", e),
-            }
+        match $e.write() {
+            Ok(e) => e,
+            Err(e) => panic!("error: {:?}", e),
         }
     }
 }
 
+/// Locks a shared `Shell` for reading, blocking until it is available.
+///
+/// The same rules as `borrow_mut!` apply: never hold the guard across a call.
 #[macro_export]
 macro_rules! borrow {
     ($e:expr) => {
-        loop {
-            match $e.try_read() {
-                Ok(e) => break e,
-                Err(std::sync::TryLockError::WouldBlock) => continue,
-                Err(e) => panic!("error: {:?}", e),
-            }
+        match $e.read() {
+            Ok(e) => e,
+            Err(e) => panic!("error: {:?}", e),
         }
     }
 }
 
+thread_local! {
+    static CURRENT_SHELL: RefCell<Option<Arc<RwLock<Shell>>>> = RefCell::new(None);
+}
+
+/// Returns the shell the current thread was started with by `run_in`, if any.
+pub fn current_shell() -> Option<Arc<RwLock<Shell>>> {
+    CURRENT_SHELL.with(|current| current.borrow().clone())
+}
+
+/// Runs `f` with `shell` as the current thread's shell.
+///
+/// `Function::call` evaluates against this shell instead of the one the
+/// function was defined in, which is how jobs get their own scope stack.
+pub fn run_in<T>(shell: Arc<RwLock<Shell>>, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_SHELL.with(|current| current.replace(Some(shell)));
+    let value = f();
+    CURRENT_SHELL.with(|current| current.replace(previous));
+    value
+}
+
+/// Interpreter state shared as `Arc<RwLock<Shell>>`.
+///
+/// Background jobs run in a `fork` of the shell that spawned them. A fork sees
+/// the parent's variables and functions as they were at spawn time, but
+/// assignments, scopes, function definitions and jobs it creates stay private.
+/// The only state a job changes in its parent is what it passes to `share`;
+/// values can also be streamed between jobs over shared channels.
+/// Every access takes the lock for a single short step and releases it before
+/// evaluating user code.
 #[derive(Debug, Clone)]
 pub struct Shell {
     environment: Environment,
     job_manager: JobManager,
     functions: HashMap<String, function::Function>,
+    parent: Option<Arc<RwLock<Shell>>>,
+    channels: Arc<Mutex<ChannelManager>>,
+    cancel_tokens: Vec<Arc<AtomicBool>>,
+    builtins: BuiltinRegistry,
+    plugins: HashMap<String, PathBuf>,
 }
 
 
@@ -44,6 +86,11 @@ impl Shell {
             environment: Environment::new(),
             job_manager: JobManager::new(),
             functions: HashMap::new(),
+            parent: None,
+            channels: Arc::new(Mutex::new(ChannelManager::new())),
+            cancel_tokens: vec![Arc::new(AtomicBool::new(false))],
+            builtins: BuiltinRegistry::new(),
+            plugins: HashMap::new(),
         }
     }
     pub fn with_environment(environment: Environment) -> Self {
@@ -51,8 +98,46 @@ impl Shell {
             environment,
             job_manager: JobManager::new(),
             functions: HashMap::new(),
+            parent: None,
+            channels: Arc::new(Mutex::new(ChannelManager::new())),
+            cancel_tokens: vec![Arc::new(AtomicBool::new(false))],
+            builtins: BuiltinRegistry::new(),
+            plugins: HashMap::new(),
         }
     }
+    /// Creates a child shell for a job spawned from `parent`, which must be the
+    /// shell `self` is borrowed from. The environment is shared copy-on-write.
+    pub fn fork(&self, parent: Arc<RwLock<Shell>>) -> Self {
+        let mut cancel_tokens = self.cancel_tokens.clone();
+        cancel_tokens.push(Arc::new(AtomicBool::new(false)));
+        Shell {
+            environment: self.environment.clone(),
+            job_manager: JobManager::new(),
+            functions: self.functions.clone(),
+            parent: Some(parent),
+            channels: self.channels.clone(),
+            cancel_tokens,
+            builtins: self.builtins.clone(),
+            plugins: self.plugins.clone(),
+        }
+    }
+    pub fn parent(&self) -> Option<Arc<RwLock<Shell>>> {
+        self.parent.clone()
+    }
+    /// Asks everything running in this shell and its forks to stop at the next
+    /// statement. The shell it was forked from keeps running.
+    pub fn cancel(&self) {
+        if let Some(token) = self.cancel_tokens.last() {
+            token.store(true, Ordering::SeqCst);
+        }
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_tokens.iter().any(|token| token.load(Ordering::SeqCst))
+    }
+    /// Channels are shared by a shell and all of its forks.
+    pub fn channels(&self) -> Arc<Mutex<ChannelManager>> {
+        self.channels.clone()
+    }
     pub fn environment(&self) -> &Environment {
         &self.environment
     }
@@ -65,6 +150,19 @@ impl Shell {
     pub fn job_manager_mut(&mut self) -> &mut JobManager {
         &mut self.job_manager
     }
+    pub fn builtins(&self) -> &BuiltinRegistry {
+        &self.builtins
+    }
+    pub fn builtins_mut(&mut self) -> &mut BuiltinRegistry {
+        &mut self.builtins
+    }
+    /// Plugin commands by name, with the executable each one runs.
+    pub fn plugins(&self) -> &HashMap<String, PathBuf> {
+        &self.plugins
+    }
+    pub fn plugins_mut(&mut self) -> &mut HashMap<String, PathBuf> {
+        &mut self.plugins
+    }
     pub fn get_function(&self, name: &str) -> Option<function::Function> {
         match self.functions.get(name) {
             Some(function) => {
@@ -74,11 +172,14 @@ impl Shell {
             None => None,
         }
     }
+    pub fn function_names(&self) -> Vec<String> {
+        self.functions.keys().cloned().collect()
+    }
     pub fn set_function(&mut self, name: String, function: function::Function) {
         self.functions.insert(name, function);
     }
     pub fn merge(&mut self, other: Shell) {
-        self.environment.global.extend(other.environment.global);
+        Arc::make_mut(&mut self.environment.global).extend(other.environment.global.as_ref().clone());
         for scope in other.environment.scoped {
             self.environment.scoped.push(scope);
         }
@@ -87,10 +188,12 @@ impl Shell {
 }
 
 
+/// Variable bindings for a shell. Scopes are reference counted so that forking
+/// a shell is cheap; a scope is only copied when one side writes to it.
 #[derive(Debug, Clone)]
 pub struct Environment {
-    global: HashMap<String, Value>,
-    scoped: Vec<HashMap<String, Value>>,
+    global: Arc<HashMap<String, Value>>,
+    scoped: Vec<Arc<HashMap<String, Value>>>,
 }
 
 
@@ -103,8 +206,8 @@ impl Environment {
     pub fn new() -> Self {
         let global = Environment::create_global();
         Environment {
-            global,
-            scoped: vec![HashMap::new()],
+            global: Arc::new(global),
+            scoped: vec![Arc::new(HashMap::new())],
         }
     }
     pub fn get(&self, name: &str) -> Option<&Value> {
@@ -117,39 +220,102 @@ impl Environment {
     }
     pub fn set(&mut self, name: String, value: Value) {
         if let Some(scope) = self.scoped.last_mut() {
-            scope.insert(name, value);
+            Arc::make_mut(scope).insert(name, value);
         } else {
-            self.global.insert(name, value);
+            Arc::make_mut(&mut self.global).insert(name, value);
         }
     }
+    pub fn set_global(&mut self, name: String, value: Value) {
+        Arc::make_mut(&mut self.global).insert(name, value);
+    }
     pub fn remove(&mut self, name: &str) {
         for scope in self.scoped.iter_mut().rev() {
-            if scope.remove(name).is_some() {
+            if scope.contains_key(name) {
+                Arc::make_mut(scope).remove(name);
                 return;
             }
         }
-        self.global.remove(name);
+        if self.global.contains_key(name) {
+            Arc::make_mut(&mut self.global).remove(name);
+        }
     }
     pub fn push_scope(&mut self) {
-        self.scoped.push(HashMap::new());
+        self.scoped.push(Arc::new(HashMap::new()));
     }
     pub fn pop_scope(&mut self) {
         self.scoped.pop();
     }
     pub fn get_current(&self) -> HashMap<String, Value> {
         if let Some(scope) = self.scoped.last() {
-            scope.clone()
+            scope.as_ref().clone()
         } else {
-            self.global.clone()
+            self.global.as_ref().clone()
         }
     }
     pub fn extend_current(&mut self, other: &HashMap<String, Value>) {
         if let Some(scope) = self.scoped.last_mut() {
-            scope.extend(other.clone());
+            Arc::make_mut(scope).extend(other.clone());
         } else {
-            self.global.extend(other.clone());
+            Arc::make_mut(&mut self.global).extend(other.clone());
         }
     }
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::File;
+
+    fn forked() -> (Arc<RwLock<Shell>>, Arc<RwLock<Shell>>) {
+        let parent = Arc::new(RwLock::new(Shell::new()));
+        let child = borrow!(parent).fork(parent.clone());
+        (parent, Arc::new(RwLock::new(child)))
+    }
+
+    #[test]
+    fn test_fork_keeps_changes_private() {
+        let parent = Arc::new(RwLock::new(Shell::new()));
+        borrow_mut!(parent).environment_mut().set("x".to_string(), Value::Integer(1));
+        let child = Arc::new(RwLock::new(borrow!(parent).fork(parent.clone())));
+        let mut borrowed_child = borrow_mut!(child);
+        borrowed_child.environment_mut().set("x".to_string(), Value::Integer(2));
+        borrowed_child.environment_mut().set_global("y".to_string(), Value::Integer(3));
+        borrowed_child.set_function("f".to_string(), function::Function::new("f", Vec::new(), File::new(Vec::new()), child.clone()));
+        drop(borrowed_child);
+        let borrowed_parent = borrow!(parent);
+        assert_eq!(borrowed_parent.environment().get("x"), Some(&Value::Integer(1)));
+        assert_eq!(borrowed_parent.environment().get("y"), None);
+        assert!(borrowed_parent.get_function("f").is_none());
+        drop(borrowed_parent);
+        borrow_mut!(parent).environment_mut().set("x".to_string(), Value::Integer(4));
+        assert_eq!(borrow!(child).environment().get("x"), Some(&Value::Integer(2)));
+    }
+
+    #[test]
+    fn test_fork_shares_channels_and_cancellation() {
+        let (parent, child) = forked();
+        assert!(Arc::ptr_eq(&borrow!(parent).channels(), &borrow!(child).channels()));
+        let pair = borrow!(child).channels().lock().unwrap().create();
+        let sender = match &pair {
+            Value::List(ends) => ends[0].clone(),
+            _ => panic!("expected a pair"),
+        };
+        assert!(borrow!(parent).channels().lock().unwrap().send(&sender, Value::Integer(1), "send").is_ok());
+        borrow!(child).cancel();
+        assert!(borrow!(child).is_cancelled());
+        assert!(!borrow!(parent).is_cancelled());
+        let (parent, child) = forked();
+        borrow!(parent).cancel();
+        assert!(borrow!(child).is_cancelled());
+    }
+
+    #[test]
+    fn test_run_in() {
+        let (_, child) = forked();
+        assert!(current_shell().is_none());
+        let inside = run_in(child.clone(), || current_shell().map(|shell| Arc::ptr_eq(&shell, &child)));
+        assert_eq!(inside, Some(true));
+        assert!(current_shell().is_none());
+    }
+}
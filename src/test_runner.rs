@@ -0,0 +1,289 @@
+//! Runs the `test_*` functions found in script files.
+//!
+//! Each test gets a fresh `Interpreter`: the whole file is run first so the
+//! test can use its helpers and variables, then the test function is called
+//! with no arguments. A test passes when it returns anything but a failure.
+//!
+//! A failure is reported at the `assert` or `assert_eq` call that failed, or
+//! at the test function when no assertion did. To tell the calls apart, each
+//! one is renamed to a numbered command that records its number when it fails.
+
+use caat_rust::Value;
+use crate::builtins::{Builtin, Context};
+use crate::parser::{self, Expression, File, MatchArm, Redirect, Span, SpanKind, Statement};
+use crate::Interpreter;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+
+const ASSERTIONS: [&str; 2] = ["assert", "assert_eq"];
+
+
+pub struct TestResult {
+    pub file: PathBuf,
+    pub line: usize,
+    pub name: String,
+    pub failure: Option<String>,
+}
+
+fn script_files(path: &Path, output: &mut Vec<PathBuf>) -> io::Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<PathBuf>>>()?;
+        entries.sort();
+        for entry in entries {
+            if entry.is_dir() || entry.extension().map_or(false, |extension| extension == "caat") {
+                script_files(&entry, output)?;
+            }
+        }
+    } else {
+        output.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn test_names(file: &File) -> Vec<String> {
+    file.statements.iter().flatten()
+        .filter_map(|statement| match statement {
+            Statement::FunctionDef(function) if function.name.starts_with("test_") => Some(function.name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The one based line `offset` is on.
+fn line_at(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+/// An assertion renamed by `number_assertions`. It runs the real one and
+/// remembers its number if it fails.
+struct NumberedAssertion {
+    name: String,
+    number: usize,
+    assertion: Arc<dyn Builtin>,
+    failed: Arc<Mutex<Option<usize>>>,
+}
+
+impl Builtin for NumberedAssertion {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn help(&self) -> &str {
+        self.assertion.help()
+    }
+    fn signature(&self) -> &str {
+        self.assertion.signature()
+    }
+    fn call(&self, context: &mut Context, args: &[Value]) -> Result<Value, String> {
+        let result = self.assertion.call(context, args);
+        if result.is_err() {
+            *self.failed.lock().unwrap() = Some(self.number);
+        }
+        result
+    }
+}
+
+/// Renames every assertion call in `file` to `assert#0`, `assert_eq#1` and so
+/// on, adding the original names to `names`. Calls are numbered in the order
+/// `parse_file_with_spans` lists their spans.
+fn number_assertions(file: &mut File, names: &mut Vec<String>) {
+    for statement in file.statements.iter_mut().flatten() {
+        match statement {
+            Statement::Assignment(assignment) => number_in_expression(&mut assignment.value, names),
+            Statement::Expression(expression) | Statement::Return(expression) => number_in_expression(expression, names),
+            Statement::FunctionDef(function) => number_assertions(&mut function.body, names),
+            Statement::Loop(body) => number_assertions(body, names),
+            Statement::Comment(_) | Statement::Blank | Statement::Break | Statement::Continue => {}
+        }
+    }
+}
+
+fn number_in_expression(expression: &mut Expression, names: &mut Vec<String>) {
+    match expression {
+        Expression::Literal(_) | Expression::Variable(_) => {}
+        Expression::Pipeline(pipeline) | Expression::HigherOrder(pipeline) => {
+            let mut part = Some(&mut pipeline.pipeline);
+            while let Some(current) = part {
+                let command = &mut current.command;
+                if ASSERTIONS.contains(&command.name.as_str()) {
+                    names.push(command.name.clone());
+                    command.name = format!("{}#{}", command.name, names.len() - 1);
+                }
+                for argument in &mut command.arguments {
+                    number_in_expression(argument, names);
+                }
+                part = current.next.as_deref_mut();
+            }
+            match &mut pipeline.redirect {
+                Some(Redirect::Input(e)) | Some(Redirect::Output(e)) | Some(Redirect::Append(e)) => number_in_expression(e, names),
+                None => {}
+            }
+        }
+        Expression::Parenthesized(e) => number_in_expression(e, names),
+        Expression::If(cond, then, else_) => {
+            number_in_expression(cond, names);
+            number_in_expression(then, names);
+            number_in_expression(else_, names);
+        }
+        Expression::Access(a, b) | Expression::Concat(a, b) => {
+            number_in_expression(a, names);
+            number_in_expression(b, names);
+        }
+        Expression::Lambda(_, body) => number_assertions(body, names),
+        Expression::Match(e, arms) => {
+            number_in_expression(e, names);
+            for arm in arms {
+                match arm {
+                    MatchArm::Expression(pattern, body) => {
+                        number_in_expression(pattern, names);
+                        number_in_expression(body, names);
+                    }
+                    MatchArm::WildcardBind(_, body) | MatchArm::WildcardDiscard(body) => number_in_expression(body, names),
+                }
+            }
+        }
+    }
+}
+
+/// Runs `file` and then the test `name` in a fresh interpreter. A failure
+/// comes with the offset of the assertion that failed, if one did.
+fn run_test(file: &File, spans: &[Span], name: &str) -> Option<(String, Option<usize>)> {
+    let interpreter = Interpreter::new();
+    let mut file = file.clone();
+    let mut names = Vec::new();
+    number_assertions(&mut file, &mut names);
+    let failed = Arc::new(Mutex::new(None));
+    let shell = interpreter.shell();
+    for (number, name) in names.iter().enumerate() {
+        let assertion = match borrow!(shell).builtins().get(name) {
+            Some(assertion) => assertion,
+            None => continue,
+        };
+        interpreter.register_builtin(Arc::new(NumberedAssertion {
+            name: format!("{}#{}", name, number),
+            number,
+            assertion,
+            failed: failed.clone(),
+        }));
+    }
+    let result = crate::eval::eval_file(shell, &mut file)
+        .map_err(crate::Error::Eval)
+        .and_then(|_| interpreter.call_function(name, &[]));
+    let message = result.err()?.to_string();
+    let number = *failed.lock().unwrap();
+    let offset = number.and_then(|number| {
+        spans.iter()
+            .filter(|span| span.kind == SpanKind::Command && ASSERTIONS.contains(&span.name.as_str()))
+            .nth(number)
+            .map(|span| span.start)
+    });
+    Some((message, offset))
+}
+
+fn run_file_tests(path: &Path) -> io::Result<Vec<TestResult>> {
+    let source = std::fs::read_to_string(path)?;
+    let (file, spans) = match parser::parse_file_with_spans(&source) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            return Ok(vec![TestResult {
+                file: path.to_path_buf(),
+                line: 0,
+                name: String::from("<parse>"),
+                failure: Some(e.to_string()),
+            }]);
+        }
+    };
+    let mut results = Vec::new();
+    for name in test_names(&file) {
+        let definition = spans.iter()
+            .find(|span| span.kind == SpanKind::Function && span.name == name)
+            .map_or(0, |span| span.start);
+        let (failure, offset) = match run_test(&file, &spans, &name) {
+            Some((message, offset)) => (Some(message), offset.unwrap_or(definition)),
+            None => (None, definition),
+        };
+        results.push(TestResult {
+            file: path.to_path_buf(),
+            line: line_at(&source, offset),
+            failure,
+            name,
+        });
+    }
+    Ok(results)
+}
+
+/// Runs every test under `path`, printing each result and a summary.
+/// Returns whether all of them passed.
+pub fn run_tests(path: &Path) -> io::Result<bool> {
+    let mut files = Vec::new();
+    script_files(path, &mut files)?;
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in files {
+        for result in run_file_tests(&file)? {
+            let location = format!("{}:{}", result.file.display(), result.line);
+            match result.failure {
+                None => {
+                    passed += 1;
+                    println!("ok    {} {}", location, result.name);
+                }
+                Some(msg) => {
+                    failed += 1;
+                    println!("FAIL  {} {}", location, result.name);
+                    for line in msg.lines() {
+                        println!("      {}", line);
+                    }
+                }
+            }
+        }
+    }
+    println!("\n{} passed; {} failed", passed, failed);
+    Ok(failed == 0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "function helper() {\n    return 1\n}\n\nfunction test_one() {\n    assert true\n}\n\nfunction test_two() {\n    assert false \"two\"\n}\n";
+
+    fn results(source: &str) -> Vec<(String, usize, Option<String>)> {
+        let path = std::env::temp_dir().join(format!("caat_test_runner_{}_{}.caat", std::process::id(), source.len()));
+        std::fs::write(&path, source).unwrap();
+        let results = run_file_tests(&path);
+        std::fs::remove_file(&path).unwrap();
+        results.unwrap().into_iter().map(|result| (result.name, result.line, result.failure)).collect()
+    }
+
+    #[test]
+    fn test_find_tests() {
+        let file = parser::parse_file(SOURCE).unwrap();
+        assert_eq!(test_names(&file), vec!["test_one".to_string(), "test_two".to_string()]);
+    }
+
+    #[test]
+    fn test_results() {
+        let results = results(SOURCE);
+        assert_eq!((results[0].0.as_str(), results[0].1, results[0].2.clone()), ("test_one", 5, None));
+        assert_eq!((results[1].0.as_str(), results[1].1), ("test_two", 10));
+        assert!(results[1].2.as_ref().unwrap().contains("two"));
+    }
+
+    #[test]
+    fn test_failing_assertion_line() {
+        let source = "function check(x) {\n    assert_eq $x 1\n}\n\nfunction test_three() {\n    assert true\n    map [1, 2] {assert_eq 1}\n    assert_eq 1 1\n}\n\nfunction test_helper() {\n    assert true\n    check 2\n}\n\nfunction test_plain() {\n    return div 1 0\n}\n";
+        let lines: Vec<(String, usize)> = results(source).into_iter().map(|(name, line, failure)| {
+            assert!(failure.is_some(), "{} passed", name);
+            (name, line)
+        }).collect();
+        assert_eq!(lines, vec![
+            (String::from("test_three"), 7),
+            (String::from("test_helper"), 2),
+            (String::from("test_plain"), 16),
+        ]);
+    }
+}
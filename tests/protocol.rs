@@ -0,0 +1,105 @@
+//! End-to-end checks of how the shell talks to external commands.
+//!
+//! The stand-in commands are links to the `caat_standin` binary, a real CAAT
+//! foreign command, placed in a temporary directory at the front of `PATH`.
+//! `PATH` is shared by the whole process, so the tests take turns.
+//!
+//! The stand-in is only built with the `test-standin` feature, so these run
+//! with `cargo test --features test-standin`.
+
+use caat_shell::{Error, Interpreter, Value};
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+
+const STANDINS: &[&str] = &["echo-value", "echo-args", "make-map", "fail", "slow"];
+
+/// Installs the stand-ins for one test and removes them again when dropped.
+struct Standins {
+    dir: PathBuf,
+    path: Option<String>,
+    _turn: MutexGuard<'static, ()>,
+}
+
+impl Standins {
+    fn install() -> Standins {
+        static TURN: Mutex<()> = Mutex::new(());
+        let turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = std::env::temp_dir().join(format!("caat_shell_standins_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in STANDINS {
+            std::os::unix::fs::symlink(env!("CARGO_BIN_EXE_caat_standin"), dir.join(name)).unwrap();
+        }
+        let path = std::env::var("PATH").ok();
+        std::env::set_var("PATH", format!("{}:{}", dir.display(), path.clone().unwrap_or_default()));
+        Standins { dir, path, _turn: turn }
+    }
+
+    /// The pid the `slow` stand-in wrote when it started, if it has.
+    fn slow_pid(&self) -> Option<String> {
+        std::fs::read_to_string(self.dir.join("slow.pid")).ok()
+    }
+}
+
+impl Drop for Standins {
+    fn drop(&mut self) {
+        match &self.path {
+            Some(path) => std::env::set_var("PATH", path),
+            None => std::env::remove_var("PATH"),
+        }
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn run(script: &str) -> Result<Value, Error> {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/scripts").join(script);
+    Interpreter::new().run_file(path)
+}
+
+fn string(s: &str) -> Value {
+    Value::String(s.to_string())
+}
+
+#[test]
+fn test_value_round_trip() {
+    let _standins = Standins::install();
+    assert_eq!(run("round_trip.caat").unwrap(), string("hello"));
+}
+
+#[test]
+fn test_structured_values() {
+    let _standins = Standins::install();
+    let numbers = Value::List(vec![Value::Integer(3), Value::Float(4.5)].into());
+    assert_eq!(run("list.caat").unwrap(), Value::List(vec![Value::Integer(1), string("two"), numbers].into()));
+    assert_eq!(run("structured_map.caat").unwrap(), Value::Integer(8080));
+}
+
+#[test]
+fn test_failure_propagates() {
+    let _standins = Standins::install();
+    // A failure a foreign command returns is an error of the script.
+    match run("failure.caat") {
+        Err(Error::Eval(msg)) => assert_eq!(msg, "broken"),
+        result => panic!("expected the error \"broken\", got {:?}", result),
+    }
+}
+
+#[test]
+fn test_pipeline_chains() {
+    let _standins = Standins::install();
+    assert_eq!(run("pipeline.caat").unwrap(), string("second first"));
+}
+
+#[test]
+fn test_block_called_per_item() {
+    let _standins = Standins::install();
+    assert_eq!(run("map.caat").unwrap(), Value::List(vec![string("item a"), string("item b")].into()));
+}
+
+#[test]
+fn test_timeout() {
+    let standins = Standins::install();
+    let value = run("timeout.caat").unwrap();
+    assert_eq!(value, Value::Failure("timed out".to_string()));
+    let pid = standins.slow_pid().expect("slow never started");
+    assert!(!std::path::Path::new("/proc").join(pid.trim()).exists(), "slow is still running");
+}
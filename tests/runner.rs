@@ -0,0 +1,8 @@
+use caat_shell::test_runner::run_tests;
+use std::path::PathBuf;
+
+#[test]
+fn test_example_scripts_pass() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/scripts/runner");
+    assert!(run_tests(&path).unwrap());
+}
@@ -0,0 +1,58 @@
+//! A CAAT foreign command for the protocol tests in `tests/protocol.rs`.
+//!
+//! The tests link it under several names and it behaves according to the name
+//! it was called by, reading its arguments and returning its value through
+//! caat_rust the way any CAAT command does.
+
+use caat_rust::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => format!("{:?}", value),
+    }
+}
+
+fn main() {
+    let mut args = caat_rust::args();
+    let name = match args.next() {
+        Some(Value::String(program)) => Path::new(&program)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or(program),
+        _ => String::new(),
+    };
+    let args: Vec<Value> = args.collect();
+    let value = match name.as_str() {
+        // Joins its arguments with spaces, like `echo`.
+        "echo-value" => Value::String(args.iter().map(text).collect::<Vec<String>>().join(" ")),
+        // Returns its arguments unchanged as a list.
+        "echo-args" => Value::List(args.into()),
+        // Returns a map built from key and value pairs.
+        "make-map" => {
+            let map: HashMap<String, Value> = args.chunks(2)
+                .map(|pair| (text(&pair[0]), pair.get(1).cloned().unwrap_or(Value::Null)))
+                .collect();
+            Value::Map(map, None)
+        }
+        // Fails with its first argument as the message.
+        "fail" => Value::Failure(args.first().map(text).unwrap_or("failed".to_string())),
+        // Writes its pid next to itself, then sleeps for a number of seconds.
+        "slow" => {
+            let program = std::env::args().next().unwrap_or_default();
+            let pid_file = Path::new(&program).with_file_name("slow.pid");
+            let _ = std::fs::write(pid_file, std::process::id().to_string());
+            let seconds = match args.first() {
+                Some(Value::Integer(n)) => *n as u64,
+                _ => 5,
+            };
+            std::thread::sleep(Duration::from_secs(seconds));
+            Value::String("done".to_string())
+        }
+        name => Value::Failure(format!("standin: unknown name {}", name)),
+    };
+    caat_rust::return_caat(value);
+}